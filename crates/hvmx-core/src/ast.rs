@@ -0,0 +1,402 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: ast.rs
+// Location: crates/hvmx-core/src/ast.rs
+// Purpose: Textual interaction net syntax trees
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{CoreError, GNet, Numb, Pair, Port, Result, Tag, Val};
+
+/// Tree: a port and everything hanging from it.
+///
+/// Cloning, comparing, printing and dropping keep their own stacks, so
+/// trees of any depth are safe to handle.
+#[derive(Debug)]
+pub enum Tree {
+    /// `name`, bound exactly twice per net
+    Var { nam: String },
+    /// `@name`
+    Ref { nam: String },
+    /// `*`
+    Era,
+    /// `123`, `+1`, `1.5`, `[+]`, `[+1]`, `[u24]`
+    Num { val: Numb },
+    /// `(fst snd)`
+    Con { fst: Box<Tree>, snd: Box<Tree> },
+    /// `{fst snd}`
+    Dup { fst: Box<Tree>, snd: Box<Tree> },
+    /// `$(fst snd)`
+    Opr { fst: Box<Tree>, snd: Box<Tree> },
+    /// `?(fst snd)`
+    Swi { fst: Box<Tree>, snd: Box<Tree> },
+}
+
+/// Net: a root tree and its redexes, `root & a ~ b & ...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Net {
+    pub root: Tree,
+    pub rbag: Vec<(Tree, Tree)>,
+}
+
+impl Tree {
+    /// Binary node with the given tag
+    pub fn node(tag: Tag, fst: Tree, snd: Tree) -> Self {
+        let (fst, snd) = (Box::new(fst), Box::new(snd));
+        match tag {
+            Tag::Dup => Tree::Dup { fst, snd },
+            Tag::Opr => Tree::Opr { fst, snd },
+            Tag::Swi => Tree::Swi { fst, snd },
+            _ => Tree::Con { fst, snd },
+        }
+    }
+
+    /// Children of a binary node
+    pub fn children(&self) -> Option<(Tag, &Tree, &Tree)> {
+        match self {
+            Tree::Con { fst, snd } => Some((Tag::Con, fst, snd)),
+            Tree::Dup { fst, snd } => Some((Tag::Dup, fst, snd)),
+            Tree::Opr { fst, snd } => Some((Tag::Opr, fst, snd)),
+            Tree::Swi { fst, snd } => Some((Tag::Swi, fst, snd)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn children_mut(&mut self) -> Option<(&mut Tree, &mut Tree)> {
+        match self {
+            Tree::Con { fst, snd } | Tree::Dup { fst, snd } | Tree::Opr { fst, snd } | Tree::Swi { fst, snd } => {
+                Some((fst, snd))
+            }
+            _ => None,
+        }
+    }
+
+    /// Shallow copy of a leaf
+    fn leaf(&self) -> Tree {
+        match self {
+            Tree::Var { nam } => Tree::Var { nam: nam.clone() },
+            Tree::Ref { nam } => Tree::Ref { nam: nam.clone() },
+            Tree::Num { val } => Tree::Num { val: *val },
+            _ => Tree::Era,
+        }
+    }
+
+    /// Number of lambdas on the right spine: `(a (b r))` has arity 2
+    pub fn arity(&self) -> usize {
+        let mut tree = self;
+        let mut arity = 0;
+        while let Tree::Con { snd, .. } = tree {
+            arity += 1;
+            tree = snd;
+        }
+        arity
+    }
+}
+
+impl Clone for Tree {
+    fn clone(&self) -> Self {
+        enum Item<'a> {
+            Tree(&'a Tree),
+            Node(Tag),
+        }
+        let mut stack = vec![Item::Tree(self)];
+        let mut done = Vec::new();
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Node(tag) => {
+                    let snd = done.pop().unwrap();
+                    let fst = done.pop().unwrap();
+                    done.push(Tree::node(tag, fst, snd));
+                }
+                Item::Tree(tree) => match tree.children() {
+                    Some((tag, fst, snd)) => {
+                        stack.push(Item::Node(tag));
+                        stack.push(Item::Tree(snd));
+                        stack.push(Item::Tree(fst));
+                    }
+                    None => done.push(tree.leaf()),
+                },
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl PartialEq for Tree {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            match (a.children(), b.children()) {
+                (Some((ta, a1, a2)), Some((tb, b1, b2))) if ta == tb => {
+                    stack.push((a2, b2));
+                    stack.push((a1, b1));
+                }
+                (None, None) => {
+                    let same = match (a, b) {
+                        (Tree::Var { nam: x }, Tree::Var { nam: y }) => x == y,
+                        (Tree::Ref { nam: x }, Tree::Ref { nam: y }) => x == y,
+                        (Tree::Num { val: x }, Tree::Num { val: y }) => x == y,
+                        (Tree::Era, Tree::Era) => true,
+                        _ => false,
+                    };
+                    if !same {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        let take = |tree: &mut Tree, stack: &mut Vec<Tree>| {
+            if let Some((fst, snd)) = tree.children_mut() {
+                for child in [fst, snd] {
+                    if child.children().is_some() {
+                        stack.push(std::mem::replace(child, Tree::Era));
+                    }
+                }
+            }
+        };
+        take(self, &mut stack);
+        while let Some(mut tree) = stack.pop() {
+            take(&mut tree, &mut stack);
+        }
+    }
+}
+
+impl Net {
+    /// Builds a definition template, resolving `@refs` with `fid`.
+    ///
+    /// Nodes and variables are allocated in pre-order, root first and then
+    /// each redex left to right; the printer walks nets in the same order.
+    pub fn build(&self, fid: impl Fn(&str) -> Option<Val>) -> Result<GNet> {
+        let mut net = GNet::new();
+        let mut builder = Builder {
+            net: &mut net,
+            vars: HashMap::new(),
+            fid,
+        };
+        let root = builder.tree(&self.root)?;
+        for (a, b) in &self.rbag {
+            let a = builder.tree(a)?;
+            let b = builder.tree(b)?;
+            builder.net.redexes.push(Pair::new(a, b));
+        }
+        net.set_root(root);
+        Ok(net)
+    }
+}
+
+impl GNet {
+    /// Allocates `tree` in the net, resolving `@refs` with `fid`, and
+    /// returns its port. Variables occurring once are left unlinked.
+    pub fn alloc_tree(&mut self, tree: &Tree, fid: impl Fn(&str) -> Option<Val>) -> Result<Port> {
+        Builder {
+            net: self,
+            vars: HashMap::new(),
+            fid,
+        }
+        .tree(tree)
+    }
+}
+
+/// Trees print in the syntax the parser reads. Printing keeps its own
+/// stack, so deep trees don't overflow the native one.
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        enum Item<'a> {
+            Tree(&'a Tree),
+            Text(&'static str),
+        }
+        let mut stack = vec![Item::Tree(self)];
+        while let Some(item) = stack.pop() {
+            let tree = match item {
+                Item::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Item::Tree(tree) => tree,
+            };
+            match tree {
+                Tree::Var { nam } => f.write_str(nam)?,
+                Tree::Ref { nam } => write!(f, "@{}", nam)?,
+                Tree::Era => f.write_str("*")?,
+                Tree::Num { val } => write!(f, "{}", val)?,
+                _ => {
+                    let (tag, fst, snd) = tree.children().unwrap();
+                    let (open, close) = match tag {
+                        Tag::Dup => ("{", "}"),
+                        Tag::Opr => ("$(", ")"),
+                        Tag::Swi => ("?(", ")"),
+                        _ => ("(", ")"),
+                    };
+                    f.write_str(open)?;
+                    stack.push(Item::Text(close));
+                    stack.push(Item::Tree(snd));
+                    stack.push(Item::Text(" "));
+                    stack.push(Item::Tree(fst));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Nets print as `root`, then one `& a ~ b` line per redex
+impl fmt::Display for Net {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for (a, b) in &self.rbag {
+            write!(f, "\n  & {} ~ {}", a, b)?;
+        }
+        Ok(())
+    }
+}
+
+struct Builder<'a, 'n, F> {
+    net: &'n mut GNet,
+    vars: HashMap<&'a str, Port>,
+    fid: F,
+}
+
+impl<'a, F: Fn(&str) -> Option<Val>> Builder<'a, '_, F> {
+    /// Allocates a tree in pre-order, keeping its own stack
+    fn tree(&mut self, tree: &'a Tree) -> Result<Port> {
+        // Each tree with the node and side its port goes into
+        let mut stack = vec![(tree, None)];
+        let mut root = Port::NONE;
+        while let Some((tree, dest)) = stack.pop() {
+            let port = match tree {
+                Tree::Var { nam } => match self.vars.get(nam.as_str()) {
+                    Some(&port) => port,
+                    None => {
+                        let var = self.net.vars_alloc()?;
+                        self.net.vars_create(var, Port::NONE);
+                        let port = Port::new(Tag::Var, var);
+                        self.vars.insert(nam, port);
+                        port
+                    }
+                },
+                Tree::Ref { nam } => {
+                    let fid = (self.fid)(nam).ok_or_else(|| CoreError::UndefinedDef(nam.clone()))?;
+                    Port::new(Tag::Ref, fid)
+                }
+                Tree::Era => Port::new(Tag::Era, 0),
+                Tree::Num { val } => Port::new(Tag::Num, val.0),
+                _ => {
+                    let (tag, fst, snd) = tree.children().unwrap();
+                    let loc = self.net.node_alloc()?;
+                    self.net.node_create(loc, Pair::new(Port::NONE, Port::NONE));
+                    stack.push((snd, Some((loc, true))));
+                    stack.push((fst, Some((loc, false))));
+                    Port::new(tag, loc)
+                }
+            };
+            match dest {
+                None => root = port,
+                Some((loc, is_snd)) => {
+                    let node = self.net.node_load(loc);
+                    let node = if is_snd { Pair::new(node.fst(), port) } else { Pair::new(port, node.snd()) };
+                    self.net.node_store(loc, node);
+                }
+            }
+        }
+        Ok(root)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(nam: &str) -> Tree {
+        Tree::Var { nam: nam.to_string() }
+    }
+
+    #[test]
+    fn test_tree_arity() {
+        let id = Tree::node(Tag::Con, var("x"), var("x"));
+        assert_eq!(id.arity(), 1);
+        let k = Tree::node(Tag::Con, var("x"), Tree::node(Tag::Con, Tree::Era, var("x")));
+        assert_eq!(k.arity(), 2);
+        assert_eq!(Tree::Era.arity(), 0);
+    }
+
+    #[test]
+    fn test_net_build() {
+        let net = Net {
+            root: Tree::node(Tag::Con, var("x"), var("x")),
+            rbag: vec![(Tree::Ref { nam: "f".to_string() }, Tree::Era)],
+        };
+        let gnet = net.build(|nam| (nam == "f").then_some(3)).unwrap();
+        assert_eq!(gnet.node_count(), 1);
+        assert_eq!(gnet.vars_count(), 1);
+        assert_eq!(gnet.root().tag(), Tag::Con);
+        let x = Port::new(Tag::Var, 1);
+        assert_eq!(gnet.node_load(gnet.root().val()), Pair::new(x, x));
+        assert_eq!(gnet.redexes, vec![Pair::new(Port::new(Tag::Ref, 3), Port::new(Tag::Era, 0))]);
+    }
+
+    #[test]
+    fn test_tree_display() {
+        let tree = Tree::node(
+            Tag::Con,
+            Tree::node(Tag::Dup, var("a"), Tree::Era),
+            Tree::node(
+                Tag::Opr,
+                Tree::Num { val: Numb::new_u24(1) },
+                Tree::node(Tag::Swi, var("a"), Tree::Ref { nam: "f".to_string() }),
+            ),
+        );
+        assert_eq!(tree.to_string(), "({a *} $(1 ?(a @f)))");
+        let net = Net {
+            root: var("r"),
+            rbag: vec![(Tree::Era, var("r"))],
+        };
+        assert_eq!(net.to_string(), "r\n  & * ~ r");
+    }
+
+    /// `(1 (1 ... (1 *)))`, `depth` nodes deep
+    fn deep(depth: usize) -> Tree {
+        let mut tree = Tree::Era;
+        for _ in 0..depth {
+            tree = Tree::node(Tag::Con, Tree::Num { val: Numb::new_u24(1) }, tree);
+        }
+        tree
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        let tree = deep(1_000_000);
+        let copy = tree.clone();
+        assert_eq!(copy, tree);
+        assert_ne!(copy, deep(999_999));
+        assert_eq!(tree.to_string().len(), 1_000_000 * 4 + 1);
+        let net = Net { root: tree, rbag: vec![] };
+        assert_eq!(net.build(|_| None).unwrap().node_count(), 1_000_000);
+    }
+
+    #[test]
+    fn test_net_build_undefined_ref() {
+        let net = Net {
+            root: Tree::Ref { nam: "g".to_string() },
+            rbag: vec![],
+        };
+        assert_eq!(net.build(|_| None).unwrap_err(), CoreError::UndefinedDef("g".to_string()));
+    }
+}
@@ -0,0 +1,398 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: bag.rs
+// Location: crates/hvmx-core/src/bag.rs
+// Purpose: Two-level redex bags with priority classes and capacities
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Redex bags, after the `hbag`/`rbag` split in `docs/dor/hvm.c`.
+//!
+//! Redexes whose rule is in the high class go to the high bag and are
+//! popped first, so cheap rules that shrink the net run before the ones
+//! that grow it. When the high bag is full, redexes spill to the low one.
+//!
+//! Capacities bound the low bag before any work is done, as
+//! `get_resources` does in `docs/dor/hvm.c`: `interact` first reserves
+//! room for every redex the interaction may create (for a CALL, the
+//! template's redexes and the link of its root), counting all of them as
+//! low priority. If they might not fit it fails with
+//! `CoreError::BagOverflow` and leaves the net as it was, so raising the
+//! capacity lets reduction carry on. Redexes the lazy evaluator sets aside
+//! count against the capacities too. The parallel evaluator splits the
+//! high capacity between its threads and shares the low one.
+
+use crate::interact::{get_rule, is_high_priority};
+use crate::{CoreError, Pair, Result, Rule};
+
+/// Which rules are high priority, and how many redexes each bag holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BagConfig {
+    /// High class membership, indexed by `Rule`
+    pub high: [bool; 8],
+    /// High bag capacity; `None` is unbounded
+    pub high_len: Option<usize>,
+    /// Low bag capacity; `None` is unbounded
+    pub low_len: Option<usize>,
+}
+
+impl BagConfig {
+    /// The reference classes, unbounded
+    pub fn new() -> Self {
+        let mut high = [false; 8];
+        for rule in [Rule::Link, Rule::Call, Rule::Void, Rule::Eras, Rule::Anni, Rule::Comm, Rule::Oper, Rule::Swit] {
+            high[rule as usize] = is_high_priority(rule);
+        }
+        Self {
+            high,
+            high_len: None,
+            low_len: None,
+        }
+    }
+
+    /// A single bag: every rule in the low class
+    pub fn single() -> Self {
+        Self {
+            high: [false; 8],
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for BagConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pending redexes, in two priority levels
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedexBag {
+    high: Vec<Pair>,
+    low: Vec<Pair>,
+    config: BagConfig,
+}
+
+impl RedexBag {
+    pub fn new(config: BagConfig) -> Self {
+        Self {
+            high: Vec::new(),
+            low: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &BagConfig {
+        &self.config
+    }
+
+    /// Changes the configuration, reclassifying the redexes held
+    pub fn set_config(&mut self, config: BagConfig) {
+        let redexes: Vec<Pair> = self.drain().collect();
+        self.config = config;
+        self.extend(redexes);
+    }
+
+    /// Whether a redex's rule is in the high class
+    pub(crate) fn is_high(&self, redex: Pair) -> bool {
+        self.config.high[get_rule(redex.fst(), redex.snd()) as usize]
+    }
+
+    /// Adds a redex to its class's bag, past capacity if it must
+    pub fn push(&mut self, redex: Pair) {
+        if self.is_high(redex) && self.config.high_len.is_none_or(|max| self.high.len() < max) {
+            self.high.push(redex);
+        } else {
+            self.low.push(redex);
+        }
+    }
+
+    /// Takes the last high-priority redex, or else the last low one
+    pub fn pop(&mut self) -> Option<Pair> {
+        self.high.pop().or_else(|| self.low.pop())
+    }
+
+    pub fn len(&self) -> usize {
+        self.high.len() + self.low.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.low.is_empty()
+    }
+
+    /// Redexes in the (high, low) bags
+    pub fn lens(&self) -> (usize, usize) {
+        (self.high.len(), self.low.len())
+    }
+
+    /// Low-priority redexes first, so that `pop` takes them last
+    pub fn iter(&self) -> impl Iterator<Item = &Pair> + '_ {
+        self.into_iter()
+    }
+
+    /// Takes every redex, in `iter` order
+    pub fn drain(&mut self) -> impl Iterator<Item = Pair> + '_ {
+        self.low.drain(..).chain(self.high.drain(..))
+    }
+
+    pub fn clear(&mut self) {
+        self.high.clear();
+        self.low.clear();
+    }
+
+    /// Fails if the low bag holds more than its capacity
+    pub fn check(&self) -> Result<()> {
+        self.reserve(0)
+    }
+
+    /// Fails if `need` more redexes of any class might not fit in the low
+    /// bag
+    pub fn reserve(&self, need: usize) -> Result<()> {
+        self.reserve_with(0, 0, need)
+    }
+
+    /// `reserve`, as if `high` and `low` more redexes of each class, held
+    /// outside the bag, were pushed to it
+    pub(crate) fn reserve_with(&self, high: usize, low: usize, need: usize) -> Result<()> {
+        let spilled = self.config.high_len.map_or(0, |max| (self.high.len() + high).saturating_sub(max));
+        match self.config.low_len {
+            Some(max) if self.low.len() + low + spilled + need > max => Err(CoreError::BagOverflow(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Extend<Pair> for RedexBag {
+    fn extend<I: IntoIterator<Item = Pair>>(&mut self, iter: I) {
+        for redex in iter {
+            self.push(redex);
+        }
+    }
+}
+
+impl FromIterator<Pair> for RedexBag {
+    fn from_iter<I: IntoIterator<Item = Pair>>(iter: I) -> Self {
+        let mut bag = RedexBag::default();
+        bag.extend(iter);
+        bag
+    }
+}
+
+impl IntoIterator for RedexBag {
+    type Item = Pair;
+    type IntoIter = std::iter::Chain<std::vec::IntoIter<Pair>, std::vec::IntoIter<Pair>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.low.into_iter().chain(self.high)
+    }
+}
+
+impl<'a> IntoIterator for &'a RedexBag {
+    type Item = &'a Pair;
+    type IntoIter = std::iter::Chain<std::slice::Iter<'a, Pair>, std::slice::Iter<'a, Pair>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.low.iter().chain(&self.high)
+    }
+}
+
+impl std::ops::Index<usize> for RedexBag {
+    type Output = Pair;
+
+    /// The `i`th redex in `iter` order
+    fn index(&self, i: usize) -> &Pair {
+        match i.checked_sub(self.low.len()) {
+            Some(i) => &self.high[i],
+            None => &self.low[i],
+        }
+    }
+}
+
+impl PartialEq<Vec<Pair>> for RedexBag {
+    fn eq(&self, other: &Vec<Pair>) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, Book, EvalMode, GNet, Port, Tag};
+
+    fn era() -> Port {
+        Port::new(Tag::Era, 0)
+    }
+
+    #[test]
+    fn test_bag_priorities() {
+        let call = Pair::new(Port::new(Tag::Ref, 1), Port::new(Tag::Con, 1));
+        let void = Pair::new(era(), era());
+        let comm = Pair::new(Port::new(Tag::Con, 1), Port::new(Tag::Dup, 2));
+        let mut bag: RedexBag = [call, void, comm].into_iter().collect();
+        assert_eq!(bag.lens(), (1, 2));
+        assert_eq!(bag, vec![call, comm, void]);
+        assert_eq!(bag.pop(), Some(void));
+        assert_eq!(bag.pop(), Some(comm));
+        assert_eq!(bag.pop(), Some(call));
+        assert_eq!(bag.pop(), None);
+
+        let mut bag = RedexBag::new(BagConfig::single());
+        bag.extend([call, void, comm]);
+        assert_eq!(bag.lens(), (0, 3));
+        assert_eq!(bag.pop(), Some(comm));
+        bag.set_config(BagConfig::new());
+        assert_eq!(bag.lens(), (1, 1));
+    }
+
+    #[test]
+    fn test_bag_capacities() {
+        let void = Pair::new(era(), era());
+        let mut bag = RedexBag::new(BagConfig {
+            high_len: Some(2),
+            low_len: Some(1),
+            ..BagConfig::new()
+        });
+        bag.extend([void, void, void]);
+        // The third spills to the low bag
+        assert_eq!(bag.lens(), (2, 1));
+        assert!(bag.check().is_ok());
+        bag.push(void);
+        assert_eq!(bag.len(), 4);
+        assert_eq!(bag.check(), Err(CoreError::BagOverflow(1)));
+        assert_eq!(bag.check().unwrap_err().to_string(), "Redex bag overflow: more than 1 low-priority redexes");
+    }
+
+    #[test]
+    fn test_bag_capacity_bounds_lazy_mode() {
+        // 2^10 calls, each needed for the result
+        let book = Book::parse(
+            "@main = r & @fan ~ (10 r)
+             @fan = (?((1 @fan.s) r) r)
+             @fan.s = ({p0 p1} r) & @fan ~ (p0 $([+] $(q r))) & @fan ~ (p1 q)",
+        )
+        .unwrap();
+        for mode in [EvalMode::Strict, EvalMode::Lazy] {
+            for max in [2, 4, 8] {
+                let mut net = GNet::new();
+                net.redexes.set_config(BagConfig {
+                    low_len: Some(max),
+                    ..BagConfig::new()
+                });
+                net.boot(&book).unwrap();
+                let result = match mode {
+                    EvalMode::Strict => net.reduce(&book),
+                    EvalMode::Lazy => net.reduce_lazy(&book),
+                };
+                assert_eq!(result.unwrap_err(), CoreError::BagOverflow(max), "{:?}", mode);
+                assert!(net.validate().is_ok(), "{:?}", mode);
+            }
+            let mut net = GNet::new();
+            match mode {
+                EvalMode::Strict => net.normalize(&book),
+                EvalMode::Lazy => net.normalize_lazy(&book),
+            }
+            .unwrap();
+            assert_eq!(net.to_string(), "1024", "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_bag_overflow_before_interaction() {
+        // One CALL that would push eight low-priority redexes
+        let book = Book::parse(
+            "@main = (a (b (c (d (e (f (g h)))))))
+               & @id ~ (1 a) & @id ~ (2 b) & @id ~ (3 c) & @id ~ (4 d)
+               & @id ~ (5 e) & @id ~ (6 f) & @id ~ (7 g) & @id ~ (8 h)
+             @id = (x x)",
+        )
+        .unwrap();
+        for mode in [EvalMode::Strict, EvalMode::Lazy] {
+            let mut net = GNet::new();
+            net.redexes.set_config(BagConfig {
+                low_len: Some(4),
+                ..BagConfig::new()
+            });
+            net.boot(&book).unwrap();
+            let booted = net.clone();
+            let result = match mode {
+                EvalMode::Strict => net.reduce(&book),
+                EvalMode::Lazy => net.reduce_lazy(&book),
+            };
+            assert_eq!(result.unwrap_err(), CoreError::BagOverflow(4), "{:?}", mode);
+            assert_eq!(net, booted, "{:?}", mode);
+
+            // The interaction runs, and is counted, once there is room
+            net.redexes.set_config(BagConfig::new());
+            let stats = net.reduce(&book).unwrap();
+            assert_eq!(stats.count(Rule::Call), 9, "{:?}", mode);
+            assert_eq!(net.to_string(), "(1 (2 (3 (4 (5 (6 (7 8)))))))", "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_bag_overflow_is_resumable() {
+        let book = corpus::sum(8);
+        let mut expected = GNet::new();
+        let total = expected.normalize(&book).unwrap();
+
+        let mut net = GNet::new();
+        net.redexes.set_config(BagConfig {
+            low_len: Some(3),
+            ..BagConfig::new()
+        });
+        net.boot(&book).unwrap();
+        let err = net.reduce(&book).unwrap_err();
+        assert_eq!(err, CoreError::BagOverflow(3));
+        assert!(net.redexes.lens().1 <= 3);
+        assert!(net.validate().is_ok());
+
+        net.redexes.set_config(BagConfig::new());
+        let rest = net.reduce(&book).unwrap();
+        assert!(rest.interactions < total.interactions);
+        assert_eq!(net.root(), expected.root());
+
+        // Two sums, so that redexes are set aside while one overflows
+        let book = Book::parse(
+            "@main = (x y) & @sum ~ (4 x) & @sum ~ (5 y)
+             @sum = (?((0 @sum.s) r) r)
+             @sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & [+1] ~ $(p1 q)",
+        )
+        .unwrap();
+        let mut expected = GNet::new();
+        expected.normalize(&book).unwrap();
+        assert_eq!(expected.to_string(), "(10 15)");
+        for mode in [EvalMode::Strict, EvalMode::Lazy] {
+            let mut net = GNet::new();
+            let mut config = BagConfig {
+                low_len: Some(0),
+                ..BagConfig::new()
+            };
+            net.redexes.set_config(config);
+            net.boot(&book).unwrap();
+            let mut overflows = 0;
+            loop {
+                let result = match mode {
+                    EvalMode::Strict => net.reduce(&book),
+                    EvalMode::Lazy => net.reduce_lazy(&book),
+                };
+                match result {
+                    Ok(_) => break,
+                    Err(err) => assert_eq!(err, CoreError::BagOverflow(config.low_len.unwrap())),
+                }
+                assert!(net.validate().is_ok(), "{:?}: {}", mode, net.validate());
+                overflows += 1;
+                config.low_len = config.low_len.map(|len| len + 1);
+                net.redexes.set_config(config);
+            }
+            assert!(overflows > 0, "{:?}", mode);
+            assert_eq!(net.to_string(), "(10 15)", "{:?}", mode);
+        }
+    }
+}
@@ -10,16 +10,21 @@
 // ==============================================================================
 
 use std::collections::HashMap;
-use crate::GNet;
+use crate::{GNet, Pair, Port, Result, Tag, Val};
 
 /// Book: stores function definitions
-#[derive(Debug, Clone)]
+///
+/// Definitions are numbered in insertion order; that number (the `fid`) is
+/// what `REF` ports carry as their value.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Book {
-    defs: HashMap<String, Def>,
+    defs: Vec<Def>,
+    ids: HashMap<String, Val>,
+    tmpls: Vec<Template>,
 }
 
 /// Definition: a named function/term
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Def {
     pub name: String,
     pub arity: usize,
@@ -29,16 +34,55 @@ pub struct Def {
 impl Book {
     pub fn new() -> Self {
         Book {
-            defs: HashMap::new(),
+            defs: Vec::new(),
+            ids: HashMap::new(),
+            tmpls: Vec::new(),
         }
     }
 
-    pub fn insert(&mut self, name: String, def: Def) {
-        self.defs.insert(name, def);
+    /// Inserts a definition, replacing any previous one with the same name.
+    /// Returns its fid.
+    pub fn insert(&mut self, name: String, def: Def) -> Val {
+        let tmpl = def.template();
+        if let Some(&fid) = self.ids.get(&name) {
+            self.defs[fid as usize] = def;
+            // Flags that relied on the old definition may be stale now
+            self.tmpls = self.defs.iter().map(Def::template).collect();
+            return fid;
+        }
+        let fid = self.defs.len() as Val;
+        self.ids.insert(name, fid);
+        self.defs.push(def);
+        self.tmpls.push(tmpl);
+        fid
     }
 
     pub fn get(&self, name: &str) -> Option<&Def> {
-        self.defs.get(name)
+        self.ids.get(name).map(|&fid| &self.defs[fid as usize])
+    }
+
+    /// Gets a definition by fid
+    pub fn get_by_id(&self, fid: Val) -> Option<&Def> {
+        self.defs.get(fid as usize)
+    }
+
+    /// Gets the compiled template of a definition by fid
+    pub fn template(&self, fid: Val) -> Option<&Template> {
+        self.tmpls.get(fid as usize)
+    }
+
+    pub(crate) fn set_safe(&mut self, fid: Val, safe: bool) {
+        self.tmpls[fid as usize].safe = safe;
+    }
+
+    /// Gets the fid of a definition
+    pub fn id(&self, name: &str) -> Option<Val> {
+        self.ids.get(name).copied()
+    }
+
+    /// Iterates definitions in fid order
+    pub fn iter(&self) -> impl Iterator<Item = (Val, &Def)> {
+        self.defs.iter().enumerate().map(|(fid, def)| (fid as Val, def))
     }
 
     pub fn len(&self) -> usize {
@@ -50,6 +94,134 @@ impl Book {
     }
 }
 
+/// Compiled definition: the layout of `Def` in `docs/dor/hvm.c`.
+///
+/// Nodes and variables are numbered densely from 0 and every variable
+/// starts out as NONE, so a CALL only has to allocate `node.len()` nodes
+/// and `vars` variables and shift each port onto them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// Can be copied by a DUP without being expanded first
+    pub safe: bool,
+    pub root: Port,
+    pub rbag: Vec<Pair>,
+    pub node: Vec<Pair>,
+    pub vars: usize,
+}
+
+/// Moves a template port onto the allocated nodes `nloc` and vars `vloc`
+pub fn adjust_port(port: Port, nloc: &[Val], vloc: &[Val]) -> Port {
+    if port == Port::NONE {
+        port
+    } else if port.is_nod() {
+        Port::new(port.tag(), nloc[port.val() as usize])
+    } else if port.is_var() {
+        Port::new(port.tag(), vloc[port.val() as usize])
+    } else {
+        port
+    }
+}
+
+/// `adjust_port` on both sides of a pair
+pub fn adjust_pair(pair: Pair, nloc: &[Val], vloc: &[Val]) -> Pair {
+    Pair::new(adjust_port(pair.fst(), nloc, vloc), adjust_port(pair.snd(), nloc, vloc))
+}
+
+impl Template {
+    /// Compiles a definition net, resolving bound variables.
+    ///
+    /// Only nets without DUP nodes or references are marked safe here;
+    /// `Book::propagate_safety` settles the flags of the rest.
+    pub fn new(net: &GNet) -> Self {
+        let nmap: HashMap<Val, Val> = net.nodes().enumerate().map(|(i, (loc, _))| (loc, i as Val)).collect();
+        let mut vmap: HashMap<Val, Val> = net
+            .vars()
+            .filter(|(_, val)| *val == Port::NONE)
+            .enumerate()
+            .map(|(i, (var, _))| (var, i as Val))
+            .collect();
+        let mut local = |port: Port| {
+            let port = if port.is_var() { net.peek(port) } else { port };
+            if port == Port::NONE {
+                port
+            } else if port.is_var() {
+                let next = vmap.len() as Val;
+                Port::new(Tag::Var, *vmap.entry(port.val()).or_insert(next))
+            } else if port.is_nod() {
+                Port::new(port.tag(), nmap[&port.val()])
+            } else {
+                port
+            }
+        };
+        let mut local_pair = |pair: Pair| Pair::new(local(pair.fst()), local(pair.snd()));
+        let node: Vec<Pair> = net.nodes().map(|(_, node)| local_pair(node)).collect();
+        let rbag: Vec<Pair> = net.redexes.iter().map(|&redex| local_pair(redex)).collect();
+        let root = local(net.root());
+        let ports = || node.iter().chain(&rbag).flat_map(|pair| [pair.fst(), pair.snd()]).chain([root]);
+        let safe = !ports().any(|port| port != Port::NONE && matches!(port.tag(), Tag::Dup | Tag::Ref));
+        Self {
+            safe,
+            root,
+            rbag,
+            node,
+            vars: vmap.len(),
+        }
+    }
+
+    /// Number of lambdas on the root's right spine, as `ast::Tree::arity`
+    pub fn arity(&self) -> usize {
+        let mut port = self.root;
+        let mut arity = 0;
+        // Bounded, in case a loaded template loops back on itself
+        while port != Port::NONE && port.tag() == Tag::Con && arity < self.node.len() {
+            arity += 1;
+            port = self.node[port.val() as usize].snd();
+        }
+        arity
+    }
+
+    /// Copies the template into `net`, linking its redexes, and returns
+    /// the adjusted root for the caller to link
+    pub fn instantiate(&self, net: &mut GNet) -> Result<Port> {
+        let nloc = (0..self.node.len()).map(|_| net.node_alloc()).collect::<Result<Vec<_>>>()?;
+        let vloc = (0..self.vars).map(|_| net.vars_alloc()).collect::<Result<Vec<_>>>()?;
+        for &var in &vloc {
+            net.vars_create(var, Port::NONE);
+        }
+        for (&loc, &node) in nloc.iter().zip(&self.node) {
+            net.node_create(loc, adjust_pair(node, &nloc, &vloc));
+        }
+        for &redex in &self.rbag {
+            net.link_pair(adjust_pair(redex, &nloc, &vloc));
+        }
+        Ok(adjust_port(self.root, &nloc, &vloc))
+    }
+
+    /// Rebuilds a standalone definition net: local node `i` goes to slot
+    /// `i + 1`, and redexes are kept as they are instead of being linked
+    pub fn to_net(&self) -> GNet {
+        let mut net = GNet::new();
+        let vloc: Vec<Val> = (0..self.vars).map(|_| net.vars_alloc().unwrap()).collect();
+        let nloc: Vec<Val> = (0..self.node.len()).map(|_| net.node_alloc().unwrap()).collect();
+        for &var in &vloc {
+            net.vars_create(var, Port::NONE);
+        }
+        for (&loc, &node) in nloc.iter().zip(&self.node) {
+            net.node_create(loc, adjust_pair(node, &nloc, &vloc));
+        }
+        net.redexes = self.rbag.iter().map(|&redex| adjust_pair(redex, &nloc, &vloc)).collect();
+        net.set_root(adjust_port(self.root, &nloc, &vloc));
+        net
+    }
+}
+
+impl Def {
+    /// Compiles the definition's net into its template form
+    pub fn template(&self) -> Template {
+        Template::new(&self.net)
+    }
+}
+
 impl Default for Book {
     fn default() -> Self {
         Self::new()
@@ -100,10 +272,75 @@ mod tests {
         assert_eq!(retrieved.unwrap().name, "func");
     }
 
+    #[test]
+    fn test_book_ids() {
+        let mut book = Book::new();
+        let def = |name: &str| Def {
+            name: name.to_string(),
+            arity: 0,
+            net: GNet::new(),
+        };
+
+        assert_eq!(book.insert("a".to_string(), def("a")), 0);
+        assert_eq!(book.insert("b".to_string(), def("b")), 1);
+        assert_eq!(book.insert("a".to_string(), def("a")), 0);
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.id("b"), Some(1));
+        assert_eq!(book.get_by_id(1).unwrap().name, "b");
+        assert!(book.get_by_id(2).is_none());
+    }
+
     #[test]
     fn test_book_get_missing() {
         let book = Book::new();
         let result = book.get("missing");
         assert!(result.is_none());
     }
+
+    #[test]
+    fn test_template_layout() {
+        let book = Book::parse("@main = ({(a b) (b c)} (a c))").unwrap();
+        let tmpl = book.template(0).unwrap();
+        assert_eq!(tmpl, &book.get("main").unwrap().template());
+        assert!(!tmpl.safe);
+        assert_eq!((tmpl.node.len(), tmpl.vars), (5, 3));
+        assert_eq!(tmpl.root, Port::new(Tag::Con, 0));
+        let var = |v| Port::new(Tag::Var, v);
+        assert_eq!(tmpl.node[0], Pair::new(Port::new(Tag::Dup, 1), Port::new(Tag::Con, 4)));
+        assert_eq!(tmpl.node[2], Pair::new(var(0), var(1)));
+        assert_eq!(tmpl.node[4], Pair::new(var(0), var(2)));
+        assert_eq!(tmpl.to_net(), book.get("main").unwrap().net);
+    }
+
+    #[test]
+    fn test_template_safe() {
+        let book = Book::parse("@main = {@id *}\n@id = (x x)\n@inc = (x y) & [+1] ~ $(x y)").unwrap();
+        let safe: Vec<bool> = book.iter().map(|(fid, _)| book.template(fid).unwrap().safe).collect();
+        assert_eq!(safe, vec![false, true, true]);
+    }
+
+    #[test]
+    fn test_template_instantiate() {
+        let book = Book::parse("@main = (x y) & [+1] ~ $(x y)").unwrap();
+        let mut net = GNet::new();
+        net.node_alloc().unwrap();
+        net.vars_alloc().unwrap();
+        let root = book.template(0).unwrap().instantiate(&mut net).unwrap();
+        // Slot 1 is taken, so the template starts at slot 2
+        assert_eq!(root, Port::new(Tag::Con, 2));
+        assert_eq!(net.node_count(), 3);
+        assert_eq!(net.vars_count(), 3);
+        assert_eq!(net.redexes.len(), 1);
+    }
+
+    #[test]
+    fn test_adjust_port() {
+        let (nloc, vloc) = ([7, 9], [4]);
+        assert_eq!(adjust_port(Port::new(Tag::Swi, 1), &nloc, &vloc), Port::new(Tag::Swi, 9));
+        assert_eq!(adjust_port(Port::new(Tag::Var, 0), &nloc, &vloc), Port::new(Tag::Var, 4));
+        assert_eq!(adjust_port(Port::new(Tag::Ref, 1), &nloc, &vloc), Port::new(Tag::Ref, 1));
+        assert_eq!(adjust_port(Port::NONE, &nloc, &vloc), Port::NONE);
+        let pair = Pair::new(Port::new(Tag::Con, 0), Port::new(Tag::Num, 5));
+        assert_eq!(adjust_pair(pair, &nloc, &vloc), Pair::new(Port::new(Tag::Con, 7), Port::new(Tag::Num, 5)));
+    }
 }
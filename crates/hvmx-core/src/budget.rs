@@ -0,0 +1,254 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: budget.rs
+// Location: crates/hvmx-core/src/budget.rs
+// Purpose: Evaluation budgets: interaction, node and time limits
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Metered evaluation, after the mana table in `docs/dor/hvm.rs`.
+//!
+//! Each interaction costs mana by rule; by default every rule but a link
+//! costs one, so mana counts interactions. A `Meter` charges each redex
+//! before it is reduced, and when a limit would be passed the redex goes
+//! back into the bag and `CoreError::Exhausted` is returned. The net is
+//! then as it was between two interactions and can be reduced further.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::interact::get_rule;
+use crate::{Book, CoreError, GNet, Port, Result, Rule, Stats, Tag};
+
+/// Checks the clock once every this many charges
+const CLOCK_EVERY: u64 = 256;
+
+/// Evaluation limits; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// Most mana to spend
+    pub max_mana: Option<u64>,
+    /// Most live nodes. Each interaction is checked before it runs against
+    /// the most nodes it can add, a whole template for a CALL, so the
+    /// count never goes past it.
+    pub max_nodes: Option<usize>,
+    /// Most wall time, from when the meter starts
+    pub max_time: Option<Duration>,
+    /// Mana per rule, indexed by `Rule`; `None` costs one per interaction
+    pub costs: Option<[u64; 8]>,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mana an interaction by `rule` costs
+    pub fn cost(&self, rule: Rule) -> u64 {
+        match &self.costs {
+            Some(costs) => costs[rule as usize],
+            None => (rule != Rule::Link) as u64,
+        }
+    }
+}
+
+/// The limit an evaluation ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Mana(u64),
+    Nodes(usize),
+    Time(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Mana(max) => write!(f, "more than {} mana", max),
+            Limit::Nodes(max) => write!(f, "more than {} nodes", max),
+            Limit::Time(max) => write!(f, "more than {:?}", max),
+        }
+    }
+}
+
+/// A budget and what has been spent of it
+#[derive(Debug, Clone)]
+pub struct Meter {
+    pub budget: Budget,
+    /// Mana spent so far
+    pub mana: u64,
+    start: Instant,
+    charges: u64,
+}
+
+impl Meter {
+    /// Starts the clock
+    pub fn new(budget: Budget) -> Self {
+        Self {
+            budget,
+            mana: 0,
+            start: Instant::now(),
+            charges: 0,
+        }
+    }
+
+    /// Time since the meter started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Charges for reducing `a ~ b` in `net`, or fails without charging
+    pub fn charge(&mut self, net: &GNet, book: &Book, a: Port, b: Port) -> Result<()> {
+        let rule = if a.tag() == Tag::Ref && b == Port::ROOT {
+            Rule::Call
+        } else {
+            get_rule(a, b)
+        };
+        let mana = self.mana + self.budget.cost(rule);
+        if let Some(max) = self.budget.max_mana {
+            if mana > max {
+                return Err(CoreError::Exhausted(Limit::Mana(max)));
+            }
+        }
+        if let Some(max) = self.budget.max_nodes {
+            if net.node_count() + max_new_nodes(book, rule, a, b) > max {
+                return Err(CoreError::Exhausted(Limit::Nodes(max)));
+            }
+        }
+        if let Some(max) = self.budget.max_time {
+            if self.charges.is_multiple_of(CLOCK_EVERY) && self.elapsed() > max {
+                return Err(CoreError::Exhausted(Limit::Time(max)));
+            }
+        }
+        self.mana = mana;
+        self.charges += 1;
+        Ok(())
+    }
+}
+
+/// Most nodes reducing `a ~ b` by `rule` can add to the live count: a
+/// commutation takes two nodes and makes four, a switch takes one and makes
+/// up to two, and a CALL makes its template's nodes
+fn max_new_nodes(book: &Book, rule: Rule, a: Port, b: Port) -> usize {
+    match rule {
+        Rule::Comm => 2,
+        Rule::Swit => 1,
+        Rule::Call => {
+            let fid = if a.tag() == Tag::Ref { a.val() } else { b.val() };
+            book.template(fid).map_or(0, |tmpl| tmpl.node.len())
+        }
+        _ => 0,
+    }
+}
+
+impl GNet {
+    /// Reduces until the bag is empty or `meter` runs out. Running out
+    /// leaves the net ready to be reduced again.
+    pub fn reduce_metered(&mut self, book: &Book, meter: &mut Meter) -> Result<Stats> {
+        let mut stats = Stats::new();
+        while let Some(redex) = self.redexes.pop() {
+            let (a, b) = (redex.fst(), redex.snd());
+            if let Err(err) = meter.charge(self, book, a, b) {
+                self.redexes.push(redex);
+                return Err(err);
+            }
+            let rule = self.interact_popped(book, redex)?;
+            stats.record(rule);
+        }
+        Ok(stats)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus;
+
+    fn booted(book: &Book) -> GNet {
+        let mut net = GNet::new();
+        net.boot(book).unwrap();
+        net
+    }
+
+    #[test]
+    fn test_budget_mana_counts_interactions() {
+        let book = corpus::sum(8);
+        let expected = GNet::new().normalize(&book).unwrap();
+        let mut net = booted(&book);
+        let mut meter = Meter::new(Budget::new());
+        let stats = net.reduce_metered(&book, &mut meter).unwrap();
+        assert_eq!(stats, expected);
+        assert_eq!(meter.mana, expected.interactions);
+    }
+
+    #[test]
+    fn test_budget_exhausted_and_resumed() {
+        let book = corpus::sum(8);
+        let mut expected = GNet::new();
+        let total = expected.normalize(&book).unwrap();
+
+        let mut net = booted(&book);
+        let mut meter = Meter::new(Budget {
+            max_mana: Some(10),
+            ..Budget::new()
+        });
+        while let Err(err) = net.reduce_metered(&book, &mut meter) {
+            let max = meter.budget.max_mana.unwrap();
+            assert_eq!(err, CoreError::Exhausted(Limit::Mana(max)));
+            assert!(meter.mana <= max);
+            assert!(!net.redexes.is_empty());
+            meter.budget.max_mana = Some(meter.mana + 10);
+        }
+        assert_eq!(meter.mana, total.interactions);
+        assert_eq!(net.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_budget_costs() {
+        let book = corpus::sum(4);
+        let total = GNet::new().normalize(&book).unwrap();
+        let mut costs = [0; 8];
+        costs[Rule::Call as usize] = 5;
+        let mut meter = Meter::new(Budget {
+            costs: Some(costs),
+            ..Budget::new()
+        });
+        booted(&book).reduce_metered(&book, &mut meter).unwrap();
+        assert_eq!(meter.mana, 5 * total.count(Rule::Call));
+        assert_eq!(Budget::new().cost(Rule::Link), 0);
+    }
+
+    #[test]
+    fn test_budget_runaway_program() {
+        let book = Book::parse("@main = * & @spin ~ (* *)\n@spin = (a b) & @spin ~ (a b)").unwrap();
+        let mut meter = Meter::new(Budget {
+            max_mana: Some(1000),
+            ..Budget::new()
+        });
+        let err = booted(&book).reduce_metered(&book, &mut meter).unwrap_err();
+        assert_eq!(err.to_string(), "Budget exhausted: more than 1000 mana");
+
+        // Grows one node per call
+        let book = Book::parse("@main = x & @grow ~ (* x)\n@grow = (a (* b)) & @grow ~ (a b)").unwrap();
+        let mut meter = Meter::new(Budget {
+            max_nodes: Some(64),
+            ..Budget::new()
+        });
+        let mut net = booted(&book);
+        assert_eq!(net.reduce_metered(&book, &mut meter), Err(CoreError::Exhausted(Limit::Nodes(64))));
+        assert!(net.node_count() <= 64);
+
+        let mut meter = Meter::new(Budget {
+            max_time: Some(Duration::from_millis(5)),
+            ..Budget::new()
+        });
+        let err = booted(&book).reduce_metered(&book, &mut meter).unwrap_err();
+        assert_eq!(err, CoreError::Exhausted(Limit::Time(Duration::from_millis(5))));
+    }
+}
@@ -0,0 +1,255 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: checkpoint.rs
+// Location: crates/hvmx-core/src/checkpoint.rs
+// Purpose: Copy-on-write heap pages, checkpoints and rollback
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Checkpoints, after `Rollback` in `docs/dor/hvm.rs`.
+//!
+//! The node and variable buffers are split into pages behind `Arc`s. A
+//! checkpoint clones the page list, sharing every page with the live net;
+//! the first write to a shared page copies it, so a checkpoint costs one
+//! pointer per page up front and one page per page touched afterwards.
+//! Free lists, the root and the redex bag are copied whole.
+
+use std::sync::Arc;
+
+use crate::{CoreError, GNet, Pair, Port, RedexBag, Result, Val};
+
+/// Slots per page
+pub const PAGE_LEN: usize = 1 << 12;
+
+/// A growable buffer of shared pages, copied on first write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pages<T> {
+    pages: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T: Copy> Pages<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<T> {
+        self.pages.get(i / PAGE_LEN).and_then(|page| page.get(i % PAGE_LEN)).copied()
+    }
+
+    /// Slot `i`, copying its page if a checkpoint shares it
+    pub fn get_mut(&mut self, i: usize) -> &mut T {
+        assert!(i < self.len, "page slot {} out of bounds", i);
+        &mut Arc::make_mut(&mut self.pages[i / PAGE_LEN])[i % PAGE_LEN]
+    }
+
+    pub fn push(&mut self, val: T) {
+        match self.pages.last_mut() {
+            Some(page) if page.len() < PAGE_LEN => Arc::make_mut(page).push(val),
+            _ => {
+                let mut page = Vec::with_capacity(PAGE_LEN);
+                page.push(val);
+                self.pages.push(Arc::new(page));
+            }
+        }
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.pages.iter().flat_map(|page| page.iter().copied())
+    }
+
+    /// Pages this buffer shares with some other one
+    pub fn shared(&self) -> usize {
+        self.pages.iter().filter(|page| Arc::strong_count(page) > 1).count()
+    }
+}
+
+impl<T: Copy> From<Vec<T>> for Pages<T> {
+    fn from(buf: Vec<T>) -> Self {
+        let len = buf.len();
+        let pages = buf.chunks(PAGE_LEN).map(|chunk| Arc::new(chunk.to_vec())).collect();
+        Self { pages, len }
+    }
+}
+
+/// A saved net state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    node_buf: Pages<Pair>,
+    vars_buf: Pages<Port>,
+    node_free: Vec<Val>,
+    vars_free: Vec<Val>,
+    root: Port,
+    redexes: RedexBag,
+}
+
+impl GNet {
+    /// Saves the current state and returns its id. Checkpoints stay until
+    /// dropped, and ids are never reused.
+    pub fn checkpoint(&mut self) -> usize {
+        let checkpoint = Checkpoint {
+            node_buf: self.node_buf.clone(),
+            vars_buf: self.vars_buf.clone(),
+            node_free: self.node_free.clone(),
+            vars_free: self.vars_free.clone(),
+            root: self.root,
+            redexes: self.redexes.clone(),
+        };
+        let id = self.next_checkpoint;
+        self.next_checkpoint += 1;
+        self.checkpoints.push((id, checkpoint));
+        id
+    }
+
+    /// Rolls the net back to checkpoint `id`, which is kept
+    pub fn restore(&mut self, id: usize) -> Result<()> {
+        let (_, checkpoint) = self
+            .checkpoints
+            .iter()
+            .find(|(got, _)| *got == id)
+            .ok_or(CoreError::UnknownCheckpoint(id))?;
+        let checkpoint = checkpoint.clone();
+        self.node_buf = checkpoint.node_buf;
+        self.vars_buf = checkpoint.vars_buf;
+        self.node_free = checkpoint.node_free;
+        self.vars_free = checkpoint.vars_free;
+        self.root = checkpoint.root;
+        self.redexes = checkpoint.redexes;
+        Ok(())
+    }
+
+    /// Drops checkpoint `id`, returning whether it existed
+    pub fn drop_checkpoint(&mut self, id: usize) -> bool {
+        let len = self.checkpoints.len();
+        self.checkpoints.retain(|(got, _)| *got != id);
+        self.checkpoints.len() != len
+    }
+
+    /// Ids of the checkpoints kept, oldest first
+    pub fn checkpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.checkpoints.iter().map(|(id, _)| *id)
+    }
+
+    /// Node and variable pages the live net shares with its checkpoints
+    pub fn shared_pages(&self) -> usize {
+        self.node_buf.shared() + self.vars_buf.shared()
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, ParConfig, Tag};
+
+    #[test]
+    fn test_pages_copy_on_write() {
+        let mut a: Pages<u32> = (0..PAGE_LEN as u32 * 3).collect::<Vec<_>>().into();
+        assert_eq!(a.len(), PAGE_LEN * 3);
+        let b = a.clone();
+        assert_eq!(a.shared(), 3);
+        *a.get_mut(PAGE_LEN + 1) = 7;
+        assert_eq!(a.shared(), 2);
+        assert_eq!(a.get(PAGE_LEN + 1), Some(7));
+        assert_eq!(b.get(PAGE_LEN + 1), Some(PAGE_LEN as u32 + 1));
+        a.push(9);
+        assert_eq!(a.get(PAGE_LEN * 3), Some(9));
+        assert_eq!(b.get(PAGE_LEN * 3), None);
+        assert_eq!(a.iter().count(), a.len());
+    }
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let book = corpus::sum(6);
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let start = net.checkpoint();
+        let booted = net.clone();
+        net.reduce(&book).unwrap();
+        let done = net.to_string();
+        let end = net.checkpoint();
+
+        net.restore(start).unwrap();
+        assert_eq!(net.to_string(), booted.to_string());
+        assert_eq!(net.redexes, booted.redexes);
+        // Restoring again replays the same evaluation
+        net.reduce(&book).unwrap();
+        assert_eq!(net.to_string(), done);
+        net.restore(start).unwrap();
+        net.restore(end).unwrap();
+        assert_eq!(net.to_string(), done);
+        assert_eq!(net.checkpoints().collect::<Vec<_>>(), vec![start, end]);
+    }
+
+    #[test]
+    fn test_checkpoint_shares_pages() {
+        let book = corpus::sum(10);
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        let era = Port::new(Tag::Era, 0);
+        // A big net, partly written after the checkpoint
+        for _ in 0..PAGE_LEN * 4 {
+            let loc = net.node_alloc().unwrap();
+            net.node_create(loc, Pair::new(era, era));
+        }
+        let id = net.checkpoint();
+        let pages = net.shared_pages();
+        assert!(pages >= 4);
+        let loc = net.node_alloc().unwrap();
+        net.node_create(loc, Pair::new(era, era));
+        assert!(net.shared_pages() >= pages - 1);
+        net.node_store(1, Pair::new(era, era));
+        net.restore(id).unwrap();
+        assert_eq!(net.node_count(), PAGE_LEN * 4);
+    }
+
+    #[test]
+    fn test_checkpoint_errors_and_drop() {
+        let mut net = GNet::new();
+        let a = net.checkpoint();
+        let b = net.checkpoint();
+        assert_ne!(a, b);
+        assert!(net.drop_checkpoint(a));
+        assert!(!net.drop_checkpoint(a));
+        assert_eq!(net.restore(a), Err(CoreError::UnknownCheckpoint(a)));
+        assert!(net.restore(b).is_ok());
+        assert_eq!(net.checkpoint(), b + 1);
+    }
+
+    #[test]
+    fn test_checkpoints_left_out_of_clone_and_eq() {
+        let book = corpus::sum(6);
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let plain = net.clone();
+        net.checkpoint();
+        assert_eq!(net, plain);
+        let copy = net.clone();
+        assert_eq!(copy.checkpoints().count(), 0);
+        assert_eq!(copy, net);
+    }
+
+    #[test]
+    fn test_checkpoint_survives_parallel_reduce() {
+        let book = corpus::sum(6);
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let id = net.checkpoint();
+        net.reduce_par(&book, &ParConfig::new(2)).unwrap();
+        let done = net.to_string();
+        net.restore(id).unwrap();
+        net.reduce(&book).unwrap();
+        assert_eq!(net.to_string(), done);
+    }
+}
@@ -0,0 +1,237 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: corpus.rs
+// Location: crates/hvmx-core/src/corpus.rs
+// Purpose: Test corpus shared by every evaluator
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+use crate::book::Def;
+use crate::{Book, GNet, Numb, Pair, Port, Tag, Val};
+
+/// Builds a definition net by hand
+struct Builder {
+    net: GNet,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self { net: GNet::new() }
+    }
+
+    fn var(&mut self) -> Port {
+        let var = self.net.vars_alloc().unwrap();
+        self.net.vars_create(var, Port::NONE);
+        Port::new(Tag::Var, var)
+    }
+
+    fn node(&mut self, tag: Tag, fst: Port, snd: Port) -> Port {
+        let loc = self.net.node_alloc().unwrap();
+        self.net.node_create(loc, Pair::new(fst, snd));
+        Port::new(tag, loc)
+    }
+
+    fn con(&mut self, fst: Port, snd: Port) -> Port {
+        self.node(Tag::Con, fst, snd)
+    }
+
+    fn redex(&mut self, a: Port, b: Port) {
+        self.net.redexes.push(Pair::new(a, b));
+    }
+
+    fn define(mut self, book: &mut Book, name: &str, root: Port) {
+        self.net.set_root(root);
+        let def = Def {
+            name: name.to_string(),
+            arity: 0,
+            net: self.net,
+        };
+        book.insert(name.to_string(), def);
+    }
+}
+
+/// U24 number
+pub(crate) fn num(val: Val) -> Port {
+    Port::new(Tag::Num, Numb::new_u24(val).0)
+}
+
+/// Operator, optionally applied to its first operand
+fn op(op: u32, val: Option<Val>) -> Port {
+    let sym = Numb::new_sym(op);
+    let numb = val.map_or(sym, |val| Numb::partial(sym, Numb::new_u24(val)));
+    Port::new(Tag::Num, numb.0)
+}
+
+/// Reserves fids so definitions can refer to each other
+fn declare(book: &mut Book, names: &[&str]) {
+    for name in names {
+        Builder::new().define(book, name, Port::new(Tag::Era, 0));
+    }
+}
+
+fn refer(book: &Book, name: &str) -> Port {
+    Port::new(Tag::Ref, book.id(name).unwrap())
+}
+
+/// `@main = 7`
+pub(crate) fn constant() -> Book {
+    let mut book = Book::new();
+    Builder::new().define(&mut book, "main", num(7));
+    book
+}
+
+/// `@id = (x x)`
+/// `@main = r & @id ~ (42 r)`
+pub(crate) fn identity() -> Book {
+    let mut book = Book::new();
+    declare(&mut book, &["main", "id"]);
+
+    let mut b = Builder::new();
+    let x = b.var();
+    let lam = b.con(x, x);
+    b.define(&mut book, "id", lam);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let app = b.con(num(42), r);
+    b.redex(refer(&book, "id"), app);
+    b.define(&mut book, "main", r);
+    book
+}
+
+/// `@c2 = ({(x y) (y z)} (x z))`
+/// `@inc = (x y) & [+1] ~ $(x y)`
+/// `@main = r & @c2 ~ (@inc (0 r))`, or, if `twice`,
+/// `@main = r & @c2 ~ (@inc (m r)) & @c2 ~ (@inc (0 m))`
+fn church(twice: bool) -> Book {
+    let mut book = Book::new();
+    declare(&mut book, &["main", "c2", "inc"]);
+
+    let mut b = Builder::new();
+    let (x, y, z) = (b.var(), b.var(), b.var());
+    let f0 = b.con(x, y);
+    let f1 = b.con(y, z);
+    let dup = b.node(Tag::Dup, f0, f1);
+    let body = b.con(x, z);
+    let lam = b.con(dup, body);
+    b.define(&mut book, "c2", lam);
+
+    let mut b = Builder::new();
+    let (x, y) = (b.var(), b.var());
+    let opr = b.node(Tag::Opr, x, y);
+    b.redex(op(Numb::OP_ADD, Some(1)), opr);
+    let lam = b.con(x, y);
+    b.define(&mut book, "inc", lam);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let mut arg = num(0);
+    if twice {
+        let m = b.var();
+        let inner = b.con(num(0), m);
+        let app = b.con(refer(&book, "inc"), inner);
+        b.redex(refer(&book, "c2"), app);
+        arg = m;
+    }
+    let outer = b.con(arg, r);
+    let app = b.con(refer(&book, "inc"), outer);
+    b.redex(refer(&book, "c2"), app);
+    b.define(&mut book, "main", r);
+    book
+}
+
+/// Church 2 applied to an increment: `2`
+pub(crate) fn church_inc() -> Book {
+    church(false)
+}
+
+/// Church 2 applied to an increment, twice: `4`
+pub(crate) fn church_twice() -> Book {
+    church(true)
+}
+
+/// `@sum = (?((0 @sum.s) r) r)`
+/// `@sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & [+1] ~ $(p1 q)`
+/// `@main = r & @sum ~ (n r)`
+pub(crate) fn sum(n: Val) -> Book {
+    let mut book = Book::new();
+    declare(&mut book, &["main", "sum", "sum.s"]);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let cases = b.con(num(0), refer(&book, "sum.s"));
+    let swi = b.node(Tag::Swi, cases, r);
+    let lam = b.con(swi, r);
+    b.define(&mut book, "sum", lam);
+
+    let mut b = Builder::new();
+    let (p0, p1, q, r) = (b.var(), b.var(), b.var(), b.var());
+    let acc = b.node(Tag::Opr, q, r);
+    let acc = b.node(Tag::Opr, op(Numb::OP_ADD, None), acc);
+    let app = b.con(p0, acc);
+    b.redex(refer(&book, "sum"), app);
+    let inc = b.node(Tag::Opr, p1, q);
+    b.redex(op(Numb::OP_ADD, Some(1)), inc);
+    let dup = b.node(Tag::Dup, p0, p1);
+    let lam = b.con(dup, r);
+    b.define(&mut book, "sum.s", lam);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let app = b.con(num(n), r);
+    b.redex(refer(&book, "sum"), app);
+    b.define(&mut book, "main", r);
+    book
+}
+
+/// `@gen = (?((1 @gen.s) r) r)`
+/// `@gen.s = ({p0 p1} r) & @gen ~ (p0 $([+] $(q r))) & @gen ~ (p1 q)`
+/// `@main = r & @gen ~ (d r)`
+///
+/// A full binary tree of calls of depth `d`, reducing to `2^d`.
+pub(crate) fn pow2(d: Val) -> Book {
+    let mut book = Book::new();
+    declare(&mut book, &["main", "gen", "gen.s"]);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let cases = b.con(num(1), refer(&book, "gen.s"));
+    let swi = b.node(Tag::Swi, cases, r);
+    let lam = b.con(swi, r);
+    b.define(&mut book, "gen", lam);
+
+    let mut b = Builder::new();
+    let (p0, p1, q, r) = (b.var(), b.var(), b.var(), b.var());
+    let add = b.node(Tag::Opr, q, r);
+    let add = b.node(Tag::Opr, op(Numb::OP_ADD, None), add);
+    let app = b.con(p0, add);
+    b.redex(refer(&book, "gen"), app);
+    let app = b.con(p1, q);
+    b.redex(refer(&book, "gen"), app);
+    let dup = b.node(Tag::Dup, p0, p1);
+    let lam = b.con(dup, r);
+    b.define(&mut book, "gen.s", lam);
+
+    let mut b = Builder::new();
+    let r = b.var();
+    let app = b.con(num(d), r);
+    b.redex(refer(&book, "gen"), app);
+    b.define(&mut book, "main", r);
+    book
+}
+
+/// Every program, with the number its `@main` reduces to
+pub(crate) fn all() -> Vec<(&'static str, Book, Val)> {
+    vec![
+        ("constant", constant(), 7),
+        ("identity", identity(), 42),
+        ("church_inc", church_inc(), 2),
+        ("church_twice", church_twice(), 4),
+        ("sum_10", sum(10), 55),
+        ("sum_100", sum(100), 5050),
+        ("pow2_10", pow2(10), 1024),
+    ]
+}
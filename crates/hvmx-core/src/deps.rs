@@ -0,0 +1,296 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: deps.rs
+// Location: crates/hvmx-core/src/deps.rs
+// Purpose: Definition dependency graph of a book
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Which definitions refer to which.
+//!
+//! An edge goes from a definition to every `@ref` in its net. Every walk
+//! keeps its own stack, so long reference chains don't overflow.
+
+use std::collections::HashMap;
+
+use crate::book::Def;
+use crate::{Book, CoreError, GNet, Pair, Port, Result, Tag, Val};
+
+impl Book {
+    /// Fids a definition refers to, sorted and without repeats
+    pub fn deps(&self, fid: Val) -> Vec<Val> {
+        let Some(tmpl) = self.template(fid) else {
+            return Vec::new();
+        };
+        let pairs = tmpl.rbag.iter().chain(&tmpl.node);
+        let mut deps: Vec<Val> = pairs
+            .flat_map(|pair| [pair.fst(), pair.snd()])
+            .chain([tmpl.root])
+            .filter(|&port| port != Port::NONE && port.tag() == Tag::Ref)
+            .map(|port| port.val())
+            .collect();
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    }
+
+    /// Fids reachable from `entry`, itself included, in fid order
+    pub fn reachable(&self, entry: &str) -> Result<Vec<Val>> {
+        let entry = self.id(entry).ok_or_else(|| CoreError::UndefinedDef(entry.to_string()))?;
+        let mut seen = vec![false; self.len()];
+        let mut stack = vec![entry];
+        seen[entry as usize] = true;
+        while let Some(fid) = stack.pop() {
+            for dep in self.deps(fid) {
+                if (dep as usize) < seen.len() && !seen[dep as usize] {
+                    seen[dep as usize] = true;
+                    stack.push(dep);
+                }
+            }
+        }
+        Ok((0..self.len() as Val).filter(|&fid| seen[fid as usize]).collect())
+    }
+
+    /// A copy holding only what `entry` reaches. Definitions keep their
+    /// relative order and references are renumbered to match; a reference
+    /// to a fid the book doesn't have is an `UndefinedDef` error.
+    pub fn prune(&self, entry: &str) -> Result<Book> {
+        let keep = self.reachable(entry)?;
+        let fids: HashMap<Val, Val> = keep.iter().enumerate().map(|(new, &old)| (old, new as Val)).collect();
+        self.remap(&keep, &fids)
+    }
+
+    /// A copy holding the definitions in `keep`, in that order, with every
+    /// reference renumbered through `fids`
+    pub(crate) fn remap(&self, keep: &[Val], fids: &HashMap<Val, Val>) -> Result<Book> {
+        let mut book = Book::new();
+        for &fid in keep {
+            let def = self.get_by_id(fid).unwrap();
+            let net = rename_refs(&def.net, fids)?;
+            book.insert(def.name.clone(), Def { net, ..def.clone() });
+        }
+        book.propagate_safety();
+        Ok(book)
+    }
+
+    /// Strongly connected components, each sorted, dependencies before
+    /// the definitions that use them (Tarjan's algorithm)
+    pub fn sccs(&self) -> Vec<Vec<Val>> {
+        const UNSEEN: usize = usize::MAX;
+        let len = self.len();
+        let deps: Vec<Vec<Val>> = (0..len as Val)
+            .map(|fid| self.deps(fid).into_iter().filter(|&dep| (dep as usize) < len).collect())
+            .collect();
+        let mut index = vec![UNSEEN; len];
+        let mut low = vec![0; len];
+        let mut on_stack = vec![false; len];
+        let mut stack = Vec::new();
+        let mut sccs = Vec::new();
+        let mut next = 0;
+
+        for root in 0..len {
+            if index[root] != UNSEEN {
+                continue;
+            }
+            // (node, next edge to visit)
+            let mut work = vec![(root, 0)];
+            while let Some(&mut (v, ref mut edge)) = work.last_mut() {
+                if *edge == 0 && index[v] == UNSEEN {
+                    index[v] = next;
+                    low[v] = next;
+                    next += 1;
+                    stack.push(v);
+                    on_stack[v] = true;
+                }
+                if let Some(&w) = deps[v].get(*edge) {
+                    *edge += 1;
+                    let w = w as usize;
+                    if index[w] == UNSEEN {
+                        work.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                work.pop();
+                if let Some(&(parent, _)) = work.last() {
+                    low[parent] = low[parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut scc = Vec::new();
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        scc.push(w as Val);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    scc.sort_unstable();
+                    sccs.push(scc);
+                }
+            }
+        }
+        sccs
+    }
+
+    /// Recursive groups: components with more than one definition, or a
+    /// definition that refers to itself
+    pub fn cycles(&self) -> Vec<Vec<Val>> {
+        self.sccs()
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.deps(scc[0]).contains(&scc[0]))
+            .collect()
+    }
+
+    /// Every fid, each after the definitions it refers to. Members of a
+    /// cycle come out together, in fid order.
+    pub fn topo_order(&self) -> Vec<Val> {
+        self.sccs().into_iter().flatten().collect()
+    }
+}
+
+/// Copies a net, renumbering its `@ref` ports through `fids`. Unknown
+/// fids are named as `Display` prints them, `@fid`.
+fn rename_refs(net: &GNet, fids: &HashMap<Val, Val>) -> Result<GNet> {
+    let rename = |port: Port| {
+        if port == Port::NONE || port.tag() != Tag::Ref {
+            return Ok(port);
+        }
+        match fids.get(&port.val()) {
+            Some(&fid) => Ok(Port::new(Tag::Ref, fid)),
+            None => Err(CoreError::UndefinedDef(port.val().to_string())),
+        }
+    };
+    let rename_pair = |pair: Pair| Ok(Pair::new(rename(pair.fst())?, rename(pair.snd())?));
+    let mut out = net.clone();
+    for (loc, node) in net.nodes() {
+        out.node_store(loc, rename_pair(node)?);
+    }
+    for (var, val) in net.vars() {
+        out.vars_create(var, rename(val)?);
+    }
+    out.vars_create(Port::ROOT.val(), rename(net.vars_load(Port::ROOT.val()))?);
+    out.redexes = net.redexes.iter().map(|&redex| rename_pair(redex)).collect::<Result<_>>()?;
+    Ok(out)
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = "@main = r & @even ~ (4 r)\n\
+                       @even = (?((1 @even.s) r) r)\n\
+                       @odd = (?((0 @odd.s) r) r)\n\
+                       @even.s = (x r) & @odd ~ (x r)\n\
+                       @odd.s = (x r) & @even ~ (x r)\n\
+                       @dead = (x x) & @lone ~ *\n\
+                       @lone = @lone\n\
+                       @id = (x x)";
+
+    fn names(book: &Book, fids: &[Val]) -> Vec<String> {
+        fids.iter().map(|&fid| book.get_by_id(fid).unwrap().name.clone()).collect()
+    }
+
+    #[test]
+    fn test_deps() {
+        let book = Book::parse(SRC).unwrap();
+        assert_eq!(names(&book, &book.deps(book.id("even").unwrap())), vec!["even.s"]);
+        assert_eq!(names(&book, &book.deps(book.id("dead").unwrap())), vec!["lone"]);
+        assert!(book.deps(book.id("id").unwrap()).is_empty());
+        assert!(book.deps(99).is_empty());
+    }
+
+    #[test]
+    fn test_reachable() {
+        let book = Book::parse(SRC).unwrap();
+        let reach = book.reachable("main").unwrap();
+        assert_eq!(names(&book, &reach), vec!["main", "even", "odd", "even.s", "odd.s"]);
+        assert_eq!(names(&book, &book.reachable("dead").unwrap()), vec!["dead", "lone"]);
+        assert_eq!(book.reachable("nope"), Err(CoreError::UndefinedDef("nope".to_string())));
+    }
+
+    #[test]
+    fn test_prune() {
+        let book = Book::parse(SRC).unwrap();
+        let pruned = book.prune("main").unwrap();
+        assert_eq!(pruned.len(), 5);
+        assert!(pruned.get("dead").is_none());
+        let expected = "@main = r & @even ~ (4 r)\n\
+                        @even = (?((1 @even.s) r) r)\n\
+                        @odd = (?((0 @odd.s) r) r)\n\
+                        @even.s = (x r) & @odd ~ (x r)\n\
+                        @odd.s = (x r) & @even ~ (x r)";
+        assert_eq!(pruned, Book::parse(expected).unwrap());
+        let dead = book.prune("dead").unwrap();
+        assert_eq!(dead.to_string(), "@dead = (a a)\n  & @lone ~ *\n@lone = @lone\n");
+
+        let mut a = GNet::new();
+        a.normalize(&book).unwrap();
+        let mut b = GNet::new();
+        b.normalize(&pruned).unwrap();
+        assert_eq!(a.to_string(), "1");
+        assert_eq!(b.to_string(), "1");
+
+        // A reference past the end of the book is an error, not a panic
+        let mut net = GNet::new();
+        net.vars_create(Port::ROOT.val(), Port::new(Tag::Ref, 9));
+        let mut dangling = Book::new();
+        dangling.insert("main".to_string(), Def { name: "main".to_string(), arity: 0, net });
+        assert_eq!(dangling.prune("main"), Err(CoreError::UndefinedDef("9".to_string())));
+    }
+
+    #[test]
+    fn test_sccs_and_cycles() {
+        let book = Book::parse(SRC).unwrap();
+        let sccs: Vec<Vec<String>> = book.sccs().iter().map(|scc| names(&book, scc)).collect();
+        assert_eq!(
+            sccs,
+            vec![
+                vec!["even", "odd", "even.s", "odd.s"],
+                vec!["main"],
+                vec!["lone"],
+                vec!["dead"],
+                vec!["id"],
+            ]
+        );
+        let cycles: Vec<Vec<String>> = book.cycles().iter().map(|scc| names(&book, scc)).collect();
+        assert_eq!(cycles, vec![vec!["even", "odd", "even.s", "odd.s"], vec!["lone"]]);
+    }
+
+    #[test]
+    fn test_topo_order() {
+        let book = Book::parse(SRC).unwrap();
+        let order = book.topo_order();
+        assert_eq!(order.len(), book.len());
+        let pos: HashMap<Val, usize> = order.iter().enumerate().map(|(i, &fid)| (fid, i)).collect();
+        let sccs = book.sccs();
+        let scc_of = |fid: Val| sccs.iter().position(|scc| scc.contains(&fid)).unwrap();
+        for (fid, _) in book.iter() {
+            for dep in book.deps(fid) {
+                assert!(pos[&dep] < pos[&fid] || scc_of(dep) == scc_of(fid));
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // Deep enough to overflow a recursive walk
+        let mut src = String::from("@main = @d0\n");
+        for i in 0..20_000 {
+            src.push_str(&format!("@d{} = @d{}\n", i, i + 1));
+        }
+        src.push_str("@d20000 = *\n");
+        let book = Book::parse(&src).unwrap();
+        assert_eq!(book.reachable("main").unwrap().len(), book.len());
+        assert_eq!(book.sccs().len(), book.len());
+        assert_eq!(book.topo_order()[0], book.id("d20000").unwrap());
+    }
+}
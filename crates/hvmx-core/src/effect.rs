@@ -0,0 +1,549 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: effect.rs
+// Location: crates/hvmx-core/src/effect.rs
+// Purpose: Host effect handlers called from nets
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Host effects, after the `IO_CALL` family in `docs/dor/hvm.rs`.
+//!
+//! An effect is a definition whose calls the runtime handles in Rust. A
+//! request is a call `@name ~ (arg ret)`. The runtime sets requests aside
+//! until no other redex is left, so that each `arg` is in normal form,
+//! then runs them one at a time in the order they were reached: `arg` is
+//! read back as a tree and erased, and the handler's tree is linked to
+//! `ret`. A request whose `arg` still waits on another request's result
+//! runs after it; one whose `arg` holds any other free variable fails.
+//! Under a budget, requests are charged when they run.
+//!
+//! The definition itself is a stub that other evaluators run instead;
+//! `Effects::prelude` writes stubs for every registered effect.
+//!
+//! Strings are lists of characters, `(c0 (c1 ... *))`.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ast::Tree;
+use crate::interact::get_rule;
+use crate::{Book, CoreError, GNet, Numb, Pair, Port, Result, Rule, Tag, Val};
+
+/// A host function: the request's argument to its result
+pub type Handler = Arc<dyn Fn(&Tree) -> Result<Tree> + Send + Sync>;
+
+/// What the built-in effects may touch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Whether `IO/print` may write to stdout
+    pub stdout: bool,
+    /// Directories `IO/read` may read under; empty denies every read
+    pub read_dirs: Vec<PathBuf>,
+    /// Whether `IO/time` may read the clock
+    pub clock: bool,
+    /// Most effect calls per `reduce`, built-in or not; `None` is unbounded
+    pub max_calls: Option<u64>,
+}
+
+impl Sandbox {
+    /// Stdout and the clock, but no files
+    pub fn new() -> Self {
+        Self {
+            stdout: true,
+            read_dirs: Vec::new(),
+            clock: true,
+            max_calls: None,
+        }
+    }
+
+    /// Nothing at all
+    pub fn deny_all() -> Self {
+        Self {
+            stdout: false,
+            clock: false,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Named effect handlers and the sandbox they run in
+#[derive(Clone, Default)]
+pub struct Effects {
+    handlers: HashMap<String, Handler>,
+    pub sandbox: Sandbox,
+}
+
+impl fmt::Debug for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Effects")
+            .field("names", &self.names())
+            .field("sandbox", &self.sandbox)
+            .finish()
+    }
+}
+
+impl Effects {
+    /// No effects
+    pub fn new(sandbox: Sandbox) -> Self {
+        Self {
+            handlers: HashMap::new(),
+            sandbox,
+        }
+    }
+
+    /// `IO/print`, `IO/read` and `IO/time`, limited by `sandbox`:
+    ///
+    /// - `IO/print` writes a string or a number to stdout and returns the
+    ///   bytes written;
+    /// - `IO/read` takes a path string and returns the file's contents;
+    /// - `IO/time` ignores its argument and returns the Unix time in
+    ///   milliseconds, as `(hi lo)` with `ms = hi << 24 | lo`.
+    pub fn builtin(sandbox: Sandbox) -> Self {
+        let mut effects = Self::new(sandbox.clone());
+        let allowed = sandbox.clone();
+        effects.register("IO/print", move |arg| {
+            if !allowed.stdout {
+                return denied("IO/print", "stdout");
+            }
+            let text = match arg {
+                Tree::Num { val } => val.to_string(),
+                _ => read_string(arg).ok_or_else(|| failed("IO/print", "expected a string or a number"))?,
+            };
+            let mut out = std::io::stdout().lock();
+            out.write_all(text.as_bytes())
+                .and_then(|()| out.flush())
+                .map_err(|err| failed("IO/print", &err.to_string()))?;
+            Ok(Tree::Num { val: Numb::new_u24(text.len() as u32) })
+        });
+        let allowed = sandbox.clone();
+        effects.register("IO/read", move |arg| {
+            let path = read_string(arg).ok_or_else(|| failed("IO/read", "expected a path string"))?;
+            let path = std::fs::canonicalize(&path).map_err(|err| failed("IO/read", &format!("{}: {}", path, err)))?;
+            let inside = allowed
+                .read_dirs
+                .iter()
+                .any(|dir| std::fs::canonicalize(dir).is_ok_and(|dir| path.starts_with(dir)));
+            if !inside {
+                return denied("IO/read", &path.display().to_string());
+            }
+            let text = std::fs::read_to_string(&path).map_err(|err| failed("IO/read", &format!("{}: {}", path.display(), err)))?;
+            Ok(string(&text))
+        });
+        effects.register("IO/time", move |_| {
+            if !sandbox.clock {
+                return denied("IO/time", "the clock");
+            }
+            let ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+            let half = |n: u64| Box::new(Tree::Num { val: Numb::new_u24((n & 0xFF_FFFF) as u32) });
+            Ok(Tree::Con {
+                fst: half(ms >> 24),
+                snd: half(ms),
+            })
+        });
+        effects
+    }
+
+    /// Handles calls to `@name` with `handler`, replacing any previous one
+    pub fn register(&mut self, name: &str, handler: impl Fn(&Tree) -> Result<Tree> + Send + Sync + 'static) {
+        self.handlers.insert(name.to_string(), Arc::new(handler));
+    }
+
+    /// Registered effect names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Stub definitions for every registered effect, to prepend to a book's
+    /// source. Other evaluators erase the argument and return `*`.
+    pub fn prelude(&self) -> String {
+        self.names().iter().map(|name| format!("@{} = (* *)\n", name)).collect()
+    }
+
+    /// Whether `a ~ b` is a request for a registered effect
+    pub(crate) fn is_request(&self, book: &Book, a: Port, b: Port) -> bool {
+        if self.is_empty() || get_rule(a, b) != Rule::Call {
+            return false;
+        }
+        let (r, node) = if a.tag() == Tag::Ref { (a, b) } else { (b, a) };
+        node.tag() == Tag::Con && book.get_by_id(r.val()).is_some_and(|def| self.handlers.contains_key(&def.name))
+    }
+
+    /// Runs the first pending request whose argument is ready, charging it
+    /// with `check` and counting it in `calls`. If it fails, every request
+    /// goes back into the redex bag.
+    pub(crate) fn perform(
+        &self,
+        net: &mut GNet,
+        book: &Book,
+        pending: &mut Vec<Pair>,
+        calls: &mut u64,
+        check: impl FnMut(&GNet, Port, Port) -> Result<()>,
+    ) -> Result<()> {
+        let result = self.perform_next(net, book, pending, calls, check);
+        if result.is_err() {
+            net.redexes.extend(pending.drain(..));
+        }
+        result
+    }
+
+    fn perform_next(
+        &self,
+        net: &mut GNet,
+        book: &Book,
+        pending: &mut Vec<Pair>,
+        calls: &mut u64,
+        mut check: impl FnMut(&GNet, Port, Port) -> Result<()>,
+    ) -> Result<()> {
+        if let Some(max) = self.sandbox.max_calls.filter(|max| *calls >= *max) {
+            return Err(CoreError::Effect(format!("more than {} effect calls", max)));
+        }
+        // An argument is ready once it is closed. One whose free variables
+        // no request returns will never be.
+        let requests: Vec<(Port, Port)> = pending.iter().map(|redex| request(*redex)).collect();
+        let rets: HashSet<Val> = requests
+            .iter()
+            .flat_map(|&(_, node)| unlinked(net, net.node_load(node.val()).snd()).into_keys())
+            .collect();
+        let mut open = None;
+        let mut ready = None;
+        for (index, &(_, node)) in requests.iter().enumerate() {
+            let free: Vec<Val> = unlinked(net, net.node_load(node.val()).fst())
+                .into_iter()
+                .filter_map(|(var, count)| (count == 1).then_some(var))
+                .collect();
+            if free.is_empty() {
+                ready = Some(index);
+                break;
+            }
+            if open.is_none() && !free.iter().any(|var| rets.contains(var)) {
+                open = Some(index);
+            }
+        }
+        let Some(index) = ready else {
+            return Err(match open {
+                Some(index) => {
+                    let (r, node) = requests[index];
+                    let arg = net.to_tree(net.node_load(node.val()).fst(), Some(book));
+                    failed(&book.get_by_id(r.val()).unwrap().name, &format!("argument '{}' is not closed", arg))
+                }
+                None => CoreError::Effect(format!("{} requests wait on each other", pending.len())),
+            });
+        };
+        let redex = pending[index];
+        check(net, redex.fst(), redex.snd())?;
+        let (r, node) = requests[index];
+        let name = &book.get_by_id(r.val()).unwrap().name;
+        let (arg, ret) = (net.node_load(node.val()).fst(), net.node_load(node.val()).snd());
+
+        let tree = (self.handlers[name])(&net.to_tree(arg, Some(book)))?;
+        if let Some(var) = unpaired(&tree) {
+            return Err(CoreError::Effect(format!("@{} returned '{}', where '{}' occurs once", name, tree, var)));
+        }
+        let port = net.alloc_tree(&tree, |nam| book.id(nam))?;
+        *calls += 1;
+        pending.remove(index);
+        net.node_take(node.val());
+        net.link(arg, Port::new(Tag::Era, 0));
+        net.link(ret, port);
+        Ok(())
+    }
+}
+
+/// A request's REF and `(arg ret)` node
+fn request(redex: Pair) -> (Port, Port) {
+    if redex.fst().tag() == Tag::Ref {
+        (redex.fst(), redex.snd())
+    } else {
+        (redex.snd(), redex.fst())
+    }
+}
+
+/// Unlinked variables in the tree under `port`, following linked ones,
+/// and how often each occurs there
+fn unlinked(net: &GNet, port: Port) -> HashMap<Val, usize> {
+    let mut count = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![port];
+    while let Some(port) = stack.pop() {
+        if port.tag() == Tag::Var {
+            let val = net.vars_load(port.val());
+            if val == Port::NONE || val == Port::FREE {
+                *count.entry(port.val()).or_default() += 1;
+            } else if seen.insert(port.val()) {
+                stack.push(val);
+            }
+        } else if port.is_nod() {
+            let node = net.node_load(port.val());
+            stack.push(node.snd());
+            stack.push(node.fst());
+        }
+    }
+    count
+}
+
+fn failed(name: &str, msg: &str) -> CoreError {
+    CoreError::Effect(format!("@{}: {}", name, msg))
+}
+
+fn denied<T>(name: &str, what: &str) -> Result<T> {
+    Err(failed(name, &format!("{} is outside the sandbox", what)))
+}
+
+/// A variable occurring once in `tree`, if any
+fn unpaired(tree: &Tree) -> Option<&str> {
+    let mut count: HashMap<&str, usize> = HashMap::new();
+    let mut stack = vec![tree];
+    while let Some(tree) = stack.pop() {
+        match tree {
+            Tree::Var { nam } => *count.entry(nam).or_default() += 1,
+            _ => {
+                if let Some((_, fst, snd)) = tree.children() {
+                    stack.push(snd);
+                    stack.push(fst);
+                }
+            }
+        }
+    }
+    count.into_iter().find(|(_, n)| *n == 1).map(|(nam, _)| nam)
+}
+
+/// `text` as a list of characters
+pub fn string(text: &str) -> Tree {
+    text.chars().rev().fold(Tree::Era, |rest, c| Tree::Con {
+        fst: Box::new(Tree::Num { val: Numb::new_u24(c as u32) }),
+        snd: Box::new(rest),
+    })
+}
+
+/// The text a list of characters holds, if `tree` is one
+pub fn read_string(mut tree: &Tree) -> Option<String> {
+    let mut text = String::new();
+    loop {
+        match tree {
+            Tree::Era => return Some(text),
+            Tree::Con { fst, snd } => match **fst {
+                Tree::Num { val } if val.typ() == Numb::TY_U24 => {
+                    text.push(char::from_u32(val.u24())?);
+                    tree = snd;
+                }
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BagConfig, Budget, Config, Limit, Runtime};
+    use std::sync::Mutex;
+
+    fn run(effects: Effects, src: &str) -> Result<GNet> {
+        let book = Book::parse(&(effects.prelude() + src))?;
+        Runtime::with_effects(book, Config::new(), effects)?.run().map(|(net, _)| net)
+    }
+
+    /// `@double`, which doubles a number
+    fn doubling() -> Effects {
+        let mut effects = Effects::new(Sandbox::new());
+        effects.register("double", |arg| match arg {
+            Tree::Num { val } => Ok(Tree::Num { val: Numb::new_u24(val.u24() * 2) }),
+            _ => Err(CoreError::Effect("not a number".to_string())),
+        });
+        effects
+    }
+
+    #[test]
+    fn test_strings() {
+        let tree = string("hi");
+        assert_eq!(tree.to_string(), "(104 (105 *))");
+        assert_eq!(read_string(&tree).as_deref(), Some("hi"));
+        assert_eq!(read_string(&Tree::Era).as_deref(), Some(""));
+        assert_eq!(read_string(&Tree::Num { val: Numb::new_u24(1) }), None);
+    }
+
+    #[test]
+    fn test_effect_splices_result() {
+        let effects = doubling();
+        // The argument is computed first, and the results are chained
+        let net = run(effects.clone(), "@main = r & @double ~ (x r) & @double ~ (y x) & [+1] ~ $(20 y)").unwrap();
+        assert_eq!(net.to_string(), "84");
+
+        // The optimizer leaves the calls alone
+        let book = Book::parse(&(effects.prelude() + "@main = r & @double ~ (21 r)")).unwrap();
+        let config = Config {
+            optimize: Some(crate::OptConfig::new()),
+            ..Config::new()
+        };
+        let (net, _) = Runtime::with_effects(book, config, effects.clone()).unwrap().run().unwrap();
+        assert_eq!(net.to_string(), "42");
+
+        let err = run(effects, "@main = r & @double ~ (* r)").unwrap_err();
+        assert_eq!(err, CoreError::Effect("not a number".to_string()));
+    }
+
+    #[test]
+    fn test_effect_survives_bag_overflow() {
+        let effects = doubling();
+        let src = "@main = (d (x y)) & @sum ~ (4 x) & @sum ~ (5 y) & @double ~ (21 d)
+             @sum = (?((0 @sum.s) r) r)
+             @sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & [+1] ~ $(p1 q)";
+        let book = Book::parse(&(effects.prelude() + src)).unwrap();
+        let runtime = Runtime::with_effects(book, Config::new(), effects).unwrap();
+
+        // The request is popped first, so it is pending when the sums overflow
+        let mut bag = BagConfig {
+            low_len: Some(0),
+            ..BagConfig::new()
+        };
+        let mut net = GNet::new();
+        net.redexes.set_config(bag);
+        net.boot(runtime.book()).unwrap();
+        let mut overflows = 0;
+        while let Err(err) = runtime.reduce(&mut net) {
+            assert_eq!(err, CoreError::BagOverflow(bag.low_len.unwrap()));
+            assert!(net.validate().is_ok());
+            overflows += 1;
+            bag.low_len = bag.low_len.map(|len| len + 1);
+            net.redexes.set_config(bag);
+        }
+        assert!(overflows > 0);
+        assert_eq!(net.to_string(), "(42 (10 15))");
+    }
+
+    #[test]
+    fn test_effect_order_and_strings() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut effects = Effects::new(Sandbox::new());
+        let log = seen.clone();
+        effects.register("log", move |arg| {
+            log.lock().unwrap().push(read_string(arg).unwrap());
+            Ok(string("ok"))
+        });
+        let net = run(effects, "@main = (r s) & @log ~ ((97 *) r) & @log ~ ((98 (99 *)) s)").unwrap();
+        assert_eq!(net.to_string(), "((111 (107 *)) (111 (107 *)))");
+        // The bag pops the last redex written first, so `bc` is reached first
+        assert_eq!(*seen.lock().unwrap(), vec!["bc", "a"]);
+    }
+
+    #[test]
+    fn test_builtin_effects() {
+        let net = run(Effects::builtin(Sandbox::new()), "@main = r & @IO/time ~ (* r)").unwrap();
+        let time = net.to_tree(net.root(), None);
+        let Tree::Con { fst, .. } = &time else {
+            panic!("expected (hi lo), got {}", time);
+        };
+        assert!(matches!(**fst, Tree::Num { val } if val.u24() > 0));
+
+        let dir = std::env::temp_dir().join(format!("hvmx-effects-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("in.txt");
+        std::fs::write(&file, "xy").unwrap();
+        let src = format!("@main = r & @IO/read ~ ({} r)", string(file.to_str().unwrap()));
+        let sandbox = Sandbox {
+            read_dirs: vec![dir.clone()],
+            ..Sandbox::new()
+        };
+        let net = run(Effects::builtin(sandbox), &src).unwrap();
+        assert_eq!(net.to_string(), "(120 (121 *))");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let src = format!("@main = r & @IO/print ~ ({} r)", string("hi"));
+        let err = run(Effects::builtin(Sandbox::deny_all()), &src).unwrap_err();
+        assert_eq!(err.to_string(), "Effect error: @IO/print: stdout is outside the sandbox");
+
+        let err = run(Effects::builtin(Sandbox::deny_all()), "@main = r & @IO/time ~ (* r)").unwrap_err();
+        assert_eq!(err.to_string(), "Effect error: @IO/time: the clock is outside the sandbox");
+
+        let src = format!("@main = r & @IO/read ~ ({} r)", string("Cargo.toml"));
+        let err = run(Effects::builtin(Sandbox::new()), &src).unwrap_err();
+        assert!(err.to_string().contains("outside the sandbox"), "{}", err);
+
+        let sandbox = Sandbox {
+            max_calls: Some(1),
+            ..Sandbox::new()
+        };
+        let err = run(Effects::builtin(sandbox), "@main = (r s) & @IO/time ~ (* r) & @IO/time ~ (* s)").unwrap_err();
+        assert_eq!(err.to_string(), "Effect error: more than 1 effect calls");
+    }
+
+    #[test]
+    fn test_effects_wait_on_each_other() {
+        let mut effects = Effects::new(Sandbox::new());
+        effects.register("id", |arg| Ok(arg.clone()));
+        let book = Book::parse(&(effects.prelude() + "@main = * & @id ~ (x y) & @id ~ (y x)")).unwrap();
+        let runtime = Runtime::with_effects(book.clone(), Config::new(), effects).unwrap();
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let err = runtime.reduce(&mut net).unwrap_err();
+        assert_eq!(err, CoreError::Effect("2 requests wait on each other".to_string()));
+        // The requests are back in the bag
+        assert_eq!(net.redexes.len(), 2);
+        assert!(net.validate().is_ok());
+    }
+
+    #[test]
+    fn test_effect_argument_not_closed() {
+        let mut effects = Effects::new(Sandbox::new());
+        effects.register("id", |arg| Ok(arg.clone()));
+        // `a` is bound in the root, not by another request
+        let err = run(effects, "@main = (a r) & @id ~ (a r)").unwrap_err();
+        assert_eq!(err, CoreError::Effect("@id: argument 'a' is not closed".to_string()));
+    }
+
+    #[test]
+    fn test_effect_charged_once() {
+        // Only calls cost, and one is allowed per run
+        let mut costs = [0; 8];
+        costs[Rule::Call as usize] = 1;
+        let config = Config {
+            budget: Some(Budget {
+                max_mana: Some(1),
+                costs: Some(costs),
+                ..Budget::new()
+            }),
+            ..Config::new()
+        };
+        let effects = doubling();
+        let book = Book::parse(&(effects.prelude() + "@main = (a b) & @double ~ (1 a) & @double ~ (2 b)")).unwrap();
+        let runtime = Runtime::with_effects(book, config, effects).unwrap();
+        let mut net = GNet::new();
+        net.boot(runtime.book()).unwrap();
+        let mut resumes = 0;
+        while let Err(err) = runtime.reduce(&mut net) {
+            assert_eq!(err, CoreError::Exhausted(Limit::Mana(1)));
+            resumes += 1;
+            assert!(resumes < 8, "requests set aside are charged again");
+        }
+        // `@main`, then each request as it runs
+        assert_eq!(resumes, 2);
+        assert_eq!(net.to_string(), "(2 4)");
+    }
+}
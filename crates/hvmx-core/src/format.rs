@@ -0,0 +1,403 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: format.rs
+// Location: crates/hvmx-core/src/format.rs
+// Purpose: Binary book format, compatible with hvm.c's book_load
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Binary books.
+//!
+//! A buffer is a `HEADER_LEN`-word header followed by the exact payload
+//! `book_load` in `docs/dor/hvm.c` and `hvm.cu` reads, so C code can load
+//! it with `book_load(book, buf + HEADER_LEN)`. All words are little-endian.
+//!
+//! ```text
+//! header  ::= MAGIC VERSION payload_len
+//! payload ::= defs_len def*
+//! def     ::= fid name[64] safe rbag_len node_len vars_len root
+//!             rbag_pair[rbag_len] node_pair[node_len]
+//! ```
+//!
+//! Names are NUL-padded to 256 bytes. Pairs are two words, `fst` first.
+//! `BookView` reads a buffer in place, e.g. straight from a memory-mapped file.
+
+use crate::book::{Def, Template};
+use crate::{Book, CoreError, Pair, Port, Result, Val};
+
+/// "HVMX" in little-endian
+pub const MAGIC: u32 = u32::from_le_bytes(*b"HVMX");
+/// Format version
+pub const VERSION: u32 = 1;
+/// Header length in words
+pub const HEADER_LEN: usize = 3;
+
+/// Name length in bytes, NUL included
+const NAME_LEN: usize = 256;
+/// `DEF_RBAG_LEN` and `DEF_NODE_LEN` in hvm.c
+const DEF_LEN: usize = 0xFFF;
+/// Words before a definition's pairs
+const DEF_HEADER_LEN: usize = 1 + NAME_LEN / 4 + 5;
+
+fn invalid<T>(msg: impl Into<String>) -> Result<T> {
+    Err(CoreError::InvalidBuffer(msg.into()))
+}
+
+impl Book {
+    /// Serializes the book: header, then the `book_load` payload
+    pub fn to_buffer(&self) -> Result<Vec<u32>> {
+        let mut buf = vec![MAGIC, VERSION, 0, self.len() as u32];
+        for (fid, def) in self.iter() {
+            let tmpl = self.template(fid).unwrap();
+            let name = def.name.as_bytes();
+            if name.len() >= NAME_LEN {
+                return invalid(format!("name of @{} is longer than {} bytes", def.name, NAME_LEN - 1));
+            }
+            if tmpl.rbag.len() > DEF_LEN || tmpl.node.len() > DEF_LEN {
+                return invalid(format!("@{} has more than {} redexes or nodes", def.name, DEF_LEN));
+            }
+
+            buf.push(fid);
+            let mut bytes = [0u8; NAME_LEN];
+            bytes[..name.len()].copy_from_slice(name);
+            buf.extend(bytes.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])));
+            buf.push(tmpl.safe as u32);
+            buf.push(tmpl.rbag.len() as u32);
+            buf.push(tmpl.node.len() as u32);
+            buf.push(tmpl.vars as u32);
+            buf.push(u32::from(tmpl.root));
+            for pair in tmpl.rbag.iter().chain(&tmpl.node) {
+                buf.push(u32::from(pair.fst()));
+                buf.push(u32::from(pair.snd()));
+            }
+        }
+        buf[2] = (buf.len() - HEADER_LEN) as u32;
+        Ok(buf)
+    }
+
+    /// Serializes the book as little-endian bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.to_buffer()?.iter().flat_map(|w| w.to_le_bytes()).collect())
+    }
+
+    /// Loads a book from a word buffer
+    pub fn from_buffer(buf: &[u32]) -> Result<Book> {
+        BookView::new(Words::U32(buf))?.to_book()
+    }
+
+    /// Loads a book from little-endian bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Book> {
+        BookView::from_bytes(bytes)?.to_book()
+    }
+}
+
+/// Word access over either a `u32` or a little-endian byte buffer
+#[derive(Debug, Clone, Copy)]
+enum Words<'a> {
+    U32(&'a [u32]),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Words<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Words::U32(buf) => buf.len(),
+            Words::Bytes(buf) => buf.len() / 4,
+        }
+    }
+
+    fn get(&self, idx: usize) -> u32 {
+        match self {
+            Words::U32(buf) => buf[idx],
+            Words::Bytes(buf) => {
+                let w = &buf[idx * 4..idx * 4 + 4];
+                u32::from_le_bytes([w[0], w[1], w[2], w[3]])
+            }
+        }
+    }
+}
+
+/// A validated binary book, read in place
+#[derive(Debug, Clone)]
+pub struct BookView<'a> {
+    words: Words<'a>,
+    defs: Vec<usize>, // word offset of each def, by fid
+}
+
+/// One definition of a `BookView`
+#[derive(Debug, Clone, Copy)]
+pub struct DefView<'a> {
+    words: Words<'a>,
+    at: usize,
+}
+
+impl<'a> BookView<'a> {
+    /// Validates a byte buffer, such as a memory-mapped file, without copying it
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        if !bytes.len().is_multiple_of(4) {
+            return invalid("length is not a multiple of 4 bytes");
+        }
+        Self::new(Words::Bytes(bytes))
+    }
+
+    fn new(words: Words<'a>) -> Result<Self> {
+        if words.len() < HEADER_LEN + 1 {
+            return invalid("too short for a header");
+        }
+        if words.get(0) != MAGIC {
+            return invalid("bad magic number");
+        }
+        if words.get(1) != VERSION {
+            return invalid(format!("unsupported version {}", words.get(1)));
+        }
+        if words.get(2) as usize != words.len() - HEADER_LEN {
+            return invalid(format!("payload is {} words, header says {}", words.len() - HEADER_LEN, words.get(2)));
+        }
+
+        let len = words.get(HEADER_LEN) as usize;
+        let mut defs = vec![usize::MAX; len];
+        let mut at = HEADER_LEN + 1;
+        for _ in 0..len {
+            if at + DEF_HEADER_LEN > words.len() {
+                return invalid("truncated definition");
+            }
+            let def = DefView { words, at };
+            let fid = def.fid() as usize;
+            if fid >= len || defs[fid] != usize::MAX {
+                return invalid(format!("bad or repeated fid {}", fid));
+            }
+            if def.rbag_len() > DEF_LEN || def.node_len() > DEF_LEN {
+                return invalid(format!("definition {} is too large", fid));
+            }
+            defs[fid] = at;
+            at += DEF_HEADER_LEN + 2 * (def.rbag_len() + def.node_len());
+            if at > words.len() {
+                return invalid("truncated definition");
+            }
+        }
+        if at != words.len() {
+            return invalid("trailing words after the last definition");
+        }
+        Ok(Self { words, defs })
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    /// Gets a definition by fid
+    pub fn get(&self, fid: Val) -> Option<DefView<'a>> {
+        let at = *self.defs.get(fid as usize)?;
+        Some(DefView { words: self.words, at })
+    }
+
+    /// Iterates definitions in fid order
+    pub fn iter(&self) -> impl Iterator<Item = DefView<'a>> + '_ {
+        self.defs.iter().map(|&at| DefView { words: self.words, at })
+    }
+
+    /// Copies the definitions into an owned `Book`
+    pub fn to_book(&self) -> Result<Book> {
+        let mut book = Book::new();
+        for def in self.iter() {
+            let tmpl = def.tmpl()?;
+            let net = tmpl.to_net();
+            let arity = tmpl.arity();
+            let name = def.name()?;
+            if book.id(&name).is_some() {
+                return invalid(format!("duplicate definition @{}", name));
+            }
+            book.insert(name.clone(), Def { name, arity, net });
+        }
+        book.propagate_safety();
+        Ok(book)
+    }
+}
+
+impl<'a> DefView<'a> {
+    fn word(&self, idx: usize) -> u32 {
+        self.words.get(self.at + idx)
+    }
+
+    pub fn fid(&self) -> Val {
+        self.word(0)
+    }
+
+    /// Name, up to the first NUL
+    pub fn name(&self) -> Result<String> {
+        let raw: Vec<u8> = (0..NAME_LEN / 4).flat_map(|i| self.word(1 + i).to_le_bytes()).collect();
+        let end = raw.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        match std::str::from_utf8(&raw[..end]) {
+            Ok(name) => Ok(name.to_string()),
+            Err(_) => invalid(format!("name of definition {} is not UTF-8", self.fid())),
+        }
+    }
+
+    pub fn safe(&self) -> bool {
+        self.word(1 + NAME_LEN / 4) != 0
+    }
+
+    pub fn rbag_len(&self) -> usize {
+        self.word(2 + NAME_LEN / 4) as usize
+    }
+
+    pub fn node_len(&self) -> usize {
+        self.word(3 + NAME_LEN / 4) as usize
+    }
+
+    pub fn vars_len(&self) -> usize {
+        self.word(4 + NAME_LEN / 4) as usize
+    }
+
+    pub fn root(&self) -> Port {
+        Port::from(self.word(5 + NAME_LEN / 4))
+    }
+
+    fn pair(&self, idx: usize) -> Pair {
+        let at = DEF_HEADER_LEN + 2 * idx;
+        Pair::new(Port::from(self.word(at)), Port::from(self.word(at + 1)))
+    }
+
+    /// Redexes, with local node and variable numbers
+    pub fn rbag(&self) -> impl Iterator<Item = Pair> + '_ {
+        (0..self.rbag_len()).map(|i| self.pair(i))
+    }
+
+    /// Nodes, with local node and variable numbers
+    pub fn nodes(&self) -> impl Iterator<Item = Pair> + '_ {
+        (self.rbag_len()..self.rbag_len() + self.node_len()).map(|i| self.pair(i))
+    }
+
+    /// Copies the definition out, checking local numbers are in range
+    fn tmpl(&self) -> Result<Template> {
+        let (node_len, vars_len) = (self.node_len(), self.vars_len());
+        let check = |port: Port| {
+            let ok = if port == Port::NONE {
+                false
+            } else if port.is_nod() {
+                (port.val() as usize) < node_len
+            } else if port.is_var() {
+                (port.val() as usize) < vars_len
+            } else {
+                true
+            };
+            if ok {
+                Ok(port)
+            } else {
+                invalid(format!("port {:#010x} of definition {} is out of range", u32::from(port), self.fid()))
+            }
+        };
+        let check_pair = |pair: Pair| Ok(Pair::new(check(pair.fst())?, check(pair.snd())?));
+        Ok(Template {
+            safe: self.safe(),
+            root: check(self.root())?,
+            rbag: self.rbag().map(check_pair).collect::<Result<_>>()?,
+            node: self.nodes().map(check_pair).collect::<Result<_>>()?,
+            vars: vars_len,
+        })
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, GNet, Tag};
+
+    const SRC: &str = "@main = r & @sum ~ (10 r)\n@sum = (?((0 @sum.s) r) r)\n\
+                       @sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & [+1] ~ $(p1 q)";
+
+    #[test]
+    fn test_buffer_layout() {
+        let book = Book::parse("@main = (x x)").unwrap();
+        let buf = book.to_buffer().unwrap();
+        assert_eq!(&buf[..HEADER_LEN], &[MAGIC, VERSION, (buf.len() - HEADER_LEN) as u32]);
+
+        let payload = &buf[HEADER_LEN..];
+        assert_eq!(payload[0], 1); // defs_len
+        assert_eq!(payload[1], 0); // fid
+        assert_eq!(payload[2], u32::from_le_bytes(*b"main"));
+        assert!(payload[3..2 + 64].iter().all(|&w| w == 0));
+        let rest = &payload[2 + 64..];
+        // safe, rbag_len, node_len, vars_len, root, node 0 = (x0 x0)
+        let root = u32::from(Port::new(Tag::Con, 0));
+        let x0 = u32::from(Port::new(Tag::Var, 0));
+        assert_eq!(rest, &[1, 0, 1, 1, root, x0, x0]);
+    }
+
+    #[test]
+    fn test_buffer_roundtrip() {
+        let book = Book::parse(SRC).unwrap();
+        let buf = book.to_buffer().unwrap();
+        assert_eq!(Book::from_buffer(&buf).unwrap(), book);
+        assert_eq!(Book::from_bytes(&book.to_bytes().unwrap()).unwrap(), book);
+    }
+
+    #[test]
+    fn test_buffer_corpus_evaluates() {
+        for (name, book, expected) in corpus::all() {
+            let loaded = Book::from_bytes(&book.to_bytes().unwrap()).unwrap();
+            let mut net = GNet::new();
+            net.normalize(&loaded).unwrap();
+            assert_eq!(net.root(), corpus::num(expected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_book_view() {
+        let book = Book::parse(SRC).unwrap();
+        let bytes = book.to_bytes().unwrap();
+        let view = BookView::from_bytes(&bytes).unwrap();
+        assert_eq!(view.len(), 3);
+        let def = view.get(2).unwrap();
+        assert_eq!(def.name().unwrap(), "sum.s");
+        assert!(!def.safe());
+        assert_eq!((def.rbag_len(), def.node_len(), def.vars_len()), (2, 6, 4));
+        assert_eq!(def.root(), Port::new(Tag::Con, 0));
+        assert_eq!(def.rbag().next().unwrap().fst(), Port::new(Tag::Ref, 1));
+        assert_eq!(view.iter().map(|d| d.fid()).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_buffer_errors() {
+        let buf = Book::parse(SRC).unwrap().to_buffer().unwrap();
+        let error = |buf: &[u32]| match Book::from_buffer(buf) {
+            Err(CoreError::InvalidBuffer(msg)) => msg,
+            other => panic!("expected an invalid buffer, got {:?}", other),
+        };
+
+        assert!(error(&buf[..2]).contains("too short"));
+        let mut bad = buf.clone();
+        bad[0] = 0;
+        assert!(error(&bad).contains("magic"));
+        bad = buf.clone();
+        bad[1] = VERSION + 1;
+        assert!(error(&bad).contains("version"));
+        assert!(error(&buf[..buf.len() - 1]).contains("payload"));
+        bad = buf.clone();
+        bad[HEADER_LEN + 1] = 7;
+        assert!(error(&bad).contains("fid"));
+        bad = buf.clone();
+        let root = HEADER_LEN + DEF_HEADER_LEN;
+        bad[root] = u32::from(Port::new(Tag::Con, 99));
+        assert!(error(&bad).contains("out of range"));
+        assert!(matches!(BookView::from_bytes(&[0; 7]), Err(CoreError::InvalidBuffer(_))));
+    }
+
+    #[test]
+    fn test_buffer_name_too_long() {
+        let name = "f".repeat(NAME_LEN);
+        let book = Book::parse(&format!("@{} = *", name)).unwrap();
+        assert!(matches!(book.to_buffer(), Err(CoreError::InvalidBuffer(_))));
+    }
+}
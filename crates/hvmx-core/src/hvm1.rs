@@ -0,0 +1,525 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: hvm1.rs
+// Location: crates/hvmx-core/src/hvm1.rs
+// Purpose: HVM1 rule syntax, compiled to terms through match trees
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! HVM1 rules, after `read_rule` and `read_statement` in `docs/dor/hvm.rs`.
+//!
+//! ```text
+//! file ::= (rule | "fun" "(" Name name* ")" "{" rule* "}"
+//!              | "ctr" "{" Name name* "}" | "run" "{" term "}")*
+//! rule ::= "(" Name pat* ")" "=" term
+//! pat  ::= name | "*" | numb | "(" Name pat* ")" | "{" Name pat* "}" | Name
+//! ```
+//!
+//! Right-hand sides are `term` terms. `(Name ..)` and a bare `Name` call a
+//! function when it has rules and build a constructor otherwise; `run`
+//! defines `Main`.
+//!
+//! Each function becomes lambdas over its arguments, then a match tree.
+//! A constructor column applies the Scott-encoded value to one case per
+//! constructor, a number column becomes a chain of switches, and the
+//! variables not matched yet are passed to each case as extra arguments.
+//! Cases are then closed, so those that call functions float into their
+//! own definitions and are only expanded when taken. Missing cases are `*`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+
+use crate::parse::Parser;
+use crate::term::{add_constructor, Program, Term};
+use crate::{CoreError, Numb, Result};
+
+/// Constructors and their arity, by name
+pub type Ctrs = BTreeMap<String, usize>;
+
+/// Rewrite rule, `lhs = rhs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub lhs: Term,
+    pub rhs: Term,
+}
+
+/// HVM1 source: its rules and declared constructors
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleBook {
+    pub rules: Vec<Rule>,
+    /// Constructors from `ctr` statements, with their arity
+    pub ctrs: Vec<(String, usize)>,
+}
+
+/// Argument pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Pat {
+    /// A variable, `None` for `*` and `_`
+    Var(Option<String>),
+    Ctr(String, Vec<Pat>),
+    Num(u32),
+}
+
+impl RuleBook {
+    /// Parses HVM1 source
+    pub fn parse(src: &str) -> Result<RuleBook> {
+        Parser::new(src).rule_book()
+    }
+
+    /// Compiles the rules into a term program
+    pub fn compile(&self) -> Result<Program> {
+        let (defs, ctrs) = self.to_defs()?;
+        Program::compile_with(&defs, ctrs)
+    }
+
+    /// Turns each function's rules into one term definition, returning them
+    /// with every constructor of the program
+    pub fn to_defs(&self) -> Result<(Vec<(String, Term)>, Ctrs)> {
+        // Functions, with their arity and rules, in order of appearance
+        let mut funs: Vec<(String, usize, Vec<&Rule>)> = Vec::new();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for rule in &self.rules {
+            let Term::Fun { name, args } = &rule.lhs else {
+                return Err(CoreError::Compile(format!("'{}' is not a rule head", rule.lhs)));
+            };
+            let i = *index.entry(name).or_insert_with(|| {
+                funs.push((name.clone(), args.len(), Vec::new()));
+                funs.len() - 1
+            });
+            if funs[i].1 != args.len() {
+                return Err(CoreError::Compile(format!(
+                    "function ({}) has rules with {} and {} arguments",
+                    name,
+                    funs[i].1,
+                    args.len()
+                )));
+            }
+            funs[i].2.push(rule);
+        }
+        let is_fun = |name: &str| index.contains_key(name);
+
+        let mut ctrs = BTreeMap::new();
+        for (name, arity) in &self.ctrs {
+            add_constructor(&mut ctrs, name, *arity)?;
+        }
+        let mut compiled = Vec::new();
+        for (name, arity, rules) in &funs {
+            let mut rows = Vec::new();
+            for rule in rules {
+                let Term::Fun { args, .. } = &rule.lhs else { unreachable!() };
+                let pats = args
+                    .iter()
+                    .map(|arg| pattern(resolve(arg.clone(), &is_fun), &mut ctrs))
+                    .collect::<Result<Vec<_>>>()?;
+                let body = resolve(rule.rhs.clone(), &is_fun);
+                body.constructors(&mut ctrs)?;
+                rows.push(Row { pats, body });
+            }
+            compiled.push((name.clone(), *arity, rows));
+        }
+
+        let ctr_list: Vec<(String, usize)> = ctrs.iter().map(|(name, arity)| (name.clone(), *arity)).collect();
+        let mut defs = Vec::new();
+        for (name, arity, rows) in compiled {
+            let mut matcher = Matcher {
+                ctrs: &ctr_list,
+                next: 0,
+            };
+            let vars: Vec<String> = (0..arity).map(|_| matcher.fresh()).collect();
+            let body = matcher.rows(vars.clone(), rows)?;
+            let term = vars.iter().rev().fold(body, |body, var| Term::lam(var, body));
+            defs.push((name, term));
+        }
+        Ok((defs, ctrs))
+    }
+}
+
+impl Program {
+    /// Parses and compiles HVM1 source
+    pub fn parse_hvm1(src: &str) -> Result<Program> {
+        RuleBook::parse(src)?.compile()
+    }
+}
+
+/// Turns `(Name ..)` into a constructor and a bare `Name` into a call or
+/// constructor, unless `Name` has rules
+fn resolve(mut term: Term, is_fun: &impl Fn(&str) -> bool) -> Term {
+    match &mut term {
+        Term::Fun { name, args } if !is_fun(name) => {
+            let (name, args) = (mem::take(name), mem::take(args));
+            term = Term::Ctr { name, args };
+        }
+        Term::Var { name } if name.starts_with(|c: char| c.is_ascii_uppercase()) => {
+            let (name, args) = (mem::take(name), Vec::new());
+            term = if is_fun(&name) {
+                Term::Fun { name, args }
+            } else {
+                Term::Ctr { name, args }
+            };
+        }
+        _ => {}
+    }
+    term.map(|child| resolve(child, is_fun))
+}
+
+/// Reads a rule argument as a pattern
+fn pattern(mut term: Term, ctrs: &mut Ctrs) -> Result<Pat> {
+    match &mut term {
+        Term::Era => Ok(Pat::Var(None)),
+        Term::Var { name } if name == "_" => Ok(Pat::Var(None)),
+        Term::Var { name } => Ok(Pat::Var(Some(mem::take(name)))),
+        Term::Ctr { name, args } => {
+            add_constructor(ctrs, name, args.len())?;
+            let args = mem::take(args).into_iter().map(|arg| pattern(arg, ctrs)).collect::<Result<_>>()?;
+            Ok(Pat::Ctr(mem::take(name), args))
+        }
+        Term::Num { numb } if numb.typ() == Numb::TY_U24 => Ok(Pat::Num(numb.u24())),
+        _ => Err(CoreError::Compile(format!("'{}' is not a pattern", term))),
+    }
+}
+
+/// Replaces free occurrences of `name` with `val`, which must not mention
+/// any name bound in `term`
+fn subst(mut term: Term, name: &str, val: &Term) -> Term {
+    match &mut term {
+        Term::Var { name: nam } if nam == name => val.clone(),
+        Term::Lam { name: nam, .. } if nam == name => term,
+        Term::Dup { nam0, nam1, expr, .. } if nam0 == name || nam1 == name => {
+            **expr = subst(mem::replace(&mut **expr, Term::Era), name, val);
+            term
+        }
+        _ => term.map(|child| subst(child, name, val)),
+    }
+}
+
+/// A rule in the middle of matching: the patterns left, one per variable
+#[derive(Debug, Clone)]
+struct Row {
+    pats: Vec<Pat>,
+    body: Term,
+}
+
+/// Builds match trees
+struct Matcher<'a> {
+    ctrs: &'a [(String, usize)],
+    next: usize,
+}
+
+impl Matcher<'_> {
+    /// Not a valid source name, so it can't capture anything
+    fn fresh(&mut self) -> String {
+        self.next += 1;
+        format!("%v{}", self.next - 1)
+    }
+
+    /// Matches `vars` against the rows, first row first
+    fn rows(&mut self, vars: Vec<String>, rows: Vec<Row>) -> Result<Term> {
+        let Some(first) = rows.first() else {
+            return Ok(Term::Era);
+        };
+        let Some(col) = first.pats.iter().position(|pat| !matches!(pat, Pat::Var(_))) else {
+            // Only variables left: the first row applies
+            let row = rows.into_iter().next().unwrap();
+            return Ok(bind(row, &vars));
+        };
+        let numeric = matches!(first.pats[col], Pat::Num(_));
+
+        // Move the column to the front and name its variables after it
+        let scrut = vars[col].clone();
+        let mut others = vars;
+        others.remove(col);
+        let mut column = Vec::new();
+        for mut row in rows {
+            let pat = row.pats.remove(col);
+            let pat = match pat {
+                Pat::Var(Some(name)) => {
+                    row.body = subst(row.body, &name, &Term::var(&scrut));
+                    Pat::Var(None)
+                }
+                Pat::Num(_) if !numeric => return Err(CoreError::Compile("mixed number and constructor patterns".to_string())),
+                Pat::Ctr(..) if numeric => return Err(CoreError::Compile("mixed number and constructor patterns".to_string())),
+                pat => pat,
+            };
+            column.push((pat, row));
+        }
+
+        let head = if numeric {
+            self.switch(&scrut, &others, column)?
+        } else {
+            self.cases(&scrut, &others, column)?
+        };
+        Ok(others.iter().fold(head, |term, var| Term::app(term, Term::var(var))))
+    }
+
+    /// `(scrut case_0 .. case_N-1)`, each case taking the constructor's
+    /// fields and then `others`
+    fn cases(&mut self, scrut: &str, others: &[String], column: Vec<(Pat, Row)>) -> Result<Term> {
+        let mut term = Term::var(scrut);
+        for (ctr, arity) in self.ctrs {
+            let fields: Vec<String> = (0..*arity).map(|_| self.fresh()).collect();
+            let val = Term::Ctr {
+                name: ctr.clone(),
+                args: fields.iter().map(|field| Term::var(field)).collect(),
+            };
+            let mut rows = Vec::new();
+            for (pat, row) in &column {
+                let head = match pat {
+                    Pat::Ctr(name, args) if name == ctr => args.clone(),
+                    Pat::Ctr(..) => continue,
+                    _ => vec![Pat::Var(None); *arity],
+                };
+                rows.push(Row {
+                    pats: head.into_iter().chain(row.pats.iter().cloned()).collect(),
+                    body: subst(row.body.clone(), scrut, &val),
+                });
+            }
+            let vars: Vec<String> = fields.iter().chain(others).cloned().collect();
+            let body = self.rows(vars.clone(), rows)?;
+            term = Term::app(term, vars.iter().rev().fold(body, |body, var| Term::lam(var, body)));
+        }
+        Ok(term)
+    }
+
+    /// `(? scrut zero succ)`, literals going down by one in `succ`
+    fn switch(&mut self, scrut: &str, others: &[String], column: Vec<(Pat, Row)>) -> Result<Term> {
+        let lams = |vars: &[String], body: Term| vars.iter().rev().fold(body, |body, var| Term::lam(var, body));
+
+        let zero_val = Term::Num { numb: Numb::new_u24(0) };
+        let mut rows = Vec::new();
+        for (pat, row) in &column {
+            if matches!(pat, Pat::Num(0) | Pat::Var(_)) {
+                rows.push(Row {
+                    pats: row.pats.clone(),
+                    body: subst(row.body.clone(), scrut, &zero_val),
+                });
+            }
+        }
+        let zero = lams(others, self.rows(others.to_vec(), rows)?);
+
+        let pred = self.fresh();
+        let succ_val = Term::Op2 {
+            oper: Numb::OP_ADD,
+            val0: Box::new(Term::var(&pred)),
+            val1: Box::new(Term::Num { numb: Numb::new_u24(1) }),
+        };
+        let mut rows = Vec::new();
+        for (pat, row) in &column {
+            let head = match pat {
+                Pat::Num(0) => continue,
+                Pat::Num(n) => Pat::Num(n - 1),
+                _ => Pat::Var(None),
+            };
+            rows.push(Row {
+                pats: [head].into_iter().chain(row.pats.iter().cloned()).collect(),
+                body: subst(row.body.clone(), scrut, &succ_val),
+            });
+        }
+        let vars: Vec<String> = [pred].into_iter().chain(others.iter().cloned()).collect();
+        let succ = lams(&vars, self.rows(vars.clone(), rows)?);
+
+        Ok(Term::Swi {
+            expr: Box::new(Term::var(scrut)),
+            zero: Box::new(zero),
+            succ: Box::new(succ),
+        })
+    }
+}
+
+/// A row whose patterns are all variables, with them bound to `vars`
+fn bind(row: Row, vars: &[String]) -> Term {
+    let mut body = row.body;
+    for (pat, var) in row.pats.into_iter().zip(vars) {
+        if let Pat::Var(Some(name)) = pat {
+            body = subst(body, &name, &Term::var(var));
+        }
+    }
+    body
+}
+
+// ==============================================================================
+// PARSER
+// ==============================================================================
+
+impl<'a> Parser<'a> {
+    /// Parses HVM1 source: rules and `fun`, `ctr` and `run` statements
+    pub fn rule_book(&mut self) -> Result<RuleBook> {
+        let mut book = RuleBook::default();
+        loop {
+            self.skip_trivia();
+            match self.peek() {
+                None => return Ok(book),
+                Some('(') => book.rules.push(self.rule()?),
+                _ => {
+                    let (line, col) = self.location();
+                    match self.name()?.as_str() {
+                        "fun" => {
+                            self.consume("(")?;
+                            self.name()?;
+                            while !self.try_consume(")") {
+                                self.name()?;
+                            }
+                            self.consume("{")?;
+                            while !self.try_consume("}") {
+                                book.rules.push(self.rule()?);
+                            }
+                        }
+                        "ctr" => {
+                            self.consume("{")?;
+                            let name = self.name()?;
+                            let mut arity = 0;
+                            while !self.try_consume("}") {
+                                self.name()?;
+                                arity += 1;
+                            }
+                            book.ctrs.push((name, arity));
+                        }
+                        "run" => {
+                            self.consume("{")?;
+                            let rhs = self.term()?;
+                            self.consume("}")?;
+                            let lhs = Term::Fun {
+                                name: "Main".to_string(),
+                                args: Vec::new(),
+                            };
+                            book.rules.push(Rule { lhs, rhs });
+                        }
+                        other => {
+                            return Err(CoreError::Parse {
+                                line,
+                                col,
+                                msg: format!("expected a rule or statement, found '{}'", other),
+                            })
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses `(Name pat*) = term`
+    fn rule(&mut self) -> Result<Rule> {
+        self.skip_trivia();
+        let (line, col) = self.location();
+        let lhs = self.term()?;
+        if !matches!(&lhs, Term::Fun { .. }) {
+            return Err(CoreError::Parse {
+                line,
+                col,
+                msg: format!("expected a rule head, found '{}'", lhs),
+            });
+        }
+        self.consume("=")?;
+        let rhs = self.term()?;
+        Ok(Rule { lhs, rhs })
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GNet;
+
+    const LISTS: &str = "
+        (Len Nil) = 0
+        (Len (Cons x xs)) = (+ 1 (Len xs))
+        (Sum Nil) = 0
+        (Sum (Cons x xs)) = (+ x (Sum xs))
+        (Range 0) = Nil
+        (Range n) = (Cons n (Range (- n 1)))
+        (Zip (Cons x xs) (Cons y ys)) = (Cons (Pair x y) (Zip xs ys))
+        (Zip xs ys) = Nil
+    ";
+
+    fn eval(src: &str) -> String {
+        let program = Program::parse_hvm1(src).unwrap();
+        let mut net = GNet::new();
+        net.normalize(&program.book).unwrap();
+        program.readback(&mut net).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_hvm1_parse() {
+        let book = RuleBook::parse("ctr {Succ p}\nfun (Inc x) { (Inc x) = {Succ x} }\nrun { (Inc 1) }").unwrap();
+        assert_eq!(book.ctrs, vec![("Succ".to_string(), 1)]);
+        let rules: Vec<String> = book.rules.iter().map(|r| format!("{} = {}", r.lhs, r.rhs)).collect();
+        assert_eq!(rules, vec!["(Inc x) = {Succ x}", "(Main) = (Inc 1)"]);
+
+        let error = |src: &str| match RuleBook::parse(src) {
+            Err(CoreError::Parse { line, col, msg }) => (line, col, msg),
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(error("(F x) = x\nfoo"), (2, 1, "expected a rule or statement, found 'foo'".to_string()));
+        assert_eq!(error("x = 1"), (1, 1, "expected a rule or statement, found 'x'".to_string()));
+        assert_eq!(error("(f x) = 1"), (1, 1, "expected a rule head, found '(f x)'".to_string()));
+    }
+
+    #[test]
+    fn test_hvm1_constructors() {
+        let main = |body: &str| eval(&format!("{}(Main) = {}", LISTS, body));
+        assert_eq!(main("(Len (Range 4))"), "4");
+        assert_eq!(main("(Sum (Range 10))"), "55");
+        assert_eq!(main("(Range 2)"), "{Cons 2 {Cons 1 {Nil}}}");
+        assert_eq!(
+            main("(Zip (Range 2) (Cons 7 Nil))"),
+            "{Cons {Pair 2 7} {Nil}}"
+        );
+    }
+
+    #[test]
+    fn test_hvm1_numbers() {
+        let fib = "(Fib 0) = 1\n(Fib 1) = 1\n(Fib n) = (+ (Fib (- n 1)) (Fib (- n 2)))\n";
+        assert_eq!(eval(&format!("{}(Main) = (Fib 10)", fib)), "89");
+        // A variable rule before a literal one wins
+        assert_eq!(eval("(F n) = n\n(F 0) = 9\n(Main) = (F 0)"), "0");
+        assert_eq!(eval("(F 2 b) = b\n(F a b) = a\n(Main) = (+ (F 2 5) (F 3 5))"), "8");
+    }
+
+    #[test]
+    fn test_hvm1_kindelia_syntax() {
+        let src = "
+            ctr {Succ p}
+            ctr {Zero}
+            fun (Add a b) {
+              (Add {Zero} b) = b
+              (Add {Succ a} b) = {Succ (Add a b)}
+            }
+            run { (Add {Succ {Zero}} {Succ {Zero}}) }
+        ";
+        assert_eq!(eval(src), "{Succ {Succ {Zero}}}");
+    }
+
+    #[test]
+    fn test_hvm1_lambdas_and_missing_cases() {
+        let src = "(Map f Nil) = Nil\n(Map f (Cons x xs)) = (Cons (f x) (Map f xs))\n\
+                   (Head (Cons x xs)) = x\n\
+                   (Main) = (Head (Map @x (* x 2) (Cons 5 Nil)))";
+        assert_eq!(eval(src), "10");
+        // No rule for `Nil`: the case is erased
+        assert_eq!(eval("(Head (Cons x xs)) = x\n(Main) = (Head Nil)"), "*");
+    }
+
+    #[test]
+    fn test_hvm1_errors() {
+        let error = |src: &str| RuleBook::parse(src).unwrap().compile().unwrap_err();
+        assert_eq!(
+            error("(F x) = x\n(F x y) = x"),
+            CoreError::Compile("function (F) has rules with 1 and 2 arguments".to_string())
+        );
+        assert_eq!(
+            error("(F (Cons x)) = x\n(G (Cons x y)) = x"),
+            CoreError::Compile("constructor {Cons} is used with 1 and 2 fields".to_string())
+        );
+        assert_eq!(
+            error("(F 0) = 1\n(F Nil) = 2"),
+            CoreError::Compile("mixed number and constructor patterns".to_string())
+        );
+        assert_eq!(error("(F (+ 1 2)) = 1"), CoreError::Compile("'(+ 1 2)' is not a pattern".to_string()));
+    }
+}
@@ -9,93 +9,231 @@
 // License: MIT OR Apache-2.0
 // ==============================================================================
 
-use crate::{Port, GNet, Tag};
+use crate::{Book, CoreError, GNet, Numb, Pair, Port, Result, Tag, Val};
 
 /// Interaction rules between ports
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Rule {
-    Link,    // VAR-VAR: just link
-    Anni,    // CON-CON same label: annihilation
-    Comm,    // CON-CON diff label: commutation
-    Eras,    // ERA-anything: erasure
-    Deref,   // REF-anything: dereference
-    Call,    // APP-LAM: beta reduction
-    Copy,    // DUP-anything: duplication
-    Oper,    // OP2-NUM: numeric operation
+    Link = 0, // VAR-anything: substitution
+    Call = 1, // REF-node: expand definition
+    Void = 2, // leaf-leaf: nothing to do
+    Eras = 3, // leaf-node: erase/copy leaf into aux ports
+    Anni = 4, // node-node same tag: annihilation
+    Comm = 5, // node-node diff tag: commutation
+    Oper = 6, // NUM-OPR: numeric operation
+    Swit = 7, // NUM-SWI: numeric switch
 }
 
+/// Rule table, indexed by tag pair (same as `get_rule` in `hvm.c`)
+const RULES: [[Rule; 8]; 8] = {
+    use Rule::*;
+    [
+        //VAR   REF   ERA   NUM   CON   DUP   OPR   SWI
+        [Link, Link, Link, Link, Link, Link, Link, Link], // VAR
+        [Link, Void, Void, Void, Call, Call, Call, Call], // REF
+        [Link, Void, Void, Void, Eras, Eras, Eras, Eras], // ERA
+        [Link, Void, Void, Void, Eras, Eras, Oper, Swit], // NUM
+        [Link, Call, Eras, Eras, Anni, Comm, Comm, Comm], // CON
+        [Link, Call, Eras, Eras, Comm, Anni, Comm, Comm], // DUP
+        [Link, Call, Eras, Oper, Comm, Comm, Anni, Comm], // OPR
+        [Link, Call, Eras, Swit, Comm, Comm, Comm, Anni], // SWI
+    ]
+};
+
 /// Get interaction rule for pair of ports
 pub fn get_rule(a: Port, b: Port) -> Rule {
-    use Tag::*;
-    
-    match (a.tag(), b.tag()) {
-        (Var, Var) => Rule::Link,
-        (Var, _) | (_, Var) => Rule::Link,
-        (Ref, _) | (_, Ref) => Rule::Deref,
-        (Num, Num) => Rule::Oper,
-        _ => Rule::Link, // Simplified
-    }
+    RULES[a.tag() as usize][b.tag() as usize]
+}
+
+/// Should ports be swapped before reducing, so that `a` has the lower tag?
+pub fn should_swap(a: Port, b: Port) -> bool {
+    b.tag() < a.tag()
 }
 
-/// Execute interaction between two ports
-pub fn interact(net: &mut GNet, a: Port, b: Port) -> Result<(), String> {
-    let rule = get_rule(a, b);
-    
+/// Execute interaction between two ports, returning the rule applied.
+/// Fails with `BagOverflow` before doing anything if the redex bag might
+/// not hold the redexes the interaction creates.
+pub fn interact(net: &mut GNet, book: &Book, a: Port, b: Port) -> Result<Rule> {
+    interact_holding(net, book, a, b, (0, 0))
+}
+
+/// `interact`, counting `held` (high, low) more redexes kept outside the bag
+pub(crate) fn interact_holding(
+    net: &mut GNet,
+    book: &Book,
+    mut a: Port,
+    mut b: Port,
+    held: (usize, usize),
+) -> Result<Rule> {
+    let mut rule = get_rule(a, b);
+
+    // Used for the root redex
+    if a.tag() == Tag::Ref && b == Port::ROOT {
+        rule = Rule::Call;
+    } else if should_swap(a, b) {
+        std::mem::swap(&mut a, &mut b);
+    }
+
+    net.redexes.reserve_with(held.0, held.1, max_redexes(book, rule, a, b))?;
     match rule {
         Rule::Link => interact_link(net, a, b),
+        Rule::Call => interact_call(net, book, a, b),
+        Rule::Void => interact_void(net, a, b),
+        Rule::Eras => interact_eras(net, a, b),
         Rule::Anni => interact_anni(net, a, b),
         Rule::Comm => interact_comm(net, a, b),
-        Rule::Eras => interact_eras(net, a, b),
-        Rule::Deref => interact_deref(net, a, b),
-        Rule::Call => interact_call(net, a, b),
-        Rule::Copy => interact_copy(net, a, b),
         Rule::Oper => interact_oper(net, a, b),
+        Rule::Swit => interact_swit(net, a, b),
+    }?;
+
+    Ok(rule)
+}
+
+/// Most redexes an interaction can push, as `get_resources` asks for in
+/// `hvm.c`: for a CALL, the template's redexes and the link of its root
+fn max_redexes(book: &Book, rule: Rule, a: Port, b: Port) -> usize {
+    match rule {
+        Rule::Void => 0,
+        Rule::Link | Rule::Oper | Rule::Swit => 1,
+        Rule::Eras | Rule::Anni => 2,
+        Rule::Comm => 4,
+        // An undefined REF fails without pushing anything
+        Rule::Call => match book.template(a.val()) {
+            Some(tmpl) if tmpl.safe && b.tag() == Tag::Dup => 2,
+            Some(tmpl) => tmpl.rbag.len() + 1,
+            None => 0,
+        },
     }
 }
 
+/// Is this rule cheap enough to run before the others?
+pub fn is_high_priority(rule: Rule) -> bool {
+    (0b0001_1101 >> rule as u8) & 1 == 1
+}
+
+/// Applies a numeric operation to two NUM port values
+pub(crate) fn operate(a: Val, b: Val) -> Val {
+    Numb::operate(Numb::new(a), Numb::new(b)).0
+}
+
 // Individual interaction implementations
 
-fn interact_link(_net: &mut GNet, a: Port, b: Port) -> Result<(), String> {
-    // VAR-VAR: create link
-    // TODO: Implement linking logic
+/// Takes the node a port points to
+fn take_node(net: &mut GNet, port: Port) -> Result<(Port, Port)> {
+    let node = net.node_take(port.val());
+    if node == Pair::FREE {
+        return Err(CoreError::FreeNode(port));
+    }
+    Ok((node.fst(), node.snd()))
+}
+
+/// Allocates a variable that has not been linked yet
+fn new_var(net: &mut GNet) -> Result<Port> {
+    let var = net.vars_alloc()?;
+    net.vars_create(var, Port::NONE);
+    Ok(Port::new(Tag::Var, var))
+}
+
+/// Allocates a node with the given aux ports
+fn new_node(net: &mut GNet, tag: Tag, fst: Port, snd: Port) -> Result<Port> {
+    let loc = net.node_alloc()?;
+    net.node_create(loc, Pair::new(fst, snd));
+    Ok(Port::new(tag, loc))
+}
+
+fn interact_link(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // VAR-anything: substitute
+    net.link(a, b);
     Ok(())
 }
 
-fn interact_anni(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // CON-CON same label: annihilate
+fn interact_call(net: &mut GNet, book: &Book, a: Port, b: Port) -> Result<()> {
+    // REF-node: copy the definition's template and link its root to `b`
+    let tmpl = book.template(a.val()).ok_or(CoreError::UndefinedRef(a.val()))?;
+
+    // Copy optimization: a safe definition is duplicated as a reference
+    if tmpl.safe && b.tag() == Tag::Dup {
+        return interact_eras(net, a, b);
+    }
+
+    let root = tmpl.instantiate(net)?;
+    net.link(root, b);
     Ok(())
 }
 
-fn interact_comm(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // CON-CON different labels: commute
+fn interact_void(_net: &mut GNet, _a: Port, _b: Port) -> Result<()> {
+    // leaf-leaf: nothing to do
     Ok(())
 }
 
-fn interact_eras(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // ERA-anything: erase
+fn interact_eras(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // leaf-node: send a copy of the leaf to each aux port
+    let (b1, b2) = take_node(net, b)?;
+    net.link(a, b1);
+    net.link(a, b2);
     Ok(())
 }
 
-fn interact_deref(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // REF-anything: dereference
+fn interact_anni(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // node-node same tag: connect aux ports pairwise
+    let (a1, a2) = take_node(net, a)?;
+    let (b1, b2) = take_node(net, b)?;
+    net.link(a1, b1);
+    net.link(a2, b2);
     Ok(())
 }
 
-fn interact_call(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // APP-LAM: function application
+fn interact_comm(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // node-node diff tag: each node passes through the other
+    let (a1, a2) = take_node(net, a)?;
+    let (b1, b2) = take_node(net, b)?;
+
+    let v0 = new_var(net)?;
+    let v1 = new_var(net)?;
+    let v2 = new_var(net)?;
+    let v3 = new_var(net)?;
+
+    let n0 = new_node(net, b.tag(), v0, v1)?;
+    let n1 = new_node(net, b.tag(), v2, v3)?;
+    let n2 = new_node(net, a.tag(), v0, v2)?;
+    let n3 = new_node(net, a.tag(), v1, v3)?;
+
+    net.link(n0, a1);
+    net.link(n1, a2);
+    net.link(n2, b1);
+    net.link(n3, b2);
     Ok(())
 }
 
-fn interact_copy(_net: &mut GNet, _a: Port, _b: Port) -> Result<(), String> {
-    // DUP-anything: duplicate
+fn interact_oper(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // NUM-OPR: operate if both operands are known, otherwise flip
+    let av = a.val();
+    let (b1, b2) = take_node(net, b)?;
+    let b2 = net.enter(b2);
+
+    if b1.tag() == Tag::Num {
+        let cv = operate(av, b1.val());
+        net.link(Port::new(Tag::Num, cv), b2);
+    } else {
+        let c = new_node(net, Tag::Opr, a, b2)?;
+        net.link(b1, c);
+    }
     Ok(())
 }
 
-fn interact_oper(_net: &mut GNet, a: Port, b: Port) -> Result<(), String> {
-    // NUM-NUM: arithmetic operation
-    let val_a = a.val();
-    let val_b = b.val();
-    let _result = val_a + val_b; // Example: addition
+fn interact_swit(net: &mut GNet, a: Port, b: Port) -> Result<()> {
+    // NUM-SWI: select the zero or successor branch
+    let av = Numb::new(a.val()).u24();
+    let (b1, b2) = take_node(net, b)?;
+    let era = Port::new(Tag::Era, 0);
+
+    let c = if av == 0 {
+        new_node(net, Tag::Con, b2, era)?
+    } else {
+        let pred = new_node(net, Tag::Con, Port::new(Tag::Num, Numb::new_u24(av - 1).0), b2)?;
+        new_node(net, Tag::Con, era, pred)?
+    };
+    net.link(c, b1);
     Ok(())
 }
 
@@ -106,6 +244,30 @@ fn interact_oper(_net: &mut GNet, a: Port, b: Port) -> Result<(), String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::book::Def;
+
+    const TAGS: [Tag; 8] = [
+        Tag::Var,
+        Tag::Ref,
+        Tag::Era,
+        Tag::Num,
+        Tag::Con,
+        Tag::Dup,
+        Tag::Opr,
+        Tag::Swi,
+    ];
+
+    fn era() -> Port {
+        Port::new(Tag::Era, 0)
+    }
+
+    fn num(val: Val) -> Port {
+        Port::new(Tag::Num, Numb::new_u24(val).0)
+    }
+
+    fn add(val: Val) -> Port {
+        Port::new(Tag::Num, Numb::partial(Numb::new_sym(Numb::OP_ADD), Numb::new_u24(val)).0)
+    }
 
     #[test]
     fn test_get_rule_link() {
@@ -115,36 +277,250 @@ mod tests {
     }
 
     #[test]
-    fn test_get_rule_deref() {
+    fn test_get_rule_call() {
         let a = Port::new(Tag::Ref, 1);
-        let b = Port::new(Tag::Var, 2);
-        assert_eq!(get_rule(a, b), Rule::Deref);
+        assert_eq!(get_rule(a, Port::new(Tag::Var, 2)), Rule::Link);
+        assert_eq!(get_rule(a, Port::new(Tag::Con, 2)), Rule::Call);
     }
 
     #[test]
     fn test_get_rule_oper() {
         let a = Port::new(Tag::Num, 10);
-        let b = Port::new(Tag::Num, 20);
+        let b = Port::new(Tag::Opr, 20);
         assert_eq!(get_rule(a, b), Rule::Oper);
+        assert_eq!(get_rule(b, a), Rule::Oper);
+    }
+
+    #[test]
+    fn test_get_rule_table() {
+        for &a in &TAGS {
+            for &b in &TAGS {
+                let expected = if a == Tag::Var || b == Tag::Var {
+                    Rule::Link
+                } else if a < Tag::Con && b < Tag::Con {
+                    Rule::Void
+                } else if a == Tag::Ref || b == Tag::Ref {
+                    Rule::Call
+                } else if a == b {
+                    Rule::Anni
+                } else if (a, b) == (Tag::Num, Tag::Opr) || (a, b) == (Tag::Opr, Tag::Num) {
+                    Rule::Oper
+                } else if (a, b) == (Tag::Num, Tag::Swi) || (a, b) == (Tag::Swi, Tag::Num) {
+                    Rule::Swit
+                } else if a < Tag::Con || b < Tag::Con {
+                    Rule::Eras
+                } else {
+                    Rule::Comm
+                };
+                let (pa, pb) = (Port::new(a, 1), Port::new(b, 2));
+                assert_eq!(get_rule(pa, pb), expected, "{:?} ~ {:?}", a, b);
+                assert_eq!(get_rule(pb, pa), expected, "{:?} ~ {:?}", b, a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_should_swap() {
+        for &a in &TAGS {
+            for &b in &TAGS {
+                let (pa, pb) = (Port::new(a, 0), Port::new(b, 0));
+                let (lo, _) = if should_swap(pa, pb) { (pb, pa) } else { (pa, pb) };
+                assert_eq!(lo.tag(), a.min(b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_high_priority() {
+        use Rule::*;
+        let high: Vec<Rule> = [Link, Call, Void, Eras, Anni, Comm, Oper, Swit]
+            .into_iter()
+            .filter(|&rule| is_high_priority(rule))
+            .collect();
+        assert_eq!(high, vec![Link, Void, Eras, Anni]);
     }
 
     #[test]
     fn test_interact_link() {
         let mut net = GNet::new();
-        let a = Port::new(Tag::Var, 1);
-        let b = Port::new(Tag::Var, 2);
-        
-        let result = interact(&mut net, a, b);
-        assert!(result.is_ok());
+        let book = Book::new();
+        let x = new_var(&mut net).unwrap();
+        let y = new_var(&mut net).unwrap();
+
+        let result = interact(&mut net, &book, x, y);
+        assert_eq!(result, Ok(Rule::Link));
+        net.link(y, num(5));
+        assert_eq!(net.peek(x), num(5));
+    }
+
+    #[test]
+    fn test_interact_void() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let result = interact(&mut net, &book, num(1), era());
+        assert_eq!(result, Ok(Rule::Void));
+        assert!(net.redexes.is_empty());
+    }
+
+    #[test]
+    fn test_interact_eras() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let x = new_var(&mut net).unwrap();
+        let y = new_var(&mut net).unwrap();
+        let con = new_node(&mut net, Tag::Con, x, y).unwrap();
+
+        // Swapped: node comes first
+        assert_eq!(interact(&mut net, &book, con, num(3)), Ok(Rule::Eras));
+        assert_eq!(net.node_count(), 0);
+        assert_eq!(net.peek(x), num(3));
+        assert_eq!(net.peek(y), num(3));
+    }
+
+    #[test]
+    fn test_interact_anni() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let x = new_var(&mut net).unwrap();
+        let y = new_var(&mut net).unwrap();
+        let a = new_node(&mut net, Tag::Dup, x, y).unwrap();
+        let b = new_node(&mut net, Tag::Dup, num(1), num(2)).unwrap();
+
+        assert_eq!(interact(&mut net, &book, a, b), Ok(Rule::Anni));
+        assert_eq!(net.node_count(), 0);
+        assert_eq!(net.peek(x), num(1));
+        assert_eq!(net.peek(y), num(2));
+    }
+
+    #[test]
+    fn test_interact_comm() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let a = new_node(&mut net, Tag::Con, num(1), num(2)).unwrap();
+        let b = new_node(&mut net, Tag::Dup, era(), era()).unwrap();
+
+        assert_eq!(interact(&mut net, &book, a, b), Ok(Rule::Comm));
+        assert_eq!(net.node_count(), 4);
+        // Every copy meets a leaf: two DUPs ~ NUM, two CONs ~ ERA
+        assert_eq!(net.redexes.len(), 4);
+        for redex in net.redexes.clone() {
+            assert_eq!(get_rule(redex.fst(), redex.snd()), Rule::Eras);
+        }
     }
 
     #[test]
     fn test_interact_oper() {
         let mut net = GNet::new();
-        let a = Port::new(Tag::Num, 5);
-        let b = Port::new(Tag::Num, 3);
-        
-        let result = interact(&mut net, a, b);
-        assert!(result.is_ok());
+        let book = Book::new();
+        let out = new_var(&mut net).unwrap();
+        let opr = new_node(&mut net, Tag::Opr, add(3), out).unwrap();
+
+        assert_eq!(interact(&mut net, &book, num(5), opr), Ok(Rule::Oper));
+        assert_eq!(net.peek(out), num(8));
+    }
+
+    #[test]
+    fn test_interact_oper_flip() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let x = new_var(&mut net).unwrap();
+        let out = new_var(&mut net).unwrap();
+        let opr = new_node(&mut net, Tag::Opr, x, out).unwrap();
+
+        assert_eq!(interact(&mut net, &book, num(5), opr), Ok(Rule::Oper));
+        net.link(x, add(4));
+        let redex = net.redexes.pop().unwrap();
+        assert_eq!(interact(&mut net, &book, redex.fst(), redex.snd()), Ok(Rule::Oper));
+        assert_eq!(net.peek(out), num(9));
+    }
+
+    #[test]
+    fn test_interact_swit() {
+        for (val, expected) in [(0, Tag::Con), (3, Tag::Con)] {
+            let mut net = GNet::new();
+            let book = Book::new();
+            let ret = new_var(&mut net).unwrap();
+            let sel = new_var(&mut net).unwrap();
+            let swi = new_node(&mut net, Tag::Swi, sel, ret).unwrap();
+
+            assert_eq!(interact(&mut net, &book, num(val), swi), Ok(Rule::Swit));
+            let got = net.peek(sel);
+            assert_eq!(got.tag(), expected);
+            let node = net.node_load(got.val());
+            if val == 0 {
+                assert_eq!(node, Pair::new(ret, era()));
+            } else {
+                let pred = net.node_load(node.snd().val());
+                assert_eq!(node.fst(), era());
+                assert_eq!(pred, Pair::new(num(val - 1), ret));
+            }
+        }
+    }
+
+    #[test]
+    fn test_interact_call() {
+        // @id = (x x)
+        let mut tmpl = GNet::new();
+        let x = new_var(&mut tmpl).unwrap();
+        let lam = new_node(&mut tmpl, Tag::Con, x, x).unwrap();
+        tmpl.set_root(lam);
+
+        let mut book = Book::new();
+        let fid = book.insert("id".to_string(), Def {
+            name: "id".to_string(),
+            arity: 1,
+            net: tmpl,
+        });
+
+        let mut net = GNet::new();
+        let out = new_var(&mut net).unwrap();
+        let app = new_node(&mut net, Tag::Con, num(42), out).unwrap();
+        let call = Port::new(Tag::Ref, fid);
+
+        assert_eq!(interact(&mut net, &book, app, call), Ok(Rule::Call));
+        let redex = net.redexes.pop().unwrap();
+        assert_eq!(interact(&mut net, &book, redex.fst(), redex.snd()), Ok(Rule::Anni));
+        assert_eq!(net.peek(out), num(42));
+        assert_eq!(net.node_count(), 0);
+    }
+
+    #[test]
+    fn test_interact_call_root() {
+        let mut tmpl = GNet::new();
+        tmpl.set_root(num(7));
+        let mut book = Book::new();
+        let fid = book.insert("main".to_string(), Def {
+            name: "main".to_string(),
+            arity: 0,
+            net: tmpl,
+        });
+
+        let mut net = GNet::new();
+        let call = Port::new(Tag::Ref, fid);
+        assert_eq!(interact(&mut net, &book, call, Port::ROOT), Ok(Rule::Call));
+        assert_eq!(net.root(), num(7));
+    }
+
+    #[test]
+    fn test_interact_call_safe_copy() {
+        // A safe definition meeting a DUP is copied as a reference
+        let book = Book::parse("@main = * \n@id = (x x)").unwrap();
+        let mut net = GNet::new();
+        let (a, b) = (new_var(&mut net).unwrap(), new_var(&mut net).unwrap());
+        let dup = new_node(&mut net, Tag::Dup, a, b).unwrap();
+        let call = Port::new(Tag::Ref, book.id("id").unwrap());
+        assert_eq!(interact(&mut net, &book, call, dup), Ok(Rule::Call));
+        assert_eq!(net.peek(a), call);
+        assert_eq!(net.peek(b), call);
+        assert_eq!(net.node_count(), 0);
+    }
+
+    #[test]
+    fn test_interact_call_undefined() {
+        let mut net = GNet::new();
+        let book = Book::new();
+        let con = new_node(&mut net, Tag::Con, era(), era()).unwrap();
+        let result = interact(&mut net, &book, Port::new(Tag::Ref, 9), con);
+        assert_eq!(result, Err(CoreError::UndefinedRef(9)));
     }
 }
@@ -0,0 +1,444 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: lazy.rs
+// Location: crates/hvmx-core/src/lazy.rs
+// Purpose: Lazy evaluation, reducing only what the root needs
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Lazy evaluation, after the lazy mode of the HVM2 reference.
+//!
+//! Strict reduction empties the redex bag. The lazy normalizer walks the
+//! tree hanging from the root, following variable chains, and collects the
+//! variables nothing has been linked to yet: the ports readback would wait
+//! on. Only redexes that reach one of them are reduced, along with the
+//! redexes those create while they reach one too; then the walk starts
+//! over. Once no redex blocks the root, those left are unreachable from it
+//! and are dropped, and their nodes and variables freed, so programs that
+//! build infinite structures they never read still terminate.
+//!
+//! Each round walks the net once. Linked variables found to lead to the
+//! root stay marked for the round, so later walks stop there, and redexes
+//! set aside are sorted again as soon as a variable they wait on is linked.
+//! When an interaction links a variable the root waits on, the variables
+//! under its new value are waited on straight away, so a result that grows
+//! a cell at a time is followed within one round instead of one per cell.
+//! Set-aside redexes count against the bag's capacities, as in strict mode.
+//!
+//! REFs in the result are left for readback to expand, as in strict mode.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::interact::interact_holding;
+use crate::{Book, CoreError, GNet, Pair, Port, Result, Stats, Tag, Val};
+
+/// Which redexes the evaluator reduces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvalMode {
+    /// All of them, to a full normal form
+    #[default]
+    Strict,
+    /// Only those that block readback of the root
+    Lazy,
+}
+
+impl GNet {
+    /// Boots `@main` and reduces it lazily
+    pub fn normalize_lazy(&mut self, book: &Book) -> Result<Stats> {
+        self.boot(book)?;
+        self.reduce_lazy(book)
+    }
+
+    /// Reduces the redexes the root needs, then drops and frees the others
+    pub fn reduce_lazy(&mut self, book: &Book) -> Result<Stats> {
+        self.reduce_lazy_with(book, |_, _, _| Ok(()))
+    }
+
+    /// `reduce_lazy`, calling `check` on each redex before reducing it.
+    /// If it fails, the pending redexes are kept.
+    pub(crate) fn reduce_lazy_with(
+        &mut self,
+        book: &Book,
+        check: impl FnMut(&GNet, Port, Port) -> Result<()>,
+    ) -> Result<Stats> {
+        self.reduce_lazy_walking(book, check, &mut 0)
+    }
+
+    /// `reduce_lazy_with`, adding the ports its walks visit to `walked`
+    fn reduce_lazy_walking(
+        &mut self,
+        book: &Book,
+        mut check: impl FnMut(&GNet, Port, Port) -> Result<()>,
+        walked: &mut usize,
+    ) -> Result<Stats> {
+        let mut stats = Stats::new();
+        let mut idle: Vec<Pair> = self.redexes.drain().collect();
+        loop {
+            let mut round = Round::new(self.waiting(walked));
+            for redex in idle {
+                round.sort(self, redex);
+            }
+            if round.work.is_empty() {
+                idle = round.into_idle(walked);
+                break;
+            }
+            while let Some(redex) = round.work.pop() {
+                round.held[self.redexes.is_high(redex) as usize] -= 1;
+                let (a, b) = (redex.fst(), redex.snd());
+                if let Err(err) = check(self, a, b) {
+                    // Put everything back, so the net can be reduced again
+                    self.redexes.extend(round.into_idle(walked));
+                    self.redexes.push(redex);
+                    return Err(err);
+                }
+                let touched = self.touched(a, b);
+                for var in &touched {
+                    round.leads.remove(var);
+                }
+                // Whatever these get linked to hangs from the root
+                let rooted: Vec<Val> = touched.iter().copied().filter(|var| round.waiting.contains(var)).collect();
+                let rule = match interact_holding(self, book, a, b, (round.held[1], round.held[0])) {
+                    Ok(rule) => rule,
+                    Err(err) => {
+                        // A full bag fails before the interaction, so the
+                        // redex goes back with the rest
+                        self.redexes.extend(round.into_idle(walked));
+                        if let CoreError::BagOverflow(_) = err {
+                            self.redexes.push(redex);
+                        }
+                        return Err(err);
+                    }
+                };
+                stats.record(rule);
+                for var in rooted {
+                    round.grow(self, var);
+                }
+                for var in touched {
+                    round.wake(self, var);
+                }
+                while let Some(redex) = self.redexes.pop() {
+                    round.sort(self, redex);
+                }
+            }
+            idle = round.into_idle(walked);
+        }
+        if !idle.is_empty() {
+            self.reclaim();
+        }
+        Ok(stats)
+    }
+
+    /// Unlinked variables in the tree hanging from the root, adding the
+    /// ports visited to `walked`
+    fn waiting(&self, walked: &mut usize) -> HashSet<Val> {
+        let mut vars = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![Port::ROOT];
+        while let Some(port) = stack.pop() {
+            if port == Port::NONE || !seen.insert(port) {
+                continue;
+            }
+            *walked += 1;
+            if port.tag() == Tag::Var {
+                let val = self.vars_load(port.val());
+                if val == Port::NONE || val == Port::FREE {
+                    vars.insert(port.val());
+                } else {
+                    stack.push(val);
+                }
+            } else if port.is_nod() {
+                let node = self.node_load(port.val());
+                stack.push(node.snd());
+                stack.push(node.fst());
+            }
+        }
+        vars
+    }
+
+    /// The variables an interaction between `a` and `b` can link or free:
+    /// the chains from either side and from their nodes' aux ports
+    fn touched(&self, a: Port, b: Port) -> Vec<Val> {
+        let mut ports = Vec::new();
+        for port in [a, b] {
+            if port.is_nod() {
+                let node = self.node_load(port.val());
+                ports.extend([node.fst(), node.snd()]);
+            } else {
+                ports.push(port);
+            }
+        }
+        let mut vars = Vec::new();
+        for mut port in ports {
+            while port.tag() == Tag::Var {
+                vars.push(port.val());
+                let val = self.vars_load(port.val());
+                if val == Port::NONE || val == Port::FREE {
+                    break;
+                }
+                port = val;
+            }
+        }
+        vars
+    }
+}
+
+/// One round of lazy reduction: what the root waits on, the redexes that
+/// reach it and those set aside
+struct Round {
+    waiting: HashSet<Val>,
+    /// Linked variables whose trees reach `waiting`
+    leads: HashSet<Val>,
+    work: Vec<Pair>,
+    /// Set-aside redexes, by id; `None` once sorted again
+    parked: Vec<Option<Pair>>,
+    /// Ids of the set-aside redexes that reach each unlinked variable
+    owners: HashMap<Val, Vec<usize>>,
+    /// Redexes in `work` and `parked`, as (low, high) classes
+    held: [usize; 2],
+    /// Walk buffers, kept between walks
+    stack: Vec<(Port, usize)>,
+    path: Vec<(Val, usize)>,
+    seen: HashSet<Val>,
+    leaves: Vec<Val>,
+    /// Ports visited by walks
+    walked: usize,
+}
+
+impl Round {
+    fn new(waiting: HashSet<Val>) -> Self {
+        Self {
+            waiting,
+            leads: HashSet::new(),
+            work: Vec::new(),
+            parked: Vec::new(),
+            owners: HashMap::new(),
+            held: [0; 2],
+            stack: Vec::new(),
+            path: Vec::new(),
+            seen: HashSet::new(),
+            leaves: Vec::new(),
+            walked: 0,
+        }
+    }
+
+    /// Adds a redex to the work if it reaches the root, else sets it aside
+    fn sort(&mut self, net: &GNet, redex: Pair) {
+        self.held[net.redexes.is_high(redex) as usize] += 1;
+        let mut leaves = std::mem::take(&mut self.leaves);
+        leaves.clear();
+        if self.reaches(net, redex.fst(), &mut leaves) || self.reaches(net, redex.snd(), &mut leaves) {
+            self.work.push(redex);
+        } else {
+            let id = self.parked.len();
+            self.parked.push(Some(redex));
+            for &var in &leaves {
+                self.owners.entry(var).or_default().push(id);
+            }
+        }
+        self.leaves = leaves;
+    }
+
+    /// Sorts again the set-aside redexes waiting on `var`, if it was linked
+    fn wake(&mut self, net: &GNet, var: Val) {
+        let val = net.vars_load(var);
+        if val != Port::NONE && val != Port::FREE {
+            self.resort(net, var);
+        }
+    }
+
+    /// Sorts again the set-aside redexes waiting on `var`
+    fn resort(&mut self, net: &GNet, var: Val) {
+        for id in self.owners.remove(&var).unwrap_or_default() {
+            if let Some(redex) = self.parked[id].take() {
+                self.held[net.redexes.is_high(redex) as usize] -= 1;
+                self.sort(net, redex);
+            }
+        }
+    }
+
+    /// Adds the unlinked variables under what waiting `var` was linked to,
+    /// if it was, waking the redexes set aside on them. The root needs them
+    /// now, and finding them here spares a walk from the root per round.
+    fn grow(&mut self, net: &GNet, var: Val) {
+        let val = net.vars_load(var);
+        if val == Port::NONE || val == Port::FREE {
+            return;
+        }
+        let mut found = Vec::new();
+        self.seen.clear();
+        self.stack.clear();
+        self.stack.push((val, usize::MAX));
+        while let Some((port, _)) = self.stack.pop() {
+            if port == Port::NONE {
+                continue;
+            }
+            self.walked += 1;
+            if port.tag() == Tag::Var {
+                let var = port.val();
+                let val = net.vars_load(var);
+                if self.waiting.contains(&var) || !self.seen.insert(var) {
+                    continue;
+                }
+                if val == Port::NONE || val == Port::FREE {
+                    self.waiting.insert(var);
+                    found.push(var);
+                } else {
+                    self.stack.push((val, usize::MAX));
+                }
+            } else if port.is_nod() {
+                let node = net.node_load(port.val());
+                self.stack.push((node.snd(), usize::MAX));
+                self.stack.push((node.fst(), usize::MAX));
+            }
+        }
+        for var in found {
+            self.resort(net, var);
+        }
+    }
+
+    /// Whether the tree under `port` reaches a waiting variable. Marks
+    /// the linked variables on the way there; if it does not, pushes the
+    /// unlinked variables it ends at to `leaves`.
+    fn reaches(&mut self, net: &GNet, port: Port, leaves: &mut Vec<Val>) -> bool {
+        // Linked variables walked through, each with the one above it.
+        // Only they can close a cycle, so only they are remembered.
+        self.path.clear();
+        self.seen.clear();
+        self.stack.clear();
+        self.stack.push((port, usize::MAX));
+        while let Some((port, up)) = self.stack.pop() {
+            if port == Port::NONE {
+                continue;
+            }
+            self.walked += 1;
+            let found = if port.tag() == Tag::Var {
+                let var = port.val();
+                let val = net.vars_load(var);
+                if self.leads.contains(&var) {
+                    true
+                } else if val == Port::NONE || val == Port::FREE {
+                    leaves.push(var);
+                    self.waiting.contains(&var)
+                } else {
+                    if self.seen.insert(var) {
+                        self.path.push((var, up));
+                        self.stack.push((val, self.path.len() - 1));
+                    }
+                    false
+                }
+            } else {
+                if port.is_nod() {
+                    let node = net.node_load(port.val());
+                    self.stack.push((node.snd(), up));
+                    self.stack.push((node.fst(), up));
+                }
+                false
+            };
+            if found {
+                let mut up = up;
+                while let Some(&(var, next)) = self.path.get(up) {
+                    self.leads.insert(var);
+                    up = next;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The redexes left, set aside or not, adding the ports visited to
+    /// `walked`
+    fn into_idle(self, walked: &mut usize) -> Vec<Pair> {
+        *walked += self.walked;
+        self.parked.into_iter().flatten().chain(self.work).collect()
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus;
+    use crate::term::Program;
+
+    const LISTS: &str = "
+        (Take 0 xs) = Nil
+        (Take n Nil) = Nil
+        (Take n (Cons x xs)) = (Cons x (Take (- n 1) xs))
+        (Nats n) = (Cons n (Nats (+ n 1)))
+        (Range 0) = Nil
+        (Range n) = (Cons n (Range (- n 1)))
+        (Sum Nil) = 0
+        (Sum (Cons x xs)) = (+ x (Sum xs))
+    ";
+
+    fn eval(src: &str, mode: EvalMode) -> String {
+        let program = Program::parse_hvm1(&format!("{}(Main) = {}", LISTS, src)).unwrap();
+        let mut net = GNet::new();
+        match mode {
+            EvalMode::Strict => net.normalize(&program.book).unwrap(),
+            EvalMode::Lazy => net.normalize_lazy(&program.book).unwrap(),
+        };
+        program.readback(&mut net).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_lazy_matches_strict() {
+        for (name, book, expected) in corpus::all() {
+            let mut net = GNet::new();
+            let stats = net.normalize_lazy(&book).unwrap();
+            assert_eq!(net.root(), corpus::num(expected), "{}", name);
+            assert!(net.redexes.is_empty(), "{}", name);
+            assert!(stats.interactions > 0, "{}", name);
+        }
+        for src in ["(Sum (Range 10))", "(Take 2 (Range 5))", "(Take 9 (Range 2))", "@x (Sum (Cons x Nil))"] {
+            assert_eq!(eval(src, EvalMode::Lazy), eval(src, EvalMode::Strict), "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_lazy_infinite_list() {
+        assert_eq!(eval("(Take 3 (Nats 0))", EvalMode::Lazy), "{Cons 0 {Cons 1 {Cons 2 {Nil}}}}");
+        assert_eq!(eval("(Sum (Take 4 (Nats 5)))", EvalMode::Lazy), "26");
+    }
+
+    #[test]
+    fn test_lazy_scales_like_strict() {
+        // Every redex is needed and the root grows by a cell at a time, so
+        // lazy mode does the same interactions, and walks a bounded number
+        // of ports for each whatever the length
+        let walk = |n: usize| {
+            let program = Program::parse_hvm1(&format!("{}(Main) = (Range {})", LISTS, n)).unwrap();
+            let book = &program.book;
+            let mut strict = GNet::new();
+            let total = strict.normalize(book).unwrap();
+            let mut net = GNet::new();
+            net.boot(book).unwrap();
+            let mut walked = 0;
+            let stats = net.reduce_lazy_walking(book, |_, _, _| Ok(()), &mut walked).unwrap();
+            assert_eq!(program.readback(&mut net).unwrap(), program.readback(&mut strict).unwrap());
+            assert!(stats.interactions <= total.interactions);
+            walked as f64 / stats.interactions as f64
+        };
+        let (small, large) = (walk(250), walk(2_000));
+        assert!(large < 32.0, "{} ports walked per interaction", large);
+        assert!(large < small * 1.5, "{} then {} ports walked per interaction", small, large);
+    }
+
+    #[test]
+    fn test_lazy_skips_unneeded_work() {
+        // Strictly, `@spin` never stops
+        let book = Book::parse("@main = r & @K ~ (1 (s r)) & @spin ~ (* s)\n@K = (a (* a))\n@spin = (a b) & @spin ~ (a b)").unwrap();
+        let mut net = GNet::new();
+        let stats = net.normalize_lazy(&book).unwrap();
+        assert_eq!(net.root(), corpus::num(1));
+        assert!(net.redexes.is_empty());
+        assert!(stats.interactions <= 4);
+    }
+}
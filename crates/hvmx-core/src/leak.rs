@@ -0,0 +1,306 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: leak.rs
+// Location: crates/hvmx-core/src/leak.rs
+// Purpose: Finding and reclaiming nodes unreachable from the root
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Leak detection, after `gnet_get_leak` and `count_memory` in `hvm.cu`.
+//!
+//! Nodes and variables are connected by the ports they hold: a node to
+//! what its aux ports point at, a linked variable to its value. Whatever is
+//! connected to the root or to a redex, in either direction, is live; the
+//! rest is leaked and can never be read or reduced. A leaked node is
+//! circular when the chain of ports holding its principal port leads back
+//! to it, as in `(a b) & a ~ ...` cycles that erasers never reach.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::{GNet, Port, Tag, Val};
+
+/// Nodes and variables nothing live can reach
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    /// Leaked nodes, in slot order
+    pub nodes: Vec<Val>,
+    /// Leaked variables, in slot order
+    pub vars: Vec<Val>,
+    /// Leaked nodes by the tag of the port pointing at them
+    pub by_tag: BTreeMap<Tag, usize>,
+    /// Leaked nodes no port points at
+    pub untagged: usize,
+    /// Leaked nodes on a cycle of principal ports
+    pub circular: usize,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.vars.is_empty()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nodes and {} vars leaked", self.nodes.len(), self.vars.len())?;
+        let mut groups: Vec<String> = self.by_tag.iter().map(|(tag, n)| format!("{:?} {}", tag, n)).collect();
+        if self.untagged > 0 {
+            groups.push(format!("untagged {}", self.untagged));
+        }
+        if !groups.is_empty() {
+            write!(f, ": {}", groups.join(", "))?;
+        }
+        if self.circular > 0 {
+            write!(f, "; {} on cycles", self.circular)?;
+        }
+        Ok(())
+    }
+}
+
+/// A heap slot: node `loc` or variable `var`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Node(Val),
+    Var(Val),
+}
+
+impl GNet {
+    /// Finds the nodes and variables unreachable from the root and the
+    /// redex bag
+    pub fn leaks(&self) -> LeakReport {
+        let (nodes, vars) = self.buffers();
+        let index = |item: Item| match item {
+            Item::Node(loc) => loc as usize,
+            Item::Var(var) => nodes.len() + var as usize,
+        };
+        let item = |port: Port| {
+            if port == Port::NONE || port == Port::ROOT {
+                None
+            } else if port.tag() == Tag::Var && (port.val() as usize) < vars.len() {
+                Some(Item::Var(port.val()))
+            } else if port.is_nod() && (port.val() as usize) < nodes.len() {
+                Some(Item::Node(port.val()))
+            } else {
+                None
+            }
+        };
+
+        // Edges both ways, and what holds each slot's port
+        let mut edges: Vec<Vec<Item>> = vec![Vec::new(); nodes.len() + vars.len()];
+        let mut holder: Vec<Option<(Item, Tag)>> = vec![None; nodes.len() + vars.len()];
+        let mut connect = |from: Item, port: Port| {
+            if let Some(to) = item(port) {
+                edges[index(from)].push(to);
+                edges[index(to)].push(from);
+                holder[index(to)] = Some((from, port.tag()));
+            }
+        };
+        for (loc, node) in self.nodes() {
+            connect(Item::Node(loc), node.fst());
+            connect(Item::Node(loc), node.snd());
+        }
+        for (var, val) in self.vars() {
+            connect(Item::Var(var), val);
+        }
+
+        let mut live = vec![false; nodes.len() + vars.len()];
+        let mut queue: VecDeque<Item> = self
+            .redexes
+            .iter()
+            .flat_map(|redex| [redex.fst(), redex.snd()])
+            .chain([self.vars_load(Port::ROOT.val())])
+            .filter_map(item)
+            .collect();
+        // ROOT's other endpoint, if it is still unlinked
+        for (loc, node) in self.nodes() {
+            if node.fst() == Port::ROOT || node.snd() == Port::ROOT {
+                queue.push_back(Item::Node(loc));
+            }
+        }
+        while let Some(next) = queue.pop_front() {
+            if !std::mem::replace(&mut live[index(next)], true) {
+                queue.extend(&edges[index(next)]);
+            }
+        }
+
+        // Each slot has at most one holder, so walking up from each leaked
+        // node, and stopping at slots already walked, visits every slot once
+        const NEW: usize = usize::MAX;
+        const DONE: usize = usize::MAX - 1;
+        // NEW, DONE, or the slot's position on the chain being walked
+        let mut state = vec![NEW; edges.len()];
+        let mut on_cycle = vec![false; edges.len()];
+        let mut chain = Vec::new();
+        for (loc, _) in self.nodes() {
+            let mut at = Some(Item::Node(loc));
+            while let Some(item) = at.filter(|item| !live[index(*item)]) {
+                match state[index(item)] {
+                    NEW => {
+                        state[index(item)] = chain.len();
+                        chain.push(item);
+                        at = holder[index(item)].map(|(up, _)| up);
+                    }
+                    DONE => break,
+                    start => {
+                        for item in &chain[start..] {
+                            on_cycle[index(*item)] = true;
+                        }
+                        break;
+                    }
+                }
+            }
+            for item in chain.drain(..) {
+                state[index(item)] = DONE;
+            }
+        }
+
+        let mut report = LeakReport::default();
+        for (loc, _) in self.nodes() {
+            if live[index(Item::Node(loc))] {
+                continue;
+            }
+            report.nodes.push(loc);
+            match holder[index(Item::Node(loc))] {
+                Some((_, tag)) => *report.by_tag.entry(tag).or_default() += 1,
+                None => report.untagged += 1,
+            }
+            report.circular += on_cycle[index(Item::Node(loc))] as usize;
+        }
+        for (var, _) in self.vars() {
+            if !live[index(Item::Var(var))] {
+                report.vars.push(var);
+            }
+        }
+        report
+    }
+
+    /// Frees every leaked node and variable, returning what was freed
+    pub fn reclaim(&mut self) -> LeakReport {
+        let report = self.leaks();
+        for &loc in &report.nodes {
+            self.node_take(loc);
+        }
+        for &var in &report.vars {
+            self.vars_take(var);
+        }
+        report
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, Book, Pair, Tag};
+
+    #[test]
+    fn test_no_leaks_after_normalize() {
+        for (name, book, _) in corpus::all() {
+            let mut net = GNet::new();
+            net.boot(&book).unwrap();
+            assert!(net.leaks().is_empty(), "{}", name);
+            // Midway, everything hangs from the root or a redex
+            for _ in 0..16 {
+                if let Some(redex) = net.redexes.pop() {
+                    crate::interact(&mut net, &book, redex.fst(), redex.snd()).unwrap();
+                }
+            }
+            assert!(net.leaks().is_empty(), "{}", name);
+            net.reduce(&book).unwrap();
+            assert!(net.leaks().is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_leaks_after_lazy_reduction() {
+        let book = Book::parse("@main = r & @K ~ (1 (s r)) & @spin ~ (* s)\n@K = (a (* a))\n@spin = (a b) & @spin ~ (a b)").unwrap();
+        // Lazy reduction frees the redex it drops
+        let mut net = GNet::new();
+        net.normalize_lazy(&book).unwrap();
+        assert!(net.leaks().is_empty());
+        assert_eq!(net.node_count(), 0);
+        assert_eq!(net.vars_count(), 0);
+        assert_eq!(net.root(), corpus::num(1));
+
+        // Dropping it by hand leaks it
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let main = net.redexes.pop().unwrap();
+        crate::interact(&mut net, &book, main.fst(), main.snd()).unwrap();
+        let spin = Port::new(Tag::Ref, book.id("spin").unwrap());
+        let redexes: Vec<Pair> = net.redexes.drain().filter(|redex| redex.fst() != spin && redex.snd() != spin).collect();
+        assert_eq!(redexes.len(), 1);
+        net.redexes.extend(redexes);
+        net.reduce(&book).unwrap();
+        let report = net.leaks();
+        assert_eq!(report.nodes.len(), 1);
+        assert_eq!(report.untagged, 1);
+        assert_eq!(report.to_string(), "1 nodes and 1 vars leaked: untagged 1");
+
+        let freed = net.reclaim();
+        assert_eq!(freed, report);
+        assert_eq!(net.node_count(), 0);
+        assert_eq!(net.vars_count(), 0);
+        assert!(net.leaks().is_empty());
+        assert!(net.validate().is_ok());
+        assert_eq!(net.root(), corpus::num(1));
+    }
+
+    #[test]
+    fn test_leaks_long_chain() {
+        let book = Book::parse("@main = (a a)").unwrap();
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+
+        // A long leaked list, then the same list closed into a ring
+        let len = 100_000;
+        let locs: Vec<Val> = (0..len).map(|_| net.node_alloc().unwrap()).collect();
+        let era = Port::new(Tag::Era, 0);
+        for (i, &loc) in locs.iter().enumerate() {
+            let next = locs.get(i + 1).map_or(era, |&next| Port::new(Tag::Con, next));
+            net.node_create(loc, Pair::new(era, next));
+        }
+        let report = net.leaks();
+        assert_eq!(report.nodes.len(), len);
+        assert_eq!((report.untagged, report.circular), (1, 0));
+
+        net.node_store(locs[len - 1], Pair::new(era, Port::new(Tag::Con, locs[0])));
+        let report = net.leaks();
+        assert_eq!((report.untagged, report.circular), (0, len));
+        net.reclaim();
+        assert_eq!(net.to_string(), "(a a)");
+    }
+
+    #[test]
+    fn test_leaks_vicious_circle() {
+        let book = Book::parse("@main = (a a)").unwrap();
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        let live = net.node_count();
+
+        // Two CONs holding each other's principal port, and a DUP holding itself
+        let era = Port::new(Tag::Era, 0);
+        let a = net.node_alloc().unwrap();
+        let b = net.node_alloc().unwrap();
+        let c = net.node_alloc().unwrap();
+        net.node_create(a, Pair::new(Port::new(Tag::Con, b), era));
+        net.node_create(b, Pair::new(era, Port::new(Tag::Con, a)));
+        net.node_create(c, Pair::new(Port::new(Tag::Dup, c), era));
+
+        let report = net.leaks();
+        assert_eq!(report.nodes, vec![a, b, c]);
+        assert_eq!(report.by_tag, BTreeMap::from([(Tag::Con, 2), (Tag::Dup, 1)]));
+        assert_eq!(report.circular, 3);
+        assert_eq!(report.to_string(), "3 nodes and 0 vars leaked: Con 2, Dup 1; 3 on cycles");
+        net.reclaim();
+        assert_eq!(net.node_count(), live);
+        assert_eq!(net.to_string(), "(a a)");
+    }
+}
@@ -15,13 +15,108 @@ pub mod net;
 pub mod interact;
 pub mod numb;
 pub mod book;
+pub mod normalize;
+pub mod parallel;
+pub mod ast;
+pub mod parse;
+pub mod show;
+pub mod format;
+pub mod safety;
+pub mod runtime;
+pub mod deps;
+pub mod optimize;
+pub mod readback;
+pub mod term;
+pub mod hvm1;
+pub mod lazy;
+pub mod budget;
+pub mod checkpoint;
+pub mod snapshot;
+pub mod validate;
+pub mod leak;
+pub mod bag;
+pub mod effect;
+
+#[cfg(test)]
+mod corpus;
 
 // Re-exports
-pub use port::{Port, Tag, Val};
+pub use port::{Port, Pair, Tag, Val};
 pub use net::GNet;
-pub use interact::interact;
+pub use interact::{interact, Rule};
 pub use numb::Numb;
-pub use book::Book;
+pub use book::{Book, Template};
+pub use normalize::Stats;
+pub use parallel::ParConfig;
+pub use safety::SafetyReport;
+pub use optimize::OptConfig;
+pub use runtime::{Config, Runtime};
+pub use lazy::EvalMode;
+pub use budget::{Budget, Limit, Meter};
+pub use snapshot::Snapshot;
+pub use validate::{ValidationReport, Violation};
+pub use leak::LeakReport;
+pub use bag::{BagConfig, RedexBag};
+pub use effect::{Effects, Sandbox};
+
+use thiserror::Error;
+
+/// Core runtime errors
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CoreError {
+    #[error("Invalid tag: {0}")]
+    InvalidTag(u32),
+
+    #[error("Out of memory: {0} buffer is full")]
+    OutOfMemory(&'static str),
+
+    #[error("Undefined definition: @{0}")]
+    UndefinedDef(String),
+
+    #[error("Undefined reference: fid {0}")]
+    UndefinedRef(Val),
+
+    #[error("Port points to a free node: {0:?}")]
+    FreeNode(Port),
+
+    #[error("Parse error at {line}:{col}: {msg}")]
+    Parse { line: usize, col: usize, msg: String },
+
+    #[error("Invalid book buffer: {0}")]
+    InvalidBuffer(String),
+
+    #[error("Unsafe program: {0}")]
+    Unsafe(String),
+
+    #[error("Unsafe definition duplicated: fid {0}")]
+    UnsafeDup(Val),
+
+    #[error("Compile error: {0}")]
+    Compile(String),
+
+    #[error("Readback error: {0}")]
+    Readback(String),
+
+    #[error("Budget exhausted: {0}")]
+    Exhausted(Limit),
+
+    #[error("Unknown checkpoint: {0}")]
+    UnknownCheckpoint(usize),
+
+    #[error("I/O error: {0}")]
+    Io(String),
+
+    #[error("Invalid net: {0}")]
+    InvalidNet(String),
+
+    #[error("Redex bag overflow: more than {0} low-priority redexes")]
+    BagOverflow(usize),
+
+    #[error("Effect error: {0}")]
+    Effect(String),
+}
+
+pub type Result<T> = std::result::Result<T, CoreError>;
 
 #[cfg(test)]
 mod tests {
@@ -10,20 +10,299 @@
 // ==============================================================================
 
 
-use crate::Port;
+use crate::bag::RedexBag;
+use crate::checkpoint::{Checkpoint, Pages};
+use crate::{CoreError, Pair, Port, Result, Tag, Val};
 
+/// GNet: a global interaction net
+///
+/// Sequential counterpart of `Net` in `docs/dor/hvm.c`. Nodes are stored as
+/// `Pair`s of aux ports, variables as the `Port` they are bound to. Slot 0 of
+/// both buffers is reserved so that `FREE` always means "empty slot", and the
+/// `ROOT` variable lives outside the buffers. Buffers are paged, so that
+/// checkpoints can share them. Clones and comparisons leave checkpoints out:
+/// a clone starts with none, and two nets with the same state are equal
+/// whatever checkpoints they keep.
+#[derive(Debug)]
 pub struct GNet {
-    pub nodes: Vec<u64>,
-    pub redexes: Vec<(Port, Port)>,
+    pub(crate) node_buf: Pages<Pair>,
+    pub(crate) vars_buf: Pages<Port>,
+    pub(crate) node_free: Vec<Val>,
+    pub(crate) vars_free: Vec<Val>,
+    pub(crate) root: Port,
+    pub redexes: RedexBag,
+    pub(crate) checkpoints: Vec<(usize, Checkpoint)>,
+    pub(crate) next_checkpoint: usize,
 }
 
 impl GNet {
     pub fn new() -> Self {
         Self {
-            nodes: Vec::new(),
-            redexes: Vec::new(),
+            node_buf: vec![Pair::FREE].into(),
+            vars_buf: vec![Port::FREE].into(),
+            node_free: Vec::new(),
+            vars_free: Vec::new(),
+            root: Port::NONE,
+            redexes: RedexBag::default(),
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
         }
     }
+
+    // Allocator
+
+    /// Allocates a node slot, reusing freed slots first
+    pub fn node_alloc(&mut self) -> Result<Val> {
+        if let Some(loc) = self.node_free.pop() {
+            return Ok(loc);
+        }
+        let loc = self.node_buf.len() as Val;
+        if loc > Port::VAL_MAX {
+            return Err(CoreError::OutOfMemory("node"));
+        }
+        self.node_buf.push(Pair::FREE);
+        Ok(loc)
+    }
+
+    /// Allocates a variable slot, reusing freed slots first
+    pub fn vars_alloc(&mut self) -> Result<Val> {
+        if let Some(var) = self.vars_free.pop() {
+            return Ok(var);
+        }
+        let var = self.vars_buf.len() as Val;
+        // The last value is taken by ROOT
+        if var >= Port::VAL_MAX {
+            return Err(CoreError::OutOfMemory("vars"));
+        }
+        self.vars_buf.push(Port::FREE);
+        Ok(var)
+    }
+
+    // Heap access
+
+    /// Stores a new node
+    pub fn node_create(&mut self, loc: Val, val: Pair) {
+        *self.node_buf.get_mut(loc as usize) = val;
+    }
+
+    /// Stores a new variable
+    pub fn vars_create(&mut self, var: Val, val: Port) {
+        *self.vars_slot(var) = val;
+    }
+
+    /// Reads a node
+    pub fn node_load(&self, loc: Val) -> Pair {
+        self.node_buf.get(loc as usize).unwrap_or(Pair::FREE)
+    }
+
+    /// Reads a variable
+    pub fn vars_load(&self, var: Val) -> Port {
+        if var == Port::ROOT.val() {
+            return self.root;
+        }
+        self.vars_buf.get(var as usize).unwrap_or(Port::FREE)
+    }
+
+    /// Overwrites a node
+    pub fn node_store(&mut self, loc: Val, val: Pair) {
+        *self.node_buf.get_mut(loc as usize) = val;
+    }
+
+    /// Exchanges a variable by a value, returning the old one
+    pub fn vars_exchange(&mut self, var: Val, val: Port) -> Port {
+        std::mem::replace(self.vars_slot(var), val)
+    }
+
+    /// Takes a node, freeing its slot
+    pub fn node_take(&mut self, loc: Val) -> Pair {
+        let got = std::mem::replace(self.node_buf.get_mut(loc as usize), Pair::FREE);
+        if got != Pair::FREE {
+            self.node_free.push(loc);
+        }
+        got
+    }
+
+    /// Takes a variable, freeing its slot
+    pub fn vars_take(&mut self, var: Val) -> Port {
+        let got = self.vars_exchange(var, Port::FREE);
+        if got != Port::FREE && var != Port::ROOT.val() {
+            self.vars_free.push(var);
+        }
+        got
+    }
+
+    fn vars_slot(&mut self, var: Val) -> &mut Port {
+        if var == Port::ROOT.val() {
+            &mut self.root
+        } else {
+            self.vars_buf.get_mut(var as usize)
+        }
+    }
+
+    // Linking
+
+    /// Peeks a variable's final target without modifying it
+    pub fn peek(&self, mut var: Port) -> Port {
+        while var.tag() == Tag::Var {
+            let val = self.vars_load(var.val());
+            if val == Port::NONE || val == Port::FREE {
+                break;
+            }
+            var = val;
+        }
+        var
+    }
+
+    /// Finds a variable's value, consuming the substitution chain
+    pub fn enter(&mut self, mut var: Port) -> Port {
+        while var.tag() == Tag::Var {
+            let val = self.vars_load(var.val());
+            if val == Port::NONE || val == Port::FREE {
+                break;
+            }
+            self.vars_take(var.val());
+            var = val;
+        }
+        var
+    }
+
+    /// Links `a ~ b`, pushing a redex when two nodes meet
+    pub fn link(&mut self, mut a: Port, mut b: Port) {
+        loop {
+            if a.tag() != Tag::Var && b.tag() == Tag::Var {
+                std::mem::swap(&mut a, &mut b);
+            }
+            if a.tag() != Tag::Var {
+                self.redexes.push(Pair::new(a, b));
+                break;
+            }
+            b = self.enter(b);
+            let a_ = self.vars_exchange(a.val(), b);
+            if a_ == Port::NONE {
+                break;
+            }
+            self.vars_take(a.val());
+            a = a_;
+        }
+    }
+
+    /// Links `a ~ b` (as a pair)
+    pub fn link_pair(&mut self, ab: Pair) {
+        self.link(ab.fst(), ab.snd());
+    }
+
+    // Root
+
+    /// Current value of the root variable
+    pub fn root(&self) -> Port {
+        self.peek(Port::ROOT)
+    }
+
+    /// Resets the root variable and links it to `port`
+    pub fn set_root(&mut self, port: Port) {
+        self.root = Port::NONE;
+        self.link(Port::ROOT, port);
+    }
+
+    /// Raw node and variable buffers, reserved slot 0 included
+    pub(crate) fn buffers(&self) -> (&Pages<Pair>, &Pages<Port>) {
+        (&self.node_buf, &self.vars_buf)
+    }
+
+    /// Rebuilds a net from raw buffers, recomputing the free lists
+    pub(crate) fn from_buffers(
+        mut node_buf: Vec<Pair>,
+        mut vars_buf: Vec<Port>,
+        root: Port,
+        redexes: Vec<Pair>,
+    ) -> Self {
+        node_buf.truncate(node_buf.iter().rposition(|n| *n != Pair::FREE).unwrap_or(0) + 1);
+        vars_buf.truncate(vars_buf.iter().rposition(|v| *v != Port::FREE).unwrap_or(0) + 1);
+        node_buf[0] = Pair::FREE;
+        vars_buf[0] = Port::FREE;
+        let node_free = (1..node_buf.len() as Val)
+            .rev()
+            .filter(|&loc| node_buf[loc as usize] == Pair::FREE)
+            .collect();
+        let vars_free = (1..vars_buf.len() as Val)
+            .rev()
+            .filter(|&var| vars_buf[var as usize] == Port::FREE)
+            .collect();
+        Self {
+            node_buf: node_buf.into(),
+            vars_buf: vars_buf.into(),
+            node_free,
+            vars_free,
+            root,
+            redexes: redexes.into_iter().collect(),
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+        }
+    }
+
+    // Iteration
+
+    /// Iterates live nodes as `(loc, node)`
+    pub fn nodes(&self) -> impl Iterator<Item = (Val, Pair)> + '_ {
+        self.node_buf
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| *node != Pair::FREE)
+            .map(|(loc, node)| (loc as Val, node))
+    }
+
+    /// Iterates live variables as `(var, value)`
+    pub fn vars(&self) -> impl Iterator<Item = (Val, Port)> + '_ {
+        self.vars_buf
+            .iter()
+            .enumerate()
+            .filter(|(_, val)| *val != Port::FREE)
+            .map(|(var, val)| (var as Val, val))
+    }
+
+    // Stats
+
+    /// Number of live nodes
+    pub fn node_count(&self) -> usize {
+        self.node_buf.len() - 1 - self.node_free.len()
+    }
+
+    /// Number of live variables
+    pub fn vars_count(&self) -> usize {
+        self.vars_buf.len() - 1 - self.vars_free.len()
+    }
+}
+
+impl Clone for GNet {
+    fn clone(&self) -> Self {
+        Self {
+            node_buf: self.node_buf.clone(),
+            vars_buf: self.vars_buf.clone(),
+            node_free: self.node_free.clone(),
+            vars_free: self.vars_free.clone(),
+            root: self.root,
+            redexes: self.redexes.clone(),
+            checkpoints: Vec::new(),
+            next_checkpoint: 0,
+        }
+    }
+}
+
+impl PartialEq for GNet {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_buf == other.node_buf
+            && self.vars_buf == other.vars_buf
+            && self.node_free == other.node_free
+            && self.vars_free == other.vars_free
+            && self.root == other.root
+            && self.redexes == other.redexes
+    }
+}
+
+impl Default for GNet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -33,6 +312,75 @@ mod tests {
     #[test]
     fn test_gnet_creation() {
         let net = GNet::new();
-        assert_eq!(net.nodes.len(), 0);
+        assert_eq!(net.node_count(), 0);
+        assert_eq!(net.root(), Port::ROOT);
+    }
+
+    #[test]
+    fn test_node_alloc_reuses_freed_slots() {
+        let mut net = GNet::new();
+        let era = Port::new(Tag::Era, 0);
+        let a = net.node_alloc().unwrap();
+        let b = net.node_alloc().unwrap();
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+        net.node_create(a, Pair::new(era, era));
+        net.node_create(b, Pair::new(era, era));
+        assert_eq!(net.node_count(), 2);
+
+        assert_eq!(net.node_take(a), Pair::new(era, era));
+        assert_eq!(net.node_count(), 1);
+        assert_eq!(net.node_alloc().unwrap(), a);
+    }
+
+    #[test]
+    fn test_vars_alloc_reuses_freed_slots() {
+        let mut net = GNet::new();
+        let x = net.vars_alloc().unwrap();
+        net.vars_create(x, Port::NONE);
+        assert_eq!(net.vars_count(), 1);
+        net.vars_take(x);
+        assert_eq!(net.vars_count(), 0);
+        assert_eq!(net.vars_alloc().unwrap(), x);
+    }
+
+    #[test]
+    fn test_link_var_to_node() {
+        let mut net = GNet::new();
+        let x = net.vars_alloc().unwrap();
+        net.vars_create(x, Port::NONE);
+        let num = Port::new(Tag::Num, 7);
+
+        // First endpoint stores the substitution
+        net.link(Port::new(Tag::Var, x), num);
+        assert!(net.redexes.is_empty());
+        assert_eq!(net.peek(Port::new(Tag::Var, x)), num);
+
+        // Second endpoint resolves it and frees the variable
+        net.set_root(Port::new(Tag::Var, x));
+        assert_eq!(net.root(), num);
+        assert_eq!(net.vars_count(), 0);
+    }
+
+    #[test]
+    fn test_link_nodes_pushes_redex() {
+        let mut net = GNet::new();
+        let a = Port::new(Tag::Era, 0);
+        let b = Port::new(Tag::Num, 1);
+        net.link(a, b);
+        assert_eq!(net.redexes, vec![Pair::new(a, b)]);
+    }
+
+    #[test]
+    fn test_enter_follows_chain() {
+        let mut net = GNet::new();
+        let x = net.vars_alloc().unwrap();
+        let y = net.vars_alloc().unwrap();
+        let era = Port::new(Tag::Era, 0);
+        net.vars_create(x, Port::new(Tag::Var, y));
+        net.vars_create(y, era);
+        assert_eq!(net.peek(Port::new(Tag::Var, x)), era);
+        assert_eq!(net.enter(Port::new(Tag::Var, x)), era);
+        assert_eq!(net.vars_count(), 0);
     }
 }
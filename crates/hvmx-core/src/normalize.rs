@@ -0,0 +1,144 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: normalize.rs
+// Location: crates/hvmx-core/src/normalize.rs
+// Purpose: Sequential reference normalizer
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+use crate::{interact, Book, CoreError, GNet, Pair, Port, Result, Rule, Tag};
+
+/// Evaluation statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Interaction count (LINK is not an interaction, as in `hvm.c`)
+    pub interactions: u64,
+    /// Times each rule was applied, indexed by `Rule as usize`
+    pub rules: [u64; 8],
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, rule: Rule) {
+        self.rules[rule as usize] += 1;
+        if rule != Rule::Link {
+            self.interactions += 1;
+        }
+    }
+
+    /// Times a rule was applied
+    pub fn count(&self, rule: Rule) -> u64 {
+        self.rules[rule as usize]
+    }
+
+    /// Adds the counts of another run
+    pub fn merge(&mut self, other: &Stats) {
+        self.interactions += other.interactions;
+        for (a, b) in self.rules.iter_mut().zip(other.rules.iter()) {
+            *a += b;
+        }
+    }
+}
+
+impl GNet {
+    /// Sets the initial redex `@main ~ ROOT`
+    pub fn boot(&mut self, book: &Book) -> Result<()> {
+        let main = book.id("main").ok_or_else(|| CoreError::UndefinedDef("main".to_string()))?;
+        self.vars_create(Port::ROOT.val(), Port::NONE);
+        self.redexes.push(Pair::new(Port::new(Tag::Ref, main), Port::ROOT));
+        Ok(())
+    }
+
+    /// Reduces redexes until the bag is empty
+    pub fn reduce(&mut self, book: &Book) -> Result<Stats> {
+        let mut stats = Stats::new();
+        while let Some(redex) = self.redexes.pop() {
+            let rule = self.interact_popped(book, redex)?;
+            stats.record(rule);
+        }
+        Ok(stats)
+    }
+
+    /// Reduces a redex popped from the bag. A full bag fails before the
+    /// interaction, and the redex goes back.
+    pub(crate) fn interact_popped(&mut self, book: &Book, redex: Pair) -> Result<Rule> {
+        let result = interact(self, book, redex.fst(), redex.snd());
+        if let Err(CoreError::BagOverflow(_)) = result {
+            self.redexes.push(redex);
+        }
+        result
+    }
+
+    /// Boots `@main` and reduces it to normal form, single-threaded.
+    ///
+    /// This is the reference evaluator: every other backend must agree with it.
+    pub fn normalize(&mut self, book: &Book) -> Result<Stats> {
+        self.boot(book)?;
+        self.reduce(book)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus;
+
+    #[test]
+    fn test_normalize_constant() {
+        let mut net = GNet::new();
+        let stats = net.normalize(&corpus::constant()).unwrap();
+        assert_eq!(net.root(), corpus::num(7));
+        assert_eq!(stats.interactions, 1);
+        assert_eq!(stats.count(Rule::Call), 1);
+    }
+
+    #[test]
+    fn test_normalize_corpus() {
+        for (name, book, expected) in corpus::all() {
+            let mut net = GNet::new();
+            let stats = net.normalize(&book).unwrap();
+            assert_eq!(net.root(), corpus::num(expected), "{}", name);
+            assert!(net.redexes.is_empty(), "{}", name);
+            assert!(stats.interactions > 0, "{}", name);
+            assert_eq!(net.node_count(), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_normalize_is_deterministic() {
+        let book = corpus::sum(8);
+        let a = GNet::new().normalize(&book).unwrap();
+        let b = GNet::new().normalize(&book).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_normalize_without_main() {
+        let mut net = GNet::new();
+        let result = net.normalize(&Book::new());
+        assert_eq!(result, Err(CoreError::UndefinedDef("main".to_string())));
+    }
+
+    #[test]
+    fn test_stats_merge() {
+        let mut a = Stats::new();
+        a.record(Rule::Link);
+        a.record(Rule::Anni);
+        let mut b = Stats::new();
+        b.record(Rule::Anni);
+        a.merge(&b);
+        assert_eq!(a.interactions, 2);
+        assert_eq!(a.count(Rule::Anni), 2);
+        assert_eq!(a.count(Rule::Link), 1);
+    }
+}
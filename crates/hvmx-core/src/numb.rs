@@ -3,29 +3,353 @@
 // ==============================================================================
 // File: numb.rs
 // Location: crates/hvmx-core/src/numb.rs
-// Purpose: Numeric operations (60-bit floats)
+// Purpose: Numeric operations (typed 24-bit numbers)
 // Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
 // Date: 2024-12-28
 // License: MIT OR Apache-2.0
 // ==============================================================================
 
+//! HVM2 numbers, ported from `docs/dor/hvm.c`.
+//!
+//! A `Numb` is the value of a NUM port: a 24-bit payload over a 5-bit type.
+//! Types 1-3 are U24, I24 and F24 numbers. Type 0 (SYM) holds an operator or
+//! a cast target in its payload. Types 4 and up are operators partially
+//! applied to a payload, waiting for their second operand.
+//!
+//! Results are bit-exact with `hvm.c`, except that integer division and
+//! remainder by zero return 0 where C is undefined.
+
+use std::fmt;
 use std::ops::{Add, Sub, Mul, Div};
 
-/// Numb: 60-bit numeric type
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct Numb(pub u64);
+use crate::{Port, Val};
+
+/// Numb: typed 24-bit number, as stored in a NUM port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Numb(pub Val);
 
 impl Numb {
-    pub fn new(val: u64) -> Self {
-        Numb(val & 0x0FFFFFFFFFFFFFFF) // 60-bit mask
+    // Types
+    pub const TY_SYM: u32 = 0x00;
+    pub const TY_U24: u32 = 0x01;
+    pub const TY_I24: u32 = 0x02;
+    pub const TY_F24: u32 = 0x03;
+
+    // Operators (FP_* take their operands flipped)
+    pub const OP_ADD: u32 = 0x04;
+    pub const OP_SUB: u32 = 0x05;
+    pub const FP_SUB: u32 = 0x06;
+    pub const OP_MUL: u32 = 0x07;
+    pub const OP_DIV: u32 = 0x08;
+    pub const FP_DIV: u32 = 0x09;
+    pub const OP_REM: u32 = 0x0A;
+    pub const FP_REM: u32 = 0x0B;
+    pub const OP_EQ: u32 = 0x0C;
+    pub const OP_NEQ: u32 = 0x0D;
+    pub const OP_LT: u32 = 0x0E;
+    pub const OP_GT: u32 = 0x0F;
+    pub const OP_AND: u32 = 0x10;
+    pub const OP_OR: u32 = 0x11;
+    pub const OP_XOR: u32 = 0x12;
+    pub const OP_SHL: u32 = 0x13;
+    pub const FP_SHL: u32 = 0x14;
+    pub const OP_SHR: u32 = 0x15;
+    pub const FP_SHR: u32 = 0x16;
+
+    const U24_MAX: f32 = ((1 << 24) - 1) as f32;
+    const U24_MIN: f32 = 0.0;
+    const I24_MAX: f32 = ((1 << 23) - 1) as f32;
+    const I24_MIN: f32 = -(1 << 23) as f32;
+
+    /// Wraps a raw NUM port value
+    pub fn new(val: Val) -> Self {
+        Numb(val & Port::VAL_MAX)
+    }
+
+    // Constructors
+
+    /// Operator or cast selector
+    pub fn new_sym(val: u32) -> Self {
+        Numb::new((val << 5) | Self::TY_SYM)
+    }
+
+    /// Unsigned 24-bit integer (wraps)
+    pub fn new_u24(val: u32) -> Self {
+        Numb::new((val << 5) | Self::TY_U24)
+    }
+
+    /// Signed 24-bit integer (wraps)
+    pub fn new_i24(val: i32) -> Self {
+        Numb::new(((val as u32) << 5) | Self::TY_I24)
+    }
+
+    /// 24-bit float: an f32 with the low 8 mantissa bits rounded off
+    pub fn new_f24(val: f32) -> Self {
+        let bits = val.to_bits();
+        let nan = val.is_nan() as u32;
+        let mut shifted_bits = bits >> 8;
+        let lost_bits = bits & 0xFF;
+        // round (hvm.c says ties to even; the formula rounds half up)
+        shifted_bits += (1 - nan) & (lost_bits.wrapping_sub((lost_bits >> 7) & (shifted_bits == 0) as u32) >> 7);
+        // ensure NaNs don't become infinities
+        shifted_bits |= nan;
+        Numb::new((shifted_bits << 5) | Self::TY_F24)
+    }
+
+    // Getters
+
+    /// Type, or operator for partial applications
+    pub fn typ(&self) -> u32 {
+        self.0 & 0x1F
+    }
+
+    pub fn sym(&self) -> u32 {
+        self.0 >> 5
+    }
+
+    pub fn u24(&self) -> u32 {
+        self.0 >> 5
+    }
+
+    pub fn i24(&self) -> i32 {
+        ((self.0 as i32) << 3) >> 8
+    }
+
+    pub fn f24(&self) -> f32 {
+        f32::from_bits((self.0 << 3) & 0xFFFF_FF00)
+    }
+
+    /// True for U24, I24 and F24 values
+    pub fn is_num(&self) -> bool {
+        (Self::TY_U24..=Self::TY_F24).contains(&self.typ())
+    }
+
+    /// True for a SYM naming a number type
+    pub fn is_cast(&self) -> bool {
+        self.typ() == Self::TY_SYM && (Self::TY_U24..=Self::TY_F24).contains(&self.sym())
     }
 
+    /// Numeric value; NaN for symbols and partial applications
     pub fn to_f64(&self) -> f64 {
-        self.0 as f64
+        match self.typ() {
+            Self::TY_U24 => self.u24() as f64,
+            Self::TY_I24 => self.i24() as f64,
+            Self::TY_F24 => self.f24() as f64,
+            _ => f64::NAN,
+        }
     }
 
     pub fn from_f64(f: f64) -> Self {
-        Numb::new(f as u64)
+        Numb::new_f24(f as f32)
+    }
+
+    // Operations
+
+    /// Applies the operator in `a`'s payload to `b`
+    pub fn partial(a: Numb, b: Numb) -> Numb {
+        Numb::new((b.0 & !0x1F) | a.sym())
+    }
+
+    /// Casts `b` to the type in `a`'s payload.
+    ///
+    /// Like Rust's `as`: u24 and i24 reinterpret bits, floats saturate
+    /// (NaN becomes 0), integers go to the closest float.
+    pub fn cast(a: Numb, b: Numb) -> Numb {
+        match (a.sym(), b.typ()) {
+            (Self::TY_U24, Self::TY_U24) => b,
+            (Self::TY_U24, Self::TY_I24) => Numb::new_u24(b.i24() as u32),
+            (Self::TY_U24, Self::TY_F24) => {
+                let val = b.f24();
+                if val.is_nan() {
+                    return Numb::new_u24(0);
+                }
+                Numb::new_u24(val.clamp(Self::U24_MIN, Self::U24_MAX) as u32)
+            }
+            (Self::TY_I24, Self::TY_U24) => Numb::new_i24(b.u24() as i32),
+            (Self::TY_I24, Self::TY_I24) => b,
+            (Self::TY_I24, Self::TY_F24) => {
+                let val = b.f24();
+                if val.is_nan() {
+                    return Numb::new_i24(0);
+                }
+                Numb::new_i24(val.clamp(Self::I24_MIN, Self::I24_MAX) as i32)
+            }
+            (Self::TY_F24, Self::TY_U24) => Numb::new_f24(b.u24() as f32),
+            (Self::TY_F24, Self::TY_I24) => Numb::new_f24(b.i24() as f32),
+            (Self::TY_F24, Self::TY_F24) => b,
+            _ => Numb::new_u24(0),
+        }
+    }
+
+    /// Combines two NUM values, as `NUM a ~ $(NUM b r)` does
+    pub fn operate(a: Numb, b: Numb) -> Numb {
+        let at = a.typ();
+        let bt = b.typ();
+        if at == Self::TY_SYM && bt == Self::TY_SYM {
+            return Numb::new_u24(0);
+        }
+        if a.is_cast() && b.is_num() {
+            return Numb::cast(a, b);
+        }
+        if b.is_cast() && a.is_num() {
+            return Numb::cast(b, a);
+        }
+        if at == Self::TY_SYM && bt != Self::TY_SYM {
+            return Numb::partial(a, b);
+        }
+        if at != Self::TY_SYM && bt == Self::TY_SYM {
+            return Numb::partial(b, a);
+        }
+        if at >= Self::OP_ADD && bt >= Self::OP_ADD {
+            return Numb::new_u24(0);
+        }
+        if at < Self::OP_ADD && bt < Self::OP_ADD {
+            return Numb::new_u24(0);
+        }
+        let (op, ty, a, b) = if at >= Self::OP_ADD { (at, bt, a, b) } else { (bt, at, b, a) };
+        match ty {
+            Self::TY_U24 => {
+                let av = a.u24();
+                let bv = b.u24();
+                match op {
+                    Self::OP_ADD => Numb::new_u24(av.wrapping_add(bv)),
+                    Self::OP_SUB => Numb::new_u24(av.wrapping_sub(bv)),
+                    Self::FP_SUB => Numb::new_u24(bv.wrapping_sub(av)),
+                    Self::OP_MUL => Numb::new_u24(av.wrapping_mul(bv)),
+                    Self::OP_DIV => Numb::new_u24(av.checked_div(bv).unwrap_or(0)),
+                    Self::FP_DIV => Numb::new_u24(bv.checked_div(av).unwrap_or(0)),
+                    Self::OP_REM => Numb::new_u24(av.checked_rem(bv).unwrap_or(0)),
+                    Self::FP_REM => Numb::new_u24(bv.checked_rem(av).unwrap_or(0)),
+                    Self::OP_EQ => Numb::new_u24((av == bv) as u32),
+                    Self::OP_NEQ => Numb::new_u24((av != bv) as u32),
+                    Self::OP_LT => Numb::new_u24((av < bv) as u32),
+                    Self::OP_GT => Numb::new_u24((av > bv) as u32),
+                    Self::OP_AND => Numb::new_u24(av & bv),
+                    Self::OP_OR => Numb::new_u24(av | bv),
+                    Self::OP_XOR => Numb::new_u24(av ^ bv),
+                    Self::OP_SHL => Numb::new_u24(av << (bv & 31)),
+                    Self::FP_SHL => Numb::new_u24(bv << (av & 31)),
+                    Self::OP_SHR => Numb::new_u24(av >> (bv & 31)),
+                    Self::FP_SHR => Numb::new_u24(bv >> (av & 31)),
+                    _ => Numb::new_u24(0),
+                }
+            }
+            Self::TY_I24 => {
+                let av = a.i24();
+                let bv = b.i24();
+                match op {
+                    Self::OP_ADD => Numb::new_i24(av.wrapping_add(bv)),
+                    Self::OP_SUB => Numb::new_i24(av.wrapping_sub(bv)),
+                    Self::FP_SUB => Numb::new_i24(bv.wrapping_sub(av)),
+                    Self::OP_MUL => Numb::new_i24(av.wrapping_mul(bv)),
+                    Self::OP_DIV => Numb::new_i24(av.checked_div(bv).unwrap_or(0)),
+                    Self::FP_DIV => Numb::new_i24(bv.checked_div(av).unwrap_or(0)),
+                    Self::OP_REM => Numb::new_i24(av.checked_rem(bv).unwrap_or(0)),
+                    Self::FP_REM => Numb::new_i24(bv.checked_rem(av).unwrap_or(0)),
+                    Self::OP_EQ => Numb::new_u24((av == bv) as u32),
+                    Self::OP_NEQ => Numb::new_u24((av != bv) as u32),
+                    Self::OP_LT => Numb::new_u24((av < bv) as u32),
+                    Self::OP_GT => Numb::new_u24((av > bv) as u32),
+                    Self::OP_AND => Numb::new_i24(av & bv),
+                    Self::OP_OR => Numb::new_i24(av | bv),
+                    Self::OP_XOR => Numb::new_i24(av ^ bv),
+                    _ => Numb::new_i24(0),
+                }
+            }
+            Self::TY_F24 => {
+                let av = a.f24();
+                let bv = b.f24();
+                match op {
+                    Self::OP_ADD => Numb::new_f24(av + bv),
+                    Self::OP_SUB => Numb::new_f24(av - bv),
+                    Self::FP_SUB => Numb::new_f24(bv - av),
+                    Self::OP_MUL => Numb::new_f24(av * bv),
+                    Self::OP_DIV => Numb::new_f24(av / bv),
+                    Self::FP_DIV => Numb::new_f24(bv / av),
+                    Self::OP_REM => Numb::new_f24(av % bv),
+                    Self::FP_REM => Numb::new_f24(bv % av),
+                    Self::OP_EQ => Numb::new_u24((av == bv) as u32),
+                    Self::OP_NEQ => Numb::new_u24((av != bv) as u32),
+                    Self::OP_LT => Numb::new_u24((av < bv) as u32),
+                    Self::OP_GT => Numb::new_u24((av > bv) as u32),
+                    Self::OP_AND => Numb::new_f24(av.atan2(bv)),
+                    Self::OP_OR => Numb::new_f24(bv.ln() / av.ln()),
+                    Self::OP_XOR => Numb::new_f24(av.powf(bv)),
+                    // hvm.c calls the double versions here
+                    Self::OP_SHL => Numb::new_f24(((av + bv) as f64).sin() as f32),
+                    Self::OP_SHR => Numb::new_f24(((av + bv) as f64).tan() as f32),
+                    _ => Numb::new_f24(0.0),
+                }
+            }
+            _ => Numb::new_u24(0),
+        }
+    }
+
+    /// Applies `op` to two numbers of the same type
+    pub fn apply(op: u32, a: Numb, b: Numb) -> Numb {
+        Numb::operate(Numb::partial(Numb::new_sym(op), a), b)
+    }
+}
+
+/// Operator and cast symbols, longest first
+pub(crate) const SYMBOLS: [(&str, u32); 22] = [
+    (":<<", Numb::FP_SHL),
+    (":>>", Numb::FP_SHR),
+    (":-", Numb::FP_SUB),
+    (":/", Numb::FP_DIV),
+    (":%", Numb::FP_REM),
+    ("<<", Numb::OP_SHL),
+    (">>", Numb::OP_SHR),
+    ("u24", Numb::TY_U24),
+    ("i24", Numb::TY_I24),
+    ("f24", Numb::TY_F24),
+    ("+", Numb::OP_ADD),
+    ("-", Numb::OP_SUB),
+    ("*", Numb::OP_MUL),
+    ("/", Numb::OP_DIV),
+    ("%", Numb::OP_REM),
+    ("=", Numb::OP_EQ),
+    ("!", Numb::OP_NEQ),
+    ("<", Numb::OP_LT),
+    (">", Numb::OP_GT),
+    ("&", Numb::OP_AND),
+    ("|", Numb::OP_OR),
+    ("^", Numb::OP_XOR),
+];
+
+/// Symbol of an operator or cast
+pub fn symbol(sym: u32) -> Option<&'static str> {
+    SYMBOLS.iter().find(|(_, s)| *s == sym).map(|(name, _)| *name)
+}
+
+/// Numbers print in the syntax the parser reads: `7`, `+7`, `-7`, `7.0`,
+/// `[+]`, `[+7]`, `[u24]`
+impl fmt::Display for Numb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.typ() {
+            Self::TY_SYM => match symbol(self.sym()) {
+                Some(sym) => write!(f, "[{}]", sym),
+                None => write!(f, "[?{}]", self.sym()),
+            },
+            Self::TY_U24 => write!(f, "{}", self.u24()),
+            Self::TY_I24 => write!(f, "{:+}", self.i24()),
+            Self::TY_F24 => {
+                let val = self.f24();
+                if val.is_nan() {
+                    write!(f, "+NaN")
+                } else if val.is_infinite() {
+                    write!(f, "{}inf", if val > 0.0 { "+" } else { "-" })
+                } else if val.fract() == 0.0 {
+                    write!(f, "{:.1}", val)
+                } else {
+                    write!(f, "{}", val)
+                }
+            }
+            op => match symbol(op) {
+                Some(sym) => write!(f, "[{}{}]", sym, self.u24()),
+                None => write!(f, "[?{}]", self.0),
+            },
+        }
     }
 }
 
@@ -33,37 +357,33 @@ impl Numb {
 
 impl Add for Numb {
     type Output = Numb;
-    
+
     fn add(self, other: Numb) -> Numb {
-        Numb::new(self.0.wrapping_add(other.0))
+        Numb::apply(Numb::OP_ADD, self, other)
     }
 }
 
 impl Sub for Numb {
     type Output = Numb;
-    
+
     fn sub(self, other: Numb) -> Numb {
-        Numb::new(self.0.wrapping_sub(other.0))
+        Numb::apply(Numb::OP_SUB, self, other)
     }
 }
 
 impl Mul for Numb {
     type Output = Numb;
-    
+
     fn mul(self, other: Numb) -> Numb {
-        Numb::new(self.0.wrapping_mul(other.0))
+        Numb::apply(Numb::OP_MUL, self, other)
     }
 }
 
 impl Div for Numb {
     type Output = Numb;
-    
+
     fn div(self, other: Numb) -> Numb {
-        if other.0 == 0 {
-            Numb(0)
-        } else {
-            Numb::new(self.0 / other.0)
-        }
+        Numb::apply(Numb::OP_DIV, self, other)
     }
 }
 
@@ -75,49 +395,257 @@ impl Div for Numb {
 mod tests {
     use super::*;
 
+    fn u24(val: u32) -> Numb {
+        Numb::new_u24(val)
+    }
+
+    fn i24(val: i32) -> Numb {
+        Numb::new_i24(val)
+    }
+
+    fn f24(val: f32) -> Numb {
+        Numb::new_f24(val)
+    }
+
+    fn op(op: u32, val: Numb) -> Numb {
+        Numb::partial(Numb::new_sym(op), val)
+    }
+
     #[test]
     fn test_numb_creation() {
-        let n = Numb::new(42);
-        assert_eq!(n.0, 42);
+        let n = Numb::new_u24(42);
+        assert_eq!(n.0, (42 << 5) | Numb::TY_U24);
+        assert_eq!(n.u24(), 42);
+        assert!(n.is_num());
+        assert_eq!(Numb::new(u32::MAX).0, Port::VAL_MAX);
     }
 
     #[test]
     fn test_numb_add() {
-        let a = Numb::new(10);
-        let b = Numb::new(20);
+        let a = u24(10);
+        let b = u24(20);
         let c = a + b;
-        assert_eq!(c.0, 30);
+        assert_eq!(c.u24(), 30);
     }
 
     #[test]
     fn test_numb_sub() {
-        let a = Numb::new(50);
-        let b = Numb::new(20);
+        let a = u24(50);
+        let b = u24(20);
         let c = a - b;
-        assert_eq!(c.0, 30);
+        assert_eq!(c.u24(), 30);
     }
 
     #[test]
     fn test_numb_mul() {
-        let a = Numb::new(5);
-        let b = Numb::new(3);
+        let a = u24(5);
+        let b = u24(3);
         let c = a * b;
-        assert_eq!(c.0, 15);
+        assert_eq!(c.u24(), 15);
     }
 
     #[test]
     fn test_numb_div() {
-        let a = Numb::new(20);
-        let b = Numb::new(4);
+        let a = u24(20);
+        let b = u24(4);
         let c = a / b;
-        assert_eq!(c.0, 5);
+        assert_eq!(c.u24(), 5);
     }
 
     #[test]
     fn test_numb_div_by_zero() {
-        let a = Numb::new(10);
-        let b = Numb::new(0);
+        let a = u24(10);
+        let b = u24(0);
         let c = a / b;
-        assert_eq!(c.0, 0); // Safe zero division
+        assert_eq!(c.u24(), 0); // Safe zero division
+        assert_eq!((i24(-10) / i24(0)).i24(), 0);
+    }
+
+    #[test]
+    fn test_numb_wrapping() {
+        assert_eq!((u24(0) - u24(1)).u24(), 0xFF_FFFF);
+        assert_eq!((u24(0xFF_FFFF) + u24(1)).u24(), 0);
+        assert_eq!((i24(0x7F_FFFF) + i24(1)).i24(), -0x80_0000);
+        assert_eq!(i24(-5).i24(), -5);
+    }
+
+    #[test]
+    fn test_numb_f24_layout() {
+        // Reference encodings computed with hvm.c's new_f24
+        assert_eq!(f24(1.0).0, 0x07F0_0003);
+        assert_eq!(f24(-2.5).0, 0x1804_0003);
+        assert_eq!(f24(0.1).0, 0x07B9_99A3);
+        assert_eq!(i24(-1).0, 0x1FFF_FFE2);
+        assert_eq!(f24(1.0).f24(), 1.0);
+        assert_eq!(f24(0.1).f24(), f32::from_bits(0x3DCC_CD00));
+        assert!(f24(f32::NAN).f24().is_nan());
+        assert_eq!(f24(f32::INFINITY).f24(), f32::INFINITY);
+    }
+
+    #[test]
+    fn test_numb_f24_rounding() {
+        // Reference encodings computed with hvm.c's new_f24, which rounds
+        // half up despite its comment
+        let f = |bits| f24(f32::from_bits(bits)).0;
+        assert_eq!(f(0x3F80_0080), 0x07F0_0023);
+        assert_eq!(f(0x3F80_0180), 0x07F0_0043);
+        assert_eq!(f(0x3F80_0081), 0x07F0_0023);
+        assert_eq!(f(0x3F80_007F), 0x07F0_0003);
+        assert_eq!(f(0x0000_0080), 0x0000_0003);
+        assert_eq!(f(0xFF80_0000), 0x1FF0_0003);
+    }
+
+    #[test]
+    fn test_numb_partial() {
+        let add3 = op(Numb::OP_ADD, u24(3));
+        assert_eq!(add3.typ(), Numb::OP_ADD);
+        assert_eq!(add3.u24(), 3);
+        assert_eq!(Numb::operate(add3, u24(4)).u24(), 7);
+        assert_eq!(Numb::operate(u24(4), add3).u24(), 7);
+        // A bare operator is partially applied by the number it meets
+        assert_eq!(Numb::operate(Numb::new_sym(Numb::OP_MUL), u24(6)), op(Numb::OP_MUL, u24(6)));
+        assert_eq!(Numb::operate(u24(6), Numb::new_sym(Numb::OP_MUL)), op(Numb::OP_MUL, u24(6)));
+    }
+
+    #[test]
+    fn test_numb_flipped_ops() {
+        let cases = [
+            (Numb::OP_SUB, Numb::FP_SUB, 2),
+            (Numb::OP_DIV, Numb::FP_DIV, 2),
+            (Numb::OP_REM, Numb::FP_REM, 2),
+            (Numb::OP_SHL, Numb::FP_SHL, 2),
+            (Numb::OP_SHR, Numb::FP_SHR, 2),
+        ];
+        for (op_, fp, b) in cases {
+            let a = 20;
+            let direct = Numb::operate(op(op_, u24(a)), u24(b));
+            let flipped = Numb::operate(op(fp, u24(b)), u24(a));
+            assert_eq!(direct, flipped, "op {:#x}", op_);
+        }
+        assert_eq!(Numb::operate(op(Numb::FP_SUB, u24(2)), u24(20)).u24(), 18);
+    }
+
+    #[test]
+    fn test_numb_u24_table() {
+        let t = |o, a, b| Numb::operate(op(o, u24(a)), u24(b)).u24();
+        assert_eq!(t(Numb::OP_ADD, 7, 5), 12);
+        assert_eq!(t(Numb::OP_SUB, 7, 5), 2);
+        assert_eq!(t(Numb::OP_MUL, 7, 5), 35);
+        assert_eq!(t(Numb::OP_DIV, 7, 5), 1);
+        assert_eq!(t(Numb::OP_REM, 7, 5), 2);
+        assert_eq!(t(Numb::OP_EQ, 7, 5), 0);
+        assert_eq!(t(Numb::OP_NEQ, 7, 5), 1);
+        assert_eq!(t(Numb::OP_LT, 7, 5), 0);
+        assert_eq!(t(Numb::OP_GT, 7, 5), 1);
+        assert_eq!(t(Numb::OP_AND, 6, 3), 2);
+        assert_eq!(t(Numb::OP_OR, 6, 3), 7);
+        assert_eq!(t(Numb::OP_XOR, 6, 3), 5);
+        assert_eq!(t(Numb::OP_SHL, 1, 23), 0x80_0000);
+        assert_eq!(t(Numb::OP_SHL, 1, 24), 0);
+        assert_eq!(t(Numb::OP_SHR, 0x80_0000, 23), 1);
+    }
+
+    #[test]
+    fn test_numb_i24_table() {
+        let t = |o, a, b| Numb::operate(op(o, i24(a)), i24(b));
+        assert_eq!(t(Numb::OP_ADD, -7, 5).i24(), -2);
+        assert_eq!(t(Numb::OP_SUB, -7, 5).i24(), -12);
+        assert_eq!(t(Numb::OP_MUL, -7, 5).i24(), -35);
+        assert_eq!(t(Numb::OP_DIV, -7, 2).i24(), -3);
+        assert_eq!(t(Numb::OP_REM, -7, 2).i24(), -1);
+        assert_eq!(t(Numb::OP_LT, -7, 5), u24(1));
+        assert_eq!(t(Numb::OP_AND, -1, 5).i24(), 5);
+        assert_eq!(t(Numb::OP_SHL, 1, 2), i24(0));
+    }
+
+    #[test]
+    fn test_numb_f24_table() {
+        let t = |o, a, b| Numb::operate(op(o, f24(a)), f24(b));
+        assert_eq!(t(Numb::OP_ADD, 1.5, 2.25).f24(), 3.75);
+        assert_eq!(t(Numb::OP_DIV, 1.0, 4.0).f24(), 0.25);
+        assert_eq!(t(Numb::OP_REM, 7.5, 2.0).f24(), 1.5);
+        assert_eq!(t(Numb::OP_GT, 7.5, 2.0), u24(1));
+        assert_eq!(t(Numb::OP_XOR, 2.0, 10.0).f24(), 1024.0);
+        assert_eq!(t(Numb::OP_AND, 0.0, 1.0).f24(), 0.0);
+        assert_eq!(t(Numb::OP_OR, 2.0, 8.0).f24(), 3.0);
+        assert!(t(Numb::OP_DIV, 0.0, 0.0).f24().is_nan());
+    }
+
+    #[test]
+    fn test_numb_matches_hvm_c() {
+        // Reference results computed with hvm.c's operate
+        let t = |o, a, b| Numb::operate(op(o, f24(a)), f24(b)).0;
+        assert_eq!(t(Numb::OP_SHL, 0.5, 0.25), 0x07E5_D003);
+        assert_eq!(t(Numb::OP_SHR, 0.5, 0.25), 0x07ED_CFA3);
+        assert_eq!(t(Numb::OP_OR, 3.0, 7.0), 0x07FC_5703);
+        assert_eq!(t(Numb::OP_AND, 1.0, 2.0), 0x07DD_AC63);
+        assert_eq!(t(Numb::OP_DIV, 1.0, 3.0), 0x07D5_5563);
+    }
+
+    #[test]
+    fn test_numb_cast() {
+        let to = |ty, n| Numb::operate(Numb::new_sym(ty), n);
+        assert_eq!(to(Numb::TY_U24, i24(-1)).u24(), 0xFF_FFFF);
+        assert_eq!(to(Numb::TY_I24, u24(0xFF_FFFF)).i24(), -1);
+        assert_eq!(to(Numb::TY_U24, f24(-3.5)).u24(), 0);
+        assert_eq!(to(Numb::TY_U24, f24(1e9)).u24(), 0xFF_FFFF);
+        assert_eq!(to(Numb::TY_U24, f24(f32::NAN)).u24(), 0);
+        assert_eq!(to(Numb::TY_I24, f24(-3.5)).i24(), -3);
+        assert_eq!(to(Numb::TY_I24, f24(-1e9)).i24(), -0x80_0000);
+        assert_eq!(to(Numb::TY_F24, u24(3)).f24(), 3.0);
+        assert_eq!(to(Numb::TY_F24, i24(-3)).f24(), -3.0);
+        assert_eq!(to(Numb::TY_F24, f24(0.5)), f24(0.5));
+        // Casts apply from either side
+        assert_eq!(Numb::operate(i24(-1), Numb::new_sym(Numb::TY_U24)).u24(), 0xFF_FFFF);
+    }
+
+    #[test]
+    fn test_numb_degenerate() {
+        let sym = Numb::new_sym(Numb::OP_ADD);
+        assert_eq!(Numb::operate(sym, sym), u24(0));
+        assert_eq!(Numb::operate(u24(1), u24(2)), u24(0));
+        let add = op(Numb::OP_ADD, u24(1));
+        assert_eq!(Numb::operate(add, add), u24(0));
+    }
+
+    #[test]
+    fn test_numb_display() {
+        assert_eq!(u24(7).to_string(), "7");
+        assert_eq!(i24(7).to_string(), "+7");
+        assert_eq!(i24(-7).to_string(), "-7");
+        assert_eq!(f24(7.0).to_string(), "7.0");
+        assert_eq!(f24(-0.5).to_string(), "-0.5");
+        assert_eq!(f24(1e20).to_string(), "100000177925948178432.0");
+        assert_eq!(f24(f32::NEG_INFINITY).to_string(), "-inf");
+        assert_eq!(f24(f32::NAN).to_string(), "+NaN");
+        assert_eq!(Numb::new_sym(Numb::FP_SHL).to_string(), "[:<<]");
+        assert_eq!(Numb::new_sym(Numb::TY_F24).to_string(), "[f24]");
+        assert_eq!(op(Numb::OP_ADD, u24(7)).to_string(), "[+7]");
+    }
+
+    #[test]
+    fn test_numb_display_roundtrip() {
+        let numbs = [
+            u24(0xFF_FFFF),
+            i24(-0x80_0000),
+            f24(0.1),
+            f24(-1e-30),
+            f24(3.0e38),
+            f24(-0.0),
+            op(Numb::FP_SUB, i24(-3)),
+            Numb::new_sym(Numb::OP_NEQ),
+        ];
+        for numb in numbs {
+            let text = numb.to_string();
+            assert_eq!(crate::parse::Parser::new(&text).numb().unwrap(), numb, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_numb_to_f64() {
+        assert_eq!(u24(7).to_f64(), 7.0);
+        assert_eq!(i24(-7).to_f64(), -7.0);
+        assert_eq!(Numb::from_f64(0.5).to_f64(), 0.5);
+        assert!(Numb::new_sym(Numb::OP_ADD).to_f64().is_nan());
     }
 }
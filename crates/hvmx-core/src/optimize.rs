@@ -0,0 +1,282 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: optimize.rs
+// Location: crates/hvmx-core/src/optimize.rs
+// Purpose: Book-level optimizer
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Ahead-of-time work on a book.
+//!
+//! Every redex in a definition is reduced each time the definition is
+//! called, so reducing it once in the template gives the same result (nets
+//! are confluent) and saves that work on every call. Commutations are left
+//! alone since they grow the net, and only small definitions that are not
+//! part of a cycle are called ahead of time, so every pass terminates.
+//! Aliases, `@a = @b`, are then removed and their uses pointed at `@b`.
+
+use std::collections::HashMap;
+
+use crate::book::{Def, Template};
+use crate::interact::get_rule;
+use crate::{interact, Book, Result, Rule, Stats, Tag, Val};
+
+/// Optimizer configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptConfig {
+    /// Remove `@a = @b` definitions
+    pub aliases: bool,
+    /// Largest definition, in nodes, that may be called ahead of time;
+    /// 0 turns inlining off
+    pub inline_size: usize,
+    /// Reduce redexes that need no caller
+    pub pre_reduce: bool,
+    /// Interactions allowed per definition
+    pub max_interactions: u64,
+}
+
+impl OptConfig {
+    pub fn new() -> Self {
+        Self {
+            aliases: true,
+            inline_size: 16,
+            pre_reduce: true,
+            max_interactions: 1 << 12,
+        }
+    }
+}
+
+impl Default for OptConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the optimizer did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptReport {
+    /// Removed aliases, as `(alias, target)`
+    pub aliases: Vec<(String, String)>,
+    /// Calls expanded ahead of time
+    pub inlined: u64,
+    /// Interactions done ahead of time, calls included
+    pub reduced: Stats,
+}
+
+impl OptReport {
+    /// Interactions no longer done on each call of the affected definitions
+    pub fn saved(&self) -> u64 {
+        self.reduced.interactions
+    }
+}
+
+impl Book {
+    /// Optimizes every definition in place. `@main` keeps fid 0.
+    pub fn optimize(&mut self, config: &OptConfig) -> Result<OptReport> {
+        self.optimize_keeping(config, &[])
+    }
+
+    /// `optimize`, never inlining the definitions named in `keep`
+    pub(crate) fn optimize_keeping(&mut self, config: &OptConfig, keep: &[&str]) -> Result<OptReport> {
+        let mut report = OptReport::default();
+        if config.pre_reduce || config.inline_size > 0 {
+            *self = self.pre_reduce(config, keep, &mut report)?;
+        }
+        if config.aliases {
+            self.remove_aliases(&mut report)?;
+        }
+        self.propagate_safety();
+        Ok(report)
+    }
+
+    /// Reduces each definition's redexes against the book as it is
+    fn pre_reduce(&self, config: &OptConfig, keep: &[&str], report: &mut OptReport) -> Result<Book> {
+        let mut inline = vec![false; self.len()];
+        for (fid, def) in self.iter() {
+            let size = self.template(fid).unwrap().node.len();
+            let kept = keep.contains(&def.name.as_str());
+            inline[fid as usize] = !kept && config.inline_size > 0 && size <= config.inline_size;
+        }
+        for cycle in self.cycles() {
+            for fid in cycle {
+                inline[fid as usize] = false;
+            }
+        }
+
+        let mut book = Book::new();
+        for (fid, def) in self.iter() {
+            let mut net = def.net.clone();
+            let mut budget = config.max_interactions;
+            let mut kept = Vec::new();
+            while let Some(redex) = net.redexes.pop() {
+                let (a, b) = (redex.fst(), redex.snd());
+                let allowed = match get_rule(a, b) {
+                    Rule::Call => {
+                        let r = if a.tag() == Tag::Ref { a } else { b };
+                        r.val() != fid && inline.get(r.val() as usize).copied().unwrap_or(false)
+                    }
+                    Rule::Comm => false,
+                    _ => config.pre_reduce,
+                };
+                if !allowed || budget == 0 {
+                    kept.push(redex);
+                    continue;
+                }
+                budget -= 1;
+                let rule = interact(&mut net, self, a, b)?;
+                if rule == Rule::Call {
+                    report.inlined += 1;
+                }
+                report.reduced.record(rule);
+            }
+            kept.reverse();
+            net.redexes.extend(kept);
+
+            let tmpl = Template::new(&net);
+            let def = Def {
+                name: def.name.clone(),
+                arity: tmpl.arity(),
+                net: tmpl.to_net(),
+            };
+            book.insert(def.name.clone(), def);
+        }
+        Ok(book)
+    }
+
+    /// Drops `@a = @b` definitions, pointing their uses at the end of the
+    /// alias chain. `@main` and aliases that loop are kept.
+    fn remove_aliases(&mut self, report: &mut OptReport) -> Result<()> {
+        let alias = |fid: Val| {
+            let tmpl = self.template(fid).unwrap();
+            let target = (tmpl.node.is_empty() && tmpl.rbag.is_empty() && tmpl.root.tag() == Tag::Ref)
+                .then_some(tmpl.root.val());
+            target.filter(|&target| (target as usize) < self.len())
+        };
+        let main = self.id("main");
+        let mut target: HashMap<Val, Val> = HashMap::new();
+        for (fid, _) in self.iter() {
+            if Some(fid) == main || alias(fid).is_none() {
+                continue;
+            }
+            // Follow the chain, giving up if it loops
+            let mut end = fid;
+            let mut steps = 0;
+            while let Some(next) = alias(end) {
+                end = next;
+                steps += 1;
+                if steps > self.len() {
+                    break;
+                }
+            }
+            if steps <= self.len() {
+                target.insert(fid, end);
+            }
+        }
+        if target.is_empty() {
+            return Ok(());
+        }
+
+        let keep: Vec<Val> = self.iter().map(|(fid, _)| fid).filter(|fid| !target.contains_key(fid)).collect();
+        let mut fids: HashMap<Val, Val> = keep.iter().enumerate().map(|(new, &old)| (old, new as Val)).collect();
+        let mut aliases: Vec<(Val, Val)> = target.into_iter().collect();
+        aliases.sort_unstable();
+        for &(fid, end) in &aliases {
+            fids.insert(fid, fids[&end]);
+            let name = |fid: Val| self.get_by_id(fid).unwrap().name.clone();
+            report.aliases.push((name(fid), name(end)));
+        }
+        *self = self.remap(&keep, &fids)?;
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, GNet};
+
+    fn run(book: &Book) -> (String, Stats) {
+        let mut net = GNet::new();
+        let stats = net.normalize(book).unwrap();
+        (net.display(book).to_string(), stats)
+    }
+
+    #[test]
+    fn test_optimize_aliases() {
+        let mut book = Book::parse("@main = r & @a ~ (1 r)\n@a = @b\n@b = @id\n@id = (x x)\n@loop = @loop").unwrap();
+        let config = OptConfig {
+            pre_reduce: false,
+            inline_size: 0,
+            ..OptConfig::new()
+        };
+        let report = book.optimize(&config).unwrap();
+        assert_eq!(
+            report.aliases,
+            vec![("a".to_string(), "id".to_string()), ("b".to_string(), "id".to_string())]
+        );
+        assert_eq!(book.to_string(), "@main = a\n  & @id ~ (1 a)\n@id = (a a)\n@loop = @loop\n");
+    }
+
+    #[test]
+    fn test_optimize_folds_constants() {
+        let mut book = Book::parse("@main = r & [+] ~ $(2 $(3 r))").unwrap();
+        let report = book.optimize(&OptConfig::new()).unwrap();
+        assert_eq!(book.to_string(), "@main = 5\n");
+        assert_eq!(report.saved(), 2);
+        assert_eq!(report.inlined, 0);
+    }
+
+    #[test]
+    fn test_optimize_inlines_small_definitions() {
+        let src = "@main = r & @sum ~ (5 r)\n@sum = (?((0 @sum.s) r) r)\n\
+                   @sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & @inc ~ (p1 q)\n\
+                   @inc = (x y) & [+1] ~ $(x y)";
+        let mut book = Book::parse(src).unwrap();
+        let (expected, before) = run(&book);
+        let report = book.optimize(&OptConfig::new()).unwrap();
+        // @inc is inlined into @sum.s, @sum is recursive and stays a call
+        assert_eq!(report.inlined, 1);
+        assert_eq!(
+            book.get("sum.s").unwrap().to_string(),
+            "@sum.s = ({a $([+1] b)} c)\n  & @1 ~ (a $([+] $(b c)))"
+        );
+        let (result, after) = run(&book);
+        assert_eq!(result, expected);
+        assert!(after.interactions < before.interactions);
+        assert!(report.saved() > 0);
+    }
+
+    #[test]
+    fn test_optimize_keeps_recursion_and_budget() {
+        let mut book = Book::parse("@main = r & @loop ~ (0 r)\n@loop = (x r) & @loop ~ (x r)").unwrap();
+        let report = book.optimize(&OptConfig::new()).unwrap();
+        assert_eq!(report.inlined, 0);
+        assert_eq!(report.saved(), 0);
+
+        let mut book = Book::parse("@main = r & [+] ~ $(2 $(3 r))").unwrap();
+        let config = OptConfig {
+            max_interactions: 1,
+            ..OptConfig::new()
+        };
+        assert_eq!(book.optimize(&config).unwrap().saved(), 1);
+        assert_eq!(run(&book).0, "5");
+    }
+
+    #[test]
+    fn test_optimize_preserves_corpus() {
+        for (name, book, _) in corpus::all() {
+            let (expected, _) = run(&book);
+            let mut optimized = book.clone();
+            optimized.optimize(&OptConfig::new()).unwrap();
+            assert_eq!(optimized.id("main"), Some(0), "{}", name);
+            assert_eq!(run(&optimized).0, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,726 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: parallel.rs
+// Location: crates/hvmx-core/src/parallel.rs
+// Purpose: Lock-free multi-threaded CPU evaluator
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Multi-threaded evaluator, modeled on the evaluator in `docs/dor/hvm.c`.
+//!
+//! Each thread owns a slice of the node and vars buffers (where it allocates),
+//! a local bag of high-priority redexes, and a slice of the shared redex
+//! buffer that idle neighbours steal from. Heap cells are atomics, and the
+//! `link` algorithm makes every substitution a single atomic exchange.
+//!
+//! Priority classes and capacities come from the net's `BagConfig`: each
+//! thread's local bag holds its share of the high capacity, and the low
+//! capacity bounds the redexes in the shared buffer, spilled ones included.
+//! An interaction that might not fit fails with `CoreError::BagOverflow`,
+//! which, like running out of memory, leaves the net as it was.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::book::{adjust_pair, adjust_port, Template};
+use crate::interact::{get_rule, operate, should_swap};
+use crate::{Book, CoreError, GNet, Numb, Pair, Port, Result, Rule, Stats, Tag, Val};
+
+/// Atomic port (heap variable cell)
+pub type APort = AtomicU32;
+/// Atomic pair (heap node cell)
+pub type APair = AtomicU64;
+
+/// Max high-priority redexes per thread, when the high bag is unbounded
+const HLEN: usize = 1 << 16;
+
+/// Parallel evaluator configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParConfig {
+    /// Worker threads
+    pub threads: usize,
+    /// Node buffer length, split evenly between threads
+    pub node_len: usize,
+    /// Vars buffer length, split evenly between threads
+    pub vars_len: usize,
+    /// Stealable redex slots per thread
+    pub rbag_len: usize,
+}
+
+impl ParConfig {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            node_len: 1 << 22,
+            vars_len: 1 << 22,
+            rbag_len: 1 << 20,
+        }
+    }
+}
+
+impl Default for ParConfig {
+    /// One thread per available core
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+/// Local thread memory
+struct TM {
+    tid: usize,
+    stats: Stats,
+    nput: usize,      // next node allocation attempt index
+    vput: usize,      // next vars allocation attempt index
+    rput: usize,      // next rbag push index
+    sidx: usize,      // steal index
+    nloc: Vec<Val>,   // global node allocation indices
+    vloc: Vec<Val>,   // global vars allocation indices
+    hbag: Vec<Pair>,  // high-priority redexes
+}
+
+impl TM {
+    fn new(tid: usize) -> Self {
+        Self {
+            tid,
+            stats: Stats::new(),
+            nput: 1,
+            vput: 1,
+            rput: 0,
+            sidx: 0,
+            nloc: Vec::new(),
+            vloc: Vec::new(),
+            hbag: Vec::new(),
+        }
+    }
+}
+
+/// Shared heap
+struct Net {
+    node_buf: Vec<APair>,
+    vars_buf: Vec<APort>,
+    root: APort,
+    rbag_buf: Vec<APair>,
+    rlen: usize,
+    /// High class membership, indexed by `Rule`
+    high: [bool; 8],
+    /// Local high-priority redexes per thread
+    hlen: usize,
+    /// Low bag capacity, and the redexes in `rbag_buf` when it is bounded
+    low_len: Option<usize>,
+    low: AtomicUsize,
+    threads: usize,
+    idle: AtomicUsize,
+    halt: AtomicBool,
+    error: Mutex<Option<CoreError>>,
+    tmpls: Vec<Template>,
+    refuse_unsafe: bool,
+}
+
+impl Net {
+    fn new(net: &GNet, book: &Book, config: &ParConfig, refuse_unsafe: bool) -> Self {
+        let threads = config.threads.max(1);
+        let bag = net.redexes.config();
+        let (node_buf, vars_buf) = net.buffers();
+        // Imported cells must fit, with room to grow in every region
+        let node_len = config.node_len.max(node_buf.len() * 2).div_ceil(threads) * threads;
+        let vars_len = config.vars_len.max(vars_buf.len() * 2).div_ceil(threads) * threads;
+
+        let mut nodes: Vec<APair> = (0..node_len).map(|_| APair::new(0)).collect();
+        for (cell, node) in nodes.iter_mut().zip(node_buf.iter()) {
+            *cell.get_mut() = u64::from(node);
+        }
+        let mut vars: Vec<APort> = (0..vars_len).map(|_| APort::new(0)).collect();
+        for (cell, var) in vars.iter_mut().zip(vars_buf.iter()) {
+            *cell.get_mut() = u32::from(var);
+        }
+
+        Self {
+            node_buf: nodes,
+            vars_buf: vars,
+            root: APort::new(u32::from(net.vars_load(Port::ROOT.val()))),
+            rbag_buf: (0..config.rbag_len.max(1) * threads).map(|_| APair::new(0)).collect(),
+            rlen: config.rbag_len.max(1),
+            high: bag.high,
+            hlen: bag.high_len.map_or(HLEN, |len| len / threads),
+            low_len: bag.low_len,
+            low: AtomicUsize::new(0),
+            threads,
+            idle: AtomicUsize::new(0),
+            halt: AtomicBool::new(false),
+            error: Mutex::new(None),
+            tmpls: (0..book.len() as Val).filter_map(|fid| book.template(fid).cloned()).collect(),
+            refuse_unsafe,
+        }
+    }
+
+    /// Moves the heap back into a sequential net
+    fn into_gnet(self) -> GNet {
+        let node_buf = self.node_buf.into_iter().map(|c| Pair::from(c.into_inner())).collect();
+        let vars_buf = self.vars_buf.into_iter().map(|c| Port::from(c.into_inner())).collect();
+        let root = Port::from(self.root.into_inner());
+        GNet::from_buffers(node_buf, vars_buf, root, Vec::new())
+    }
+
+    /// Stops every thread, keeping the first error
+    fn fail(&self, error: CoreError) {
+        let mut slot = self.error.lock().unwrap();
+        if slot.is_none() {
+            *slot = Some(error);
+        }
+        self.halt.store(true, Ordering::Release);
+    }
+
+    // Heap access
+
+    fn node_create(&self, loc: Val, val: Pair) {
+        self.node_buf[loc as usize].store(u64::from(val), Ordering::Release);
+    }
+
+    fn node_load(&self, loc: Val) -> Pair {
+        Pair::from(self.node_buf[loc as usize].load(Ordering::Acquire))
+    }
+
+    fn node_take(&self, loc: Val) -> Pair {
+        Pair::from(self.node_buf[loc as usize].swap(0, Ordering::AcqRel))
+    }
+
+    fn vars_slot(&self, var: Val) -> &APort {
+        if var == Port::ROOT.val() {
+            &self.root
+        } else {
+            &self.vars_buf[var as usize]
+        }
+    }
+
+    fn vars_create(&self, var: Val, val: Port) {
+        self.vars_slot(var).store(u32::from(val), Ordering::Release);
+    }
+
+    fn vars_exchange(&self, var: Val, val: Port) -> Port {
+        Port::from(self.vars_slot(var).swap(u32::from(val), Ordering::AcqRel))
+    }
+
+    fn vars_take(&self, var: Val) -> Port {
+        self.vars_exchange(var, Port::FREE)
+    }
+
+    // Redex bags
+
+    fn push_redex(&self, tm: &mut TM, redex: Pair) {
+        let rule = get_rule(redex.fst(), redex.snd());
+        if self.high[rule as usize] && tm.hbag.len() < self.hlen {
+            tm.hbag.push(redex);
+        } else if tm.rput < self.rlen {
+            if let Some(max) = self.low_len {
+                if self.low.fetch_add(1, Ordering::AcqRel) >= max {
+                    self.low.fetch_sub(1, Ordering::AcqRel);
+                    self.fail(CoreError::BagOverflow(max));
+                    return;
+                }
+            }
+            self.rbag_buf[tm.tid * self.rlen + tm.rput].store(u64::from(redex), Ordering::Release);
+            tm.rput += 1;
+        } else {
+            self.fail(CoreError::OutOfMemory("rbag"));
+        }
+    }
+
+    fn pop_redex(&self, tm: &mut TM) -> Pair {
+        if let Some(redex) = tm.hbag.pop() {
+            redex
+        } else if tm.rput > 0 {
+            tm.rput -= 1;
+            self.rbag_take(tm.tid * self.rlen + tm.rput)
+        } else {
+            Pair::FREE
+        }
+    }
+
+    /// Takes the redex in a shared slot, if any
+    fn rbag_take(&self, idx: usize) -> Pair {
+        let got = Pair::from(self.rbag_buf[idx].swap(0, Ordering::AcqRel));
+        if got != Pair::FREE && self.low_len.is_some() {
+            self.low.fetch_sub(1, Ordering::AcqRel);
+        }
+        got
+    }
+
+    fn rbag_len(&self, tm: &TM) -> usize {
+        tm.hbag.len() + tm.rput
+    }
+
+    // Allocator
+
+    fn node_alloc(&self, tm: &mut TM, num: usize) -> bool {
+        let len = self.node_buf.len() / self.threads;
+        tm.nloc.clear();
+        let mut lps = 0;
+        while tm.nloc.len() < num {
+            let lc = tm.tid * len + (tm.nput % len);
+            tm.nput += 1;
+            if lc > 0 && self.node_buf[lc].load(Ordering::Acquire) == 0 {
+                tm.nloc.push(lc as Val);
+            }
+            lps += 1;
+            if lps >= len {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn vars_alloc(&self, tm: &mut TM, num: usize) -> bool {
+        let len = self.vars_buf.len() / self.threads;
+        tm.vloc.clear();
+        let mut lps = 0;
+        while tm.vloc.len() < num {
+            let lc = tm.tid * len + (tm.vput % len);
+            tm.vput += 1;
+            if lc > 0 && self.vars_buf[lc].load(Ordering::Acquire) == 0 {
+                tm.vloc.push(lc as Val);
+            }
+            lps += 1;
+            if lps >= len {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Gets the resources for an interaction; running out is fatal. The
+    /// redexes needed could all be low priority, so the low bag must have
+    /// room for each.
+    fn get_resources(&self, tm: &mut TM, need_rbag: usize, need_node: usize, need_vars: usize) -> bool {
+        if let Some(max) = self.low_len {
+            if self.low.load(Ordering::Acquire) + need_rbag > max {
+                self.fail(CoreError::BagOverflow(max));
+                return false;
+            }
+        }
+        if self.rlen - tm.rput < need_rbag && self.hlen.saturating_sub(tm.hbag.len()) < need_rbag {
+            self.fail(CoreError::OutOfMemory("rbag"));
+            return false;
+        }
+        if !self.node_alloc(tm, need_node) {
+            self.fail(CoreError::OutOfMemory("node"));
+            return false;
+        }
+        if !self.vars_alloc(tm, need_vars) {
+            self.fail(CoreError::OutOfMemory("vars"));
+            return false;
+        }
+        true
+    }
+
+    // Linking
+
+    fn enter(&self, mut var: Port) -> Port {
+        while var.tag() == Tag::Var {
+            let val = self.vars_exchange(var.val(), Port::NONE);
+            if val == Port::NONE || val == Port::FREE {
+                break;
+            }
+            self.vars_take(var.val());
+            var = val;
+        }
+        var
+    }
+
+    fn link(&self, tm: &mut TM, mut a: Port, mut b: Port) {
+        loop {
+            if a.tag() != Tag::Var && b.tag() == Tag::Var {
+                std::mem::swap(&mut a, &mut b);
+            }
+            if a.tag() != Tag::Var {
+                self.push_redex(tm, Pair::new(a, b));
+                break;
+            }
+            b = self.enter(b);
+            let a_ = self.vars_exchange(a.val(), b);
+            if a_ == Port::NONE {
+                break;
+            }
+            self.vars_take(a.val());
+            a = a_;
+        }
+    }
+
+    // Interactions
+
+    fn interact_link(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 1, 0, 0) {
+            return false;
+        }
+        self.link(tm, a, b);
+        true
+    }
+
+    fn interact_call(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        let Some(tmpl) = self.tmpls.get(a.val() as usize) else {
+            self.fail(CoreError::UndefinedRef(a.val()));
+            return false;
+        };
+        if b.tag() == Tag::Dup {
+            if tmpl.safe {
+                return self.interact_eras(tm, a, b);
+            }
+            if self.refuse_unsafe {
+                self.fail(CoreError::UnsafeDup(a.val()));
+                return false;
+            }
+        }
+        if !self.get_resources(tm, tmpl.rbag.len() + 1, tmpl.node.len(), tmpl.vars) {
+            return false;
+        }
+        for &var in &tm.vloc {
+            self.vars_create(var, Port::NONE);
+        }
+        for (i, &node) in tmpl.node.iter().enumerate() {
+            let node = adjust_pair(node, &tm.nloc, &tm.vloc);
+            self.node_create(tm.nloc[i], node);
+        }
+        for &redex in &tmpl.rbag {
+            let redex = adjust_pair(redex, &tm.nloc, &tm.vloc);
+            self.link(tm, redex.fst(), redex.snd());
+        }
+        let root = adjust_port(tmpl.root, &tm.nloc, &tm.vloc);
+        self.link(tm, root, b);
+        true
+    }
+
+    fn interact_eras(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 2, 0, 0) {
+            return false;
+        }
+        if self.node_load(b.val()) == Pair::FREE {
+            return false;
+        }
+        let nb = self.node_take(b.val());
+        self.link(tm, a, nb.fst());
+        self.link(tm, a, nb.snd());
+        true
+    }
+
+    fn interact_anni(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 2, 0, 0) {
+            return false;
+        }
+        if self.node_load(a.val()) == Pair::FREE || self.node_load(b.val()) == Pair::FREE {
+            return false;
+        }
+        let na = self.node_take(a.val());
+        let nb = self.node_take(b.val());
+        self.link(tm, na.fst(), nb.fst());
+        self.link(tm, na.snd(), nb.snd());
+        true
+    }
+
+    fn interact_comm(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 4, 4, 4) {
+            return false;
+        }
+        if self.node_load(a.val()) == Pair::FREE || self.node_load(b.val()) == Pair::FREE {
+            return false;
+        }
+        let na = self.node_take(a.val());
+        let nb = self.node_take(b.val());
+
+        let v: Vec<Port> = tm.vloc.iter().map(|&var| Port::new(Tag::Var, var)).collect();
+        for &var in &tm.vloc {
+            self.vars_create(var, Port::NONE);
+        }
+        let n = tm.nloc.clone();
+        self.node_create(n[0], Pair::new(v[0], v[1]));
+        self.node_create(n[1], Pair::new(v[2], v[3]));
+        self.node_create(n[2], Pair::new(v[0], v[2]));
+        self.node_create(n[3], Pair::new(v[1], v[3]));
+
+        self.link(tm, Port::new(b.tag(), n[0]), na.fst());
+        self.link(tm, Port::new(b.tag(), n[1]), na.snd());
+        self.link(tm, Port::new(a.tag(), n[2]), nb.fst());
+        self.link(tm, Port::new(a.tag(), n[3]), nb.snd());
+        true
+    }
+
+    fn interact_oper(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 1, 1, 0) {
+            return false;
+        }
+        if self.node_load(b.val()) == Pair::FREE {
+            return false;
+        }
+        let nb = self.node_take(b.val());
+        let b1 = nb.fst();
+        let b2 = self.enter(nb.snd());
+        if b1.tag() == Tag::Num {
+            let cv = operate(a.val(), b1.val());
+            self.link(tm, Port::new(Tag::Num, cv), b2);
+        } else {
+            self.node_create(tm.nloc[0], Pair::new(a, b2));
+            self.link(tm, b1, Port::new(Tag::Opr, tm.nloc[0]));
+        }
+        true
+    }
+
+    fn interact_swit(&self, tm: &mut TM, a: Port, b: Port) -> bool {
+        if !self.get_resources(tm, 1, 2, 0) {
+            return false;
+        }
+        if self.node_load(b.val()) == Pair::FREE {
+            return false;
+        }
+        let av = Numb::new(a.val()).u24();
+        let nb = self.node_take(b.val());
+        let era = Port::new(Tag::Era, 0);
+        let (n0, n1) = (tm.nloc[0], tm.nloc[1]);
+        if av == 0 {
+            self.node_create(n0, Pair::new(nb.snd(), era));
+        } else {
+            self.node_create(n0, Pair::new(era, Port::new(Tag::Con, n1)));
+            self.node_create(n1, Pair::new(Port::new(Tag::Num, Numb::new_u24(av - 1).0), nb.snd()));
+        }
+        self.link(tm, Port::new(Tag::Con, n0), nb.fst());
+        true
+    }
+
+    /// Pops a local redex and performs a single interaction
+    fn interact(&self, tm: &mut TM) -> bool {
+        let redex = self.pop_redex(tm);
+        if redex == Pair::FREE {
+            return true;
+        }
+        let (mut a, mut b) = (redex.fst(), redex.snd());
+        let mut rule = get_rule(a, b);
+        if a.tag() == Tag::Ref && b == Port::ROOT {
+            rule = Rule::Call;
+        } else if should_swap(a, b) {
+            std::mem::swap(&mut a, &mut b);
+        }
+
+        let success = match rule {
+            Rule::Link => self.interact_link(tm, a, b),
+            Rule::Call => self.interact_call(tm, a, b),
+            Rule::Void => true,
+            Rule::Eras => self.interact_eras(tm, a, b),
+            Rule::Anni => self.interact_anni(tm, a, b),
+            Rule::Comm => self.interact_comm(tm, a, b),
+            Rule::Oper => self.interact_oper(tm, a, b),
+            Rule::Swit => self.interact_swit(tm, a, b),
+        };
+
+        if success {
+            tm.stats.record(rule);
+        } else {
+            self.push_redex(tm, redex);
+        }
+        success
+    }
+
+    // Evaluator
+
+    fn evaluator(&self, tm: &mut TM) {
+        let mut tick: u32 = 0;
+        let mut busy = self.rbag_len(tm) > 0;
+        while !self.halt.load(Ordering::Acquire) {
+            tick = tick.wrapping_add(1);
+
+            if self.rbag_len(tm) > 0 {
+                if !busy {
+                    self.idle.fetch_sub(1, Ordering::AcqRel);
+                }
+                busy = true;
+                self.interact(tm);
+            } else {
+                if busy {
+                    self.idle.fetch_add(1, Ordering::AcqRel);
+                }
+                busy = false;
+
+                // Steals a redex from the previous thread
+                let sid = (tm.tid + self.threads - 1) % self.threads;
+                let idx = sid * self.rlen + tm.sidx;
+                tm.sidx = (tm.sidx + 1) % self.rlen;
+                let got = self.rbag_take(idx);
+                if got != Pair::FREE {
+                    self.push_redex(tm, got);
+                    continue;
+                }
+                tm.sidx = 0;
+
+                thread::yield_now();
+                if tick.is_multiple_of(256) && self.idle.load(Ordering::Acquire) == self.threads {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl GNet {
+    /// Reduces redexes until the bag is empty, using `config.threads` threads
+    /// and the bag's classes and capacities
+    pub fn reduce_par(&mut self, book: &Book, config: &ParConfig) -> Result<Stats> {
+        self.reduce_par_with(book, config, false)
+    }
+
+    /// `reduce_par`, optionally failing when an unsafe definition meets a DUP
+    pub(crate) fn reduce_par_with(&mut self, book: &Book, config: &ParConfig, refuse_unsafe: bool) -> Result<Stats> {
+        let net = Net::new(self, book, config, refuse_unsafe);
+        let mut tms: Vec<TM> = (0..net.threads).map(TM::new).collect();
+
+        // Deals the initial redexes round-robin
+        for (i, &redex) in self.redexes.iter().enumerate() {
+            let tm = &mut tms[i % net.threads];
+            net.push_redex(tm, redex);
+        }
+        let idle = tms.iter().filter(|tm| net.rbag_len(tm) == 0).count();
+        net.idle.store(idle, Ordering::Release);
+
+        thread::scope(|scope| {
+            for tm in tms.iter_mut() {
+                let net = &net;
+                scope.spawn(move || net.evaluator(tm));
+            }
+        });
+
+        if let Some(error) = net.error.lock().unwrap().take() {
+            return Err(error);
+        }
+        let mut stats = Stats::new();
+        for tm in &tms {
+            stats.merge(&tm.stats);
+        }
+        // The heap is new, but checkpoints and the bag config are kept
+        let checkpoints = std::mem::take(&mut self.checkpoints);
+        let next_checkpoint = self.next_checkpoint;
+        let bag = *self.redexes.config();
+        *self = net.into_gnet();
+        self.redexes.set_config(bag);
+        self.checkpoints = checkpoints;
+        self.next_checkpoint = next_checkpoint;
+        Ok(stats)
+    }
+
+    /// Boots `@main` and reduces it to normal form on many threads
+    pub fn normalize_par(&mut self, book: &Book, config: &ParConfig) -> Result<Stats> {
+        self.boot(book)?;
+        self.reduce_par(book, config)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, BagConfig};
+
+    /// Small buffers, so that tests don't allocate the default heap
+    fn config(threads: usize) -> ParConfig {
+        ParConfig {
+            threads,
+            node_len: 1 << 16,
+            vars_len: 1 << 16,
+            rbag_len: 1 << 12,
+        }
+    }
+
+    #[test]
+    fn test_par_config() {
+        let config = ParConfig::new(0);
+        assert_eq!(config.threads, 1);
+        assert!(ParConfig::default().threads >= 1);
+    }
+
+    #[test]
+    fn test_par_matches_sequential() {
+        for threads in [1, 2, 4, 8] {
+            let config = config(threads);
+            for (name, book, _) in corpus::all() {
+                let mut seq = GNet::new();
+                let seq_stats = seq.normalize(&book).unwrap();
+
+                let mut par = GNet::new();
+                let par_stats = par.normalize_par(&book, &config).unwrap();
+
+                assert_eq!(par.root(), seq.root(), "{} on {} threads", name, threads);
+                assert_eq!(par_stats.interactions, seq_stats.interactions, "{} on {} threads", name, threads);
+                assert_eq!(par.node_count(), seq.node_count(), "{} on {} threads", name, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn test_par_tree_workload() {
+        let book = corpus::pow2(12);
+        let mut net = GNet::new();
+        let stats = net.normalize_par(&book, &config(4)).unwrap();
+        assert_eq!(net.root(), corpus::num(4096));
+        assert!(stats.count(Rule::Call) > 4096);
+    }
+
+    #[test]
+    fn test_par_out_of_memory() {
+        let config = ParConfig {
+            threads: 2,
+            node_len: 64,
+            vars_len: 64,
+            rbag_len: 64,
+        };
+        let mut net = GNet::new();
+        let result = net.normalize_par(&corpus::pow2(12), &config);
+        assert!(matches!(result, Err(CoreError::OutOfMemory(_))));
+    }
+
+    #[test]
+    fn test_par_bag_config() {
+        let book = corpus::pow2(8);
+        let mut expected = GNet::new();
+        expected.normalize(&book).unwrap();
+        let bags = [
+            BagConfig::single(),
+            BagConfig {
+                high_len: Some(0),
+                ..BagConfig::new()
+            },
+            BagConfig {
+                high_len: Some(3),
+                ..BagConfig::new()
+            },
+        ];
+        for bag in bags {
+            let mut net = GNet::new();
+            net.redexes.set_config(bag);
+            net.normalize_par(&book, &config(2)).unwrap();
+            assert_eq!(net.root(), expected.root(), "{:?}", bag);
+            assert_eq!(net.redexes.config(), &bag);
+        }
+
+        // A full low bag fails, leaving the net as it was
+        let mut net = GNet::new();
+        net.redexes.set_config(BagConfig {
+            low_len: Some(0),
+            ..BagConfig::new()
+        });
+        net.boot(&book).unwrap();
+        let booted = net.clone();
+        assert_eq!(net.reduce_par(&book, &config(2)), Err(CoreError::BagOverflow(0)));
+        assert_eq!(net, booted);
+        net.redexes.set_config(BagConfig::new());
+        net.reduce_par(&book, &config(2)).unwrap();
+        assert_eq!(net.root(), expected.root());
+    }
+
+    #[test]
+    fn test_par_undefined_ref() {
+        let mut net = GNet::new();
+        let result = net.normalize_par(&Book::new(), &config(2));
+        assert_eq!(result, Err(CoreError::UndefinedDef("main".to_string())));
+    }
+}
@@ -0,0 +1,565 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: parse.rs
+// Location: crates/hvmx-core/src/parse.rs
+// Purpose: Parser for the HVM2 textual syntax (.hvm books)
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Parser for `.hvm` books.
+//!
+//! ```text
+//! book ::= ("@" name "=" net)*
+//! net  ::= tree ("&" tree "~" tree)*
+//! tree ::= name | "@" name | "*" | numb
+//!        | "(" tree tree ")" | "{" tree tree "}"
+//!        | "$(" tree tree ")" | "?(" tree tree ")"
+//! numb ::= 123 | 0x7B | +1 | -1 | 1.5 | +inf | -inf | +NaN
+//!        | "[" op "]" | "[" op numb "]" | "[u24]" | "[i24]" | "[f24]"
+//! ```
+//!
+//! `//` starts a comment. `@main`, when present, always gets fid 0.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::ast::{Net, Tree};
+use crate::book::Def;
+use crate::numb::SYMBOLS;
+use crate::{Book, CoreError, GNet, Numb, Result, Tag, Val};
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/')
+}
+
+/// Parser: a cursor over the source, tracking line and column
+pub struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+    col: usize,
+    vars: Vec<(String, usize, usize)>, // variable occurrences in the current net
+    refs: Vec<(String, usize, usize)>, // references in the whole book
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            line: 1,
+            col: 1,
+            vars: Vec::new(),
+            refs: Vec::new(),
+        }
+    }
+
+    // Cursor
+
+    pub(crate) fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub(crate) fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace and `//` comments
+    pub(crate) fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.advance();
+                }
+                Some('/') if self.rest().starts_with("//") => {
+                    while !matches!(self.advance(), None | Some('\n')) {}
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Current line and column
+    pub(crate) fn location(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+
+    pub(crate) fn error<T>(&self, msg: impl Into<String>) -> Result<T> {
+        Err(CoreError::Parse {
+            line: self.line,
+            col: self.col,
+            msg: msg.into(),
+        })
+    }
+
+    pub(crate) fn found(&self) -> String {
+        match self.peek() {
+            Some(c) => format!("'{}'", c),
+            None => "end of input".to_string(),
+        }
+    }
+
+    pub(crate) fn consume(&mut self, text: &str) -> Result<()> {
+        self.skip_trivia();
+        if !self.rest().starts_with(text) {
+            return self.error(format!("expected '{}', found {}", text, self.found()));
+        }
+        for _ in text.chars() {
+            self.advance();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn try_consume(&mut self, text: &str) -> bool {
+        self.skip_trivia();
+        self.rest().starts_with(text) && self.consume(text).is_ok()
+    }
+
+    pub(crate) fn name(&mut self) -> Result<String> {
+        self.skip_trivia();
+        let start = self.pos;
+        while self.peek().is_some_and(is_name_char) {
+            self.advance();
+        }
+        if start == self.pos {
+            return self.error(format!("expected a name, found {}", self.found()));
+        }
+        Ok(self.src[start..self.pos].to_string())
+    }
+
+    // Grammar
+
+    /// Parses a tree
+    pub fn tree(&mut self) -> Result<Tree> {
+        self.skip_trivia();
+        let (line, col) = (self.line, self.col);
+        match self.peek() {
+            Some('*') => {
+                self.advance();
+                Ok(Tree::Era)
+            }
+            Some('@') => {
+                self.advance();
+                let nam = self.name()?;
+                self.refs.push((nam.clone(), line, col));
+                Ok(Tree::Ref { nam })
+            }
+            Some('(') => self.node('(', ')', Tag::Con),
+            Some('{') => self.node('{', '}', Tag::Dup),
+            Some('$') => {
+                self.advance();
+                self.node('(', ')', Tag::Opr)
+            }
+            Some('?') => {
+                self.advance();
+                self.node('(', ')', Tag::Swi)
+            }
+            Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | '[') => {
+                Ok(Tree::Num { val: self.numb()? })
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let nam = self.name()?;
+                self.vars.push((nam.clone(), line, col));
+                Ok(Tree::Var { nam })
+            }
+            _ => self.error(format!("expected a tree, found {}", self.found())),
+        }
+    }
+
+    fn node(&mut self, open: char, close: char, tag: Tag) -> Result<Tree> {
+        self.consume(&open.to_string())?;
+        let fst = self.tree()?;
+        let snd = self.tree()?;
+        self.consume(&close.to_string())?;
+        Ok(Tree::node(tag, fst, snd))
+    }
+
+    /// Parses a number, operator or cast
+    pub fn numb(&mut self) -> Result<Numb> {
+        self.skip_trivia();
+        if self.peek() != Some('[') {
+            return self.literal();
+        }
+        self.advance();
+        self.skip_trivia();
+        let Some(&(name, sym)) = SYMBOLS.iter().find(|(name, _)| self.rest().starts_with(name)) else {
+            return self.error(format!("expected an operator, found {}", self.found()));
+        };
+        for _ in name.chars() {
+            self.advance();
+        }
+        let sym = Numb::new_sym(sym);
+        if self.try_consume("]") {
+            return Ok(sym);
+        }
+        if (Numb::TY_U24..=Numb::TY_F24).contains(&sym.sym()) {
+            return self.error(format!("expected ']', found {}", self.found()));
+        }
+        let val = self.literal()?;
+        self.consume("]")?;
+        Ok(Numb::partial(sym, val))
+    }
+
+    fn literal(&mut self) -> Result<Numb> {
+        self.skip_trivia();
+        let (line, col) = (self.line, self.col);
+        let start = self.pos;
+        let sign = matches!(self.peek(), Some('+' | '-'));
+        if sign {
+            self.advance();
+        }
+        for special in ["inf", "NaN"] {
+            if sign && self.rest().starts_with(special) {
+                for _ in special.chars() {
+                    self.advance();
+                }
+                let val: f32 = self.src[start..self.pos].parse().unwrap();
+                return Ok(Numb::new_f24(val));
+            }
+        }
+        let digits = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_') {
+            self.advance();
+        }
+        let text = &self.src[start..self.pos];
+        let body = self.src[digits..self.pos].replace('_', "");
+        let invalid = || CoreError::Parse {
+            line,
+            col,
+            msg: format!("invalid number '{}'", text),
+        };
+        if body.contains('.') {
+            let val: f32 = text.replace('_', "").parse().map_err(|_| invalid())?;
+            return Ok(Numb::new_f24(val));
+        }
+        let (radix, body) = match body.get(..2) {
+            Some("0x") => (16, &body[2..]),
+            Some("0b") => (2, &body[2..]),
+            _ => (10, &body[..]),
+        };
+        let val = u32::from_str_radix(body, radix).map_err(|_| invalid())?;
+        let (numb, max) = match self.src[start..].chars().next() {
+            Some('+') => (Numb::new_i24(val as i32), 0x7F_FFFF),
+            Some('-') => (Numb::new_i24((val as i32).wrapping_neg()), 0x80_0000),
+            _ => (Numb::new_u24(val), 0xFF_FFFF),
+        };
+        if val > max {
+            return Err(CoreError::Parse {
+                line,
+                col,
+                msg: format!("number '{}' does not fit in 24 bits", text),
+            });
+        }
+        Ok(numb)
+    }
+
+    /// Parses a net: a root tree followed by `& a ~ b` redexes
+    pub fn net(&mut self) -> Result<Net> {
+        let root = self.tree()?;
+        let mut rbag = Vec::new();
+        while self.try_consume("&") {
+            let a = self.tree()?;
+            self.consume("~")?;
+            let b = self.tree()?;
+            rbag.push((a, b));
+        }
+        Ok(Net { root, rbag })
+    }
+
+    /// Parses a whole book, checking variables and references
+    pub fn book(&mut self) -> Result<Book> {
+        let mut defs: Vec<(String, Net)> = Vec::new();
+        let mut names = HashMap::new();
+
+        loop {
+            self.skip_trivia();
+            if self.peek().is_none() {
+                break;
+            }
+            let (line, col) = (self.line, self.col);
+            self.consume("@")?;
+            let name = self.name()?;
+            if names.insert(name.clone(), defs.len()).is_some() {
+                return Err(CoreError::Parse {
+                    line,
+                    col,
+                    msg: format!("duplicate definition '@{}'", name),
+                });
+            }
+            self.consume("=")?;
+            self.vars.clear();
+            let net = self.net()?;
+            self.check_vars()?;
+            defs.push((name, net));
+        }
+
+        for (nam, line, col) in &self.refs {
+            if !names.contains_key(nam) {
+                return Err(CoreError::Parse {
+                    line: *line,
+                    col: *col,
+                    msg: format!("undefined reference '@{}'", nam),
+                });
+            }
+        }
+
+        // `@main` first, then source order
+        let mut order: Vec<usize> = (0..defs.len()).collect();
+        if let Some(&main) = names.get("main") {
+            order.retain(|&i| i != main);
+            order.insert(0, main);
+        }
+        let ids: HashMap<&str, Val> = order
+            .iter()
+            .enumerate()
+            .map(|(fid, &i)| (defs[i].0.as_str(), fid as Val))
+            .collect();
+
+        let mut book = Book::new();
+        for &i in &order {
+            let (name, net) = &defs[i];
+            let def = Def {
+                name: name.clone(),
+                arity: net.root.arity(),
+                net: net.build(|nam| ids.get(nam).copied())?,
+            };
+            book.insert(name.clone(), def);
+        }
+        book.propagate_safety();
+        Ok(book)
+    }
+
+    /// Checks every variable of the last net occurs exactly twice
+    fn check_vars(&self) -> Result<()> {
+        let mut count: HashMap<&str, usize> = HashMap::new();
+        for (nam, line, col) in &self.vars {
+            let n = count.entry(nam).or_insert(0);
+            *n += 1;
+            if *n > 2 {
+                return Err(CoreError::Parse {
+                    line: *line,
+                    col: *col,
+                    msg: format!("variable '{}' occurs more than twice", nam),
+                });
+            }
+        }
+        for (nam, line, col) in &self.vars {
+            if count[nam.as_str()] == 1 {
+                return Err(CoreError::Parse {
+                    line: *line,
+                    col: *col,
+                    msg: format!("variable '{}' occurs only once", nam),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Book {
+    /// Parses a book in HVM2 textual syntax
+    pub fn parse(src: &str) -> Result<Book> {
+        Parser::new(src).book()
+    }
+}
+
+impl FromStr for Book {
+    type Err = CoreError;
+
+    fn from_str(src: &str) -> Result<Book> {
+        Book::parse(src)
+    }
+}
+
+impl GNet {
+    /// Parses a standalone net, checking its variables. It has no book, so
+    /// it cannot refer to definitions.
+    pub fn parse(src: &str) -> Result<GNet> {
+        let mut parser = Parser::new(src);
+        let net = parser.net()?;
+        parser.skip_trivia();
+        if parser.peek().is_some() {
+            return parser.error(format!("expected '&' or end of input, found {}", parser.found()));
+        }
+        parser.check_vars()?;
+        if let Some((nam, line, col)) = parser.refs.first() {
+            return Err(CoreError::Parse {
+                line: *line,
+                col: *col,
+                msg: format!("undefined reference '@{}'", nam),
+            });
+        }
+        net.build(|_| None)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numb::symbol;
+    use crate::{corpus, Port};
+
+    fn parse_numb(src: &str) -> Numb {
+        Parser::new(src).numb().unwrap()
+    }
+
+    fn parse_error(src: &str) -> (usize, usize, String) {
+        match Book::parse(src) {
+            Err(CoreError::Parse { line, col, msg }) => (line, col, msg),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        assert_eq!(parse_numb("42"), Numb::new_u24(42));
+        assert_eq!(parse_numb("0x2A"), Numb::new_u24(42));
+        assert_eq!(parse_numb("0b101"), Numb::new_u24(5));
+        assert_eq!(parse_numb("+7"), Numb::new_i24(7));
+        assert_eq!(parse_numb("-7"), Numb::new_i24(-7));
+        assert_eq!(parse_numb("1.5"), Numb::new_f24(1.5));
+        assert_eq!(parse_numb("-0.25"), Numb::new_f24(-0.25));
+        assert_eq!(parse_numb("+inf"), Numb::new_f24(f32::INFINITY));
+        assert_eq!(parse_numb("-inf"), Numb::new_f24(f32::NEG_INFINITY));
+        assert!(parse_numb("+NaN").f24().is_nan());
+    }
+
+    #[test]
+    fn test_parse_operators() {
+        assert_eq!(parse_numb("[+]"), Numb::new_sym(Numb::OP_ADD));
+        assert_eq!(parse_numb("[:-]"), Numb::new_sym(Numb::FP_SUB));
+        assert_eq!(parse_numb("[<<]"), Numb::new_sym(Numb::OP_SHL));
+        assert_eq!(parse_numb("[<]"), Numb::new_sym(Numb::OP_LT));
+        assert_eq!(parse_numb("[u24]"), Numb::new_sym(Numb::TY_U24));
+        let add1 = Numb::partial(Numb::new_sym(Numb::OP_ADD), Numb::new_u24(1));
+        assert_eq!(parse_numb("[+1]"), add1);
+        assert_eq!(parse_numb("[+ 1]"), add1);
+        assert_eq!(parse_numb("[*-2]"), Numb::partial(Numb::new_sym(Numb::OP_MUL), Numb::new_i24(-2)));
+        for sym in [Numb::TY_U24, Numb::OP_ADD, Numb::FP_SHR, Numb::OP_XOR] {
+            assert_eq!(parse_numb(&format!("[{}]", symbol(sym).unwrap())), Numb::new_sym(sym));
+        }
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let tree = Parser::new("(a {b $(c ?(d *))})").tree().unwrap();
+        let (tag, fst, snd) = tree.children().unwrap();
+        assert_eq!(tag, Tag::Con);
+        assert_eq!(*fst, Tree::Var { nam: "a".to_string() });
+        assert_eq!(snd.children().unwrap().0, Tag::Dup);
+    }
+
+    #[test]
+    fn test_parse_book_matches_corpus() {
+        let src = "
+            // Church numerals
+            @main = r & @c2 ~ (@inc (0 r))
+            @c2 = ({(x y) (y z)} (x z))
+            @inc = (x y) & [+1] ~ $(x y)
+        ";
+        let book = Book::parse(src).unwrap();
+        assert_eq!(book.id("main"), Some(0));
+        assert_eq!(book.get("c2").unwrap().arity, 2);
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        assert_eq!(net.root(), corpus::num(2));
+    }
+
+    #[test]
+    fn test_parse_main_gets_fid_zero() {
+        let book: Book = "@id = (x x)\n@main = r & @id ~ (+5 r)".parse().unwrap();
+        assert_eq!(book.id("main"), Some(0));
+        assert_eq!(book.id("id"), Some(1));
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        assert_eq!(net.root(), Port::new(Tag::Num, Numb::new_i24(5).0));
+    }
+
+    #[test]
+    fn test_parse_sum() {
+        let src = "
+            @main = r & @sum ~ (100 r)
+            @sum = (?((0 @sum.s) r) r)
+            @sum.s = ({p0 p1} r)
+              & @sum ~ (p0 $([+] $(q r)))
+              & [+1] ~ $(p1 q)
+        ";
+        let mut net = GNet::new();
+        net.normalize(&Book::parse(src).unwrap()).unwrap();
+        assert_eq!(net.root(), corpus::num(5050));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (line, col, msg) = parse_error("@main = (a\n  b c)");
+        assert_eq!((line, col), (2, 5));
+        assert!(msg.contains("expected ')'"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@main = (a b)");
+        assert_eq!((line, col), (1, 10));
+        assert!(msg.contains("'a' occurs only once"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@main = (a {a a})");
+        assert_eq!((line, col), (1, 15));
+        assert!(msg.contains("more than twice"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@main = *\n@f = @g");
+        assert_eq!((line, col), (2, 6));
+        assert!(msg.contains("undefined reference '@g'"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@f = *\n@f = *");
+        assert_eq!((line, col), (2, 1));
+        assert!(msg.contains("duplicate"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@main = 0xZZ");
+        assert_eq!((line, col), (1, 9));
+        assert!(msg.contains("invalid number"), "{}", msg);
+
+        let (line, col, msg) = parse_error("@main = +8388608");
+        assert_eq!((line, col), (1, 9));
+        assert!(msg.contains("24 bits"), "{}", msg);
+        assert!(Book::parse("@main = -8388608").is_ok());
+
+        let (line, col, _) = parse_error("@main = [?]");
+        assert_eq!((line, col), (1, 10));
+
+        let (line, col, _) = parse_error("main = *");
+        assert_eq!((line, col), (1, 1));
+    }
+
+    #[test]
+    fn test_parse_net() {
+        let net = GNet::parse("(a b) & (b c) ~ {a c}").unwrap();
+        assert_eq!(net.redexes.len(), 1);
+        assert_eq!(GNet::parse(&net.to_string()).unwrap().to_string(), net.to_string());
+
+        let error = |src: &str| GNet::parse(src).unwrap_err().to_string();
+        assert_eq!(error("(a b)"), "Parse error at 1:2: variable 'a' occurs only once");
+        assert_eq!(error("(a a) & @f ~ *"), "Parse error at 1:9: undefined reference '@f'");
+        assert!(error("(a a) @f").contains("expected '&' or end of input"));
+    }
+
+    #[test]
+    fn test_parse_empty_book() {
+        assert!(Book::parse("  // nothing here\n").unwrap().is_empty());
+    }
+}
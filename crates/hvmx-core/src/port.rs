@@ -9,37 +9,125 @@
 // License: MIT OR Apache-2.0
 // ==============================================================================
 
+use crate::CoreError;
 
-/// Port: 32-bit value (3-bit tag + 29-bit val)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Port: 32-bit value (29-bit val + 3-bit tag)
+///
+/// Same layout as `Port` in `docs/dor/hvm.c`: `(val << 3) | tag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Port(u32);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Port tags, numbered as in the reference runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Tag {
-    Var = 0,
-    Ref = 1,
-    Num = 2,
-    // ... outros
+    Var = 0, // variable
+    Ref = 1, // reference
+    Era = 2, // eraser
+    Num = 3, // number
+    Con = 4, // constructor
+    Dup = 5, // duplicator
+    Opr = 6, // operator
+    Swi = 7, // switch
 }
 
 pub type Val = u32;
 
+/// Every tag, indexed by its 3-bit value
+const TAGS: [Tag; 8] = [
+    Tag::Var,
+    Tag::Ref,
+    Tag::Era,
+    Tag::Num,
+    Tag::Con,
+    Tag::Dup,
+    Tag::Opr,
+    Tag::Swi,
+];
+
+impl TryFrom<u32> for Tag {
+    type Error = CoreError;
+
+    fn try_from(tag: u32) -> Result<Self, Self::Error> {
+        TAGS.get(tag as usize).copied().ok_or(CoreError::InvalidTag(tag))
+    }
+}
+
 impl Port {
+    /// Empty heap slot
+    pub const FREE: Port = Port(0x0000_0000);
+    /// Variable holding the root of a net
+    pub const ROOT: Port = Port(0xFFFF_FFF8);
+    /// Variable that has not been linked yet
+    pub const NONE: Port = Port(0xFFFF_FFFF);
+
+    /// Largest value that fits in a port
+    pub const VAL_MAX: Val = 0x1FFF_FFFF;
+
     pub fn new(tag: Tag, val: Val) -> Self {
-        Port(((tag as u32) << 29) | (val & 0x1FFFFFFF))
+        Port(((val & Self::VAL_MAX) << 3) | tag as u32)
     }
-    
+
     pub fn tag(&self) -> Tag {
-        match self.0 >> 29 {
-            0 => Tag::Var,
-            1 => Tag::Ref,
-            2 => Tag::Num,
-            _ => unreachable!(),
-        }
+        TAGS[(self.0 & 7) as usize]
     }
-    
+
     pub fn val(&self) -> Val {
-        self.0 & 0x1FFFFFFF
+        self.0 >> 3
+    }
+
+    /// True if this port points to a node
+    pub fn is_nod(&self) -> bool {
+        self.tag() >= Tag::Con
+    }
+
+    /// True if this port is a variable
+    pub fn is_var(&self) -> bool {
+        self.tag() == Tag::Var
+    }
+}
+
+impl From<u32> for Port {
+    fn from(raw: u32) -> Self {
+        Port(raw)
+    }
+}
+
+impl From<Port> for u32 {
+    fn from(port: Port) -> Self {
+        port.0
+    }
+}
+
+/// Pair: two ports packed into a u64 (`snd << 32 | fst`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Pair(u64);
+
+impl Pair {
+    /// Empty heap slot
+    pub const FREE: Pair = Pair(0);
+
+    pub fn new(fst: Port, snd: Port) -> Self {
+        Pair(((snd.0 as u64) << 32) | fst.0 as u64)
+    }
+
+    pub fn fst(&self) -> Port {
+        Port(self.0 as u32)
+    }
+
+    pub fn snd(&self) -> Port {
+        Port((self.0 >> 32) as u32)
+    }
+}
+
+impl From<u64> for Pair {
+    fn from(raw: u64) -> Self {
+        Pair(raw)
+    }
+}
+
+impl From<Pair> for u64 {
+    fn from(pair: Pair) -> Self {
+        pair.0
     }
 }
 
@@ -53,4 +141,46 @@ mod tests {
         assert_eq!(port.tag(), Tag::Var);
         assert_eq!(port.val(), 42);
     }
+
+    #[test]
+    fn test_port_all_tags() {
+        for (raw, &tag) in TAGS.iter().enumerate() {
+            let port = Port::new(tag, 0x1234);
+            assert_eq!(port.tag(), tag);
+            assert_eq!(port.val(), 0x1234);
+            assert_eq!(Tag::try_from(raw as u32), Ok(tag));
+        }
+    }
+
+    #[test]
+    fn test_tag_try_from_invalid() {
+        assert_eq!(Tag::try_from(8), Err(CoreError::InvalidTag(8)));
+    }
+
+    #[test]
+    fn test_port_reference_layout() {
+        // Matches `new_port` in hvm.c
+        assert_eq!(u32::from(Port::new(Tag::Con, 5)), (5 << 3) | 4);
+        assert_eq!(Port::ROOT, Port::new(Tag::Var, Port::VAL_MAX));
+        assert_eq!(Port::from(0xFFFF_FFFF).tag(), Tag::Swi);
+    }
+
+    #[test]
+    fn test_port_predicates() {
+        assert!(Port::new(Tag::Var, 1).is_var());
+        assert!(!Port::new(Tag::Num, 1).is_nod());
+        assert!(Port::new(Tag::Con, 1).is_nod());
+        assert!(Port::new(Tag::Swi, 1).is_nod());
+    }
+
+    #[test]
+    fn test_pair() {
+        let a = Port::new(Tag::Con, 1);
+        let b = Port::new(Tag::Era, 0);
+        let pair = Pair::new(a, b);
+        assert_eq!(pair.fst(), a);
+        assert_eq!(pair.snd(), b);
+        assert_eq!(u64::from(pair), ((u32::from(b) as u64) << 32) | u32::from(a) as u64);
+        assert_eq!(Pair::from(u64::from(pair)), pair);
+    }
 }
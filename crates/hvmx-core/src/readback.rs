@@ -0,0 +1,195 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: readback.rs
+// Location: crates/hvmx-core/src/readback.rs
+// Purpose: Reading results out of evaluated nets
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Readback, after `expand` and `pretty_print_port` in `docs/dor/hvm.c`.
+//!
+//! A normal form may still hold `@refs` whose bodies were never needed.
+//! Readback walks the tree hanging from the root, following variable
+//! chains, and expands each REF it meets by normalizing it against ROOT,
+//! then writes the result back in its place. The walk keeps its own stack.
+
+use crate::ast::Tree;
+use crate::{Book, GNet, Pair, Port, Result, Tag, Val};
+
+/// Where a port is stored
+#[derive(Clone, Copy)]
+enum Slot {
+    Root,
+    Fst(Val),
+    Snd(Val),
+}
+
+impl GNet {
+    /// Expands the REF `port` leads to, normalizing until something else
+    /// comes out, and returns that. Other ports are returned as they are.
+    ///
+    /// Like `expand` in hvm.c, this borrows ROOT and reduces any pending
+    /// redexes along the way.
+    pub fn expand(&mut self, book: &Book, port: Port) -> Result<Port> {
+        let mut budget = usize::MAX;
+        self.expand_limit(book, port, &mut budget)
+    }
+
+    /// `expand`, spending one unit of `budget` per expansion
+    fn expand_limit(&mut self, book: &Book, port: Port, budget: &mut usize) -> Result<Port> {
+        let old = self.vars_load(Port::ROOT.val());
+        let mut got = self.peek(port);
+        while got.tag() == Tag::Ref && *budget > 0 {
+            *budget -= 1;
+            self.vars_create(Port::ROOT.val(), Port::NONE);
+            self.redexes.push(Pair::new(got, Port::ROOT));
+            self.reduce(book)?;
+            got = self.peek(Port::ROOT);
+        }
+        self.vars_create(Port::ROOT.val(), old);
+        Ok(got)
+    }
+
+    /// Reads the root back as a tree, expanding every REF it reaches.
+    /// References are named after `book`'s definitions.
+    ///
+    /// This does not return for results that unfold forever, such as
+    /// `@s = (1 @s)`; use `readback_limit` for those.
+    pub fn readback(&mut self, book: &Book) -> Result<Tree> {
+        self.readback_limit(book, usize::MAX)
+    }
+
+    /// `readback`, expanding at most `max_refs` REFs. Those left over are
+    /// read back as references.
+    pub fn readback_limit(&mut self, book: &Book, max_refs: usize) -> Result<Tree> {
+        let mut budget = max_refs;
+        let mut stack = vec![Slot::Root];
+        while let Some(slot) = stack.pop() {
+            let mut port = self.slot_load(slot);
+            // The last variable of the chain, which holds the port
+            let mut last = None;
+            while port.tag() == Tag::Var {
+                let val = self.vars_load(port.val());
+                if val == Port::NONE || val == Port::FREE {
+                    break;
+                }
+                last = Some(port.val());
+                port = val;
+            }
+            if port == Port::NONE {
+                continue;
+            }
+            if port.tag() == Tag::Ref && budget > 0 {
+                let got = self.expand_limit(book, port, &mut budget)?;
+                match last {
+                    Some(var) => self.vars_create(var, got),
+                    None => self.slot_store(slot, got),
+                }
+                stack.push(slot);
+            } else if port.is_nod() {
+                stack.push(Slot::Snd(port.val()));
+                stack.push(Slot::Fst(port.val()));
+            }
+        }
+        Ok(self.to_ast(Some(book)).root)
+    }
+
+    fn slot_load(&self, slot: Slot) -> Port {
+        match slot {
+            Slot::Root => self.vars_load(Port::ROOT.val()),
+            Slot::Fst(loc) => self.node_load(loc).fst(),
+            Slot::Snd(loc) => self.node_load(loc).snd(),
+        }
+    }
+
+    fn slot_store(&mut self, slot: Slot, port: Port) {
+        match slot {
+            Slot::Root => self.vars_create(Port::ROOT.val(), port),
+            Slot::Fst(loc) => {
+                let node = self.node_load(loc);
+                self.node_store(loc, Pair::new(port, node.snd()));
+            }
+            Slot::Snd(loc) => {
+                let node = self.node_load(loc);
+                self.node_store(loc, Pair::new(node.fst(), port));
+            }
+        }
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, Numb};
+
+    fn normalized(src: &str) -> (Book, GNet) {
+        let book = Book::parse(src).unwrap();
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        (book, net)
+    }
+
+    #[test]
+    fn test_readback_corpus() {
+        for (name, book, expected) in corpus::all() {
+            let mut net = GNet::new();
+            net.normalize(&book).unwrap();
+            let tree = net.readback(&book).unwrap();
+            assert_eq!(tree, Tree::Num { val: Numb::new(corpus::num(expected).val()) }, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_readback_expands_refs() {
+        let (book, mut net) = normalized("@main = (@T (@F *))\n@T = (a (* a))\n@F = (* (b b))");
+        assert_eq!(net.to_ast(Some(&book)).root.to_string(), "(@T (@F *))");
+        let tree = net.readback(&book).unwrap();
+        assert_eq!(tree.to_string(), "((a (* a)) ((* (b b)) *))");
+        // Expansions are written back into the net
+        assert_eq!(net.display(&book).to_string(), tree.to_string());
+    }
+
+    #[test]
+    fn test_readback_follows_vars() {
+        let (book, mut net) = normalized("@main = (a (b (a b)))");
+        assert_eq!(net.readback(&book).unwrap().to_string(), "(a (b (a b)))");
+        let (book, mut net) = normalized("@main = r & @id ~ (@one r)\n@id = (x x)\n@one = 1");
+        assert_eq!(net.readback(&book).unwrap(), Tree::Num { val: Numb::new_u24(1) });
+    }
+
+    #[test]
+    fn test_expand() {
+        let (book, mut net) = normalized("@main = *\n@a = @b\n@b = (x x)");
+        let got = net.expand(&book, Port::new(Tag::Ref, book.id("a").unwrap())).unwrap();
+        assert_eq!(got.tag(), Tag::Con);
+        assert_eq!(net.root(), Port::new(Tag::Era, 0));
+        assert_eq!(net.expand(&book, Port::new(Tag::Num, 3)).unwrap(), Port::new(Tag::Num, 3));
+    }
+
+    #[test]
+    fn test_readback_limit() {
+        let (book, mut net) = normalized("@main = @s\n@s = (1 @s)");
+        let tree = net.readback_limit(&book, 3).unwrap();
+        assert_eq!(tree.to_string(), "(1 (1 (1 @s)))");
+    }
+
+    #[test]
+    fn test_readback_huge_output() {
+        // Each expansion adds one level
+        let depth = 200_000;
+        let (book, mut net) = normalized("@main = @s\n@s = (1 @s)");
+        let tree = net.readback_limit(&book, depth).unwrap();
+        let copy = tree.clone();
+        assert_eq!(copy, tree);
+        let text = tree.to_string();
+        assert_eq!(text.len(), depth * 4 + "@s".len());
+        assert!(text.starts_with("(1 (1 ") && text.contains("(1 (1 @s))"));
+    }
+}
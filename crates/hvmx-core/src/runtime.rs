@@ -0,0 +1,412 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: runtime.rs
+// Location: crates/hvmx-core/src/runtime.rs
+// Purpose: Book runner with evaluator and checking options
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+use crate::interact::get_rule;
+use crate::optimize::{OptConfig, OptReport};
+use crate::safety::SafetyReport;
+use crate::{BagConfig, Book, Budget, CoreError, Effects, EvalMode, Meter, GNet, Pair, ParConfig, Port, Result, Rule, Stats, Tag};
+
+/// Runtime configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Strict or lazy evaluation; lazy runs sequentially, ignoring `par`
+    pub mode: EvalMode,
+    /// Parallel evaluator settings; `None` runs the sequential normalizer
+    pub par: Option<ParConfig>,
+    /// Refuse books that duplicate an unsafe definition, and stop when one
+    /// meets a DUP while running
+    pub refuse_unsafe: bool,
+    /// Optimize the book before running it; off by default
+    pub optimize: Option<OptConfig>,
+    /// Limits for each `reduce` call; runs sequentially when set, as it
+    /// does with effects
+    pub budget: Option<Budget>,
+    /// In debug builds, validate the net after every this many interactions
+    /// of the sequential strict evaluator. The lazy and parallel evaluators
+    /// keep redexes outside the bag while they run, so their nets are
+    /// validated once, when they stop. Only `Runtime` runs the check, not
+    /// `GNet::reduce` and the like called directly.
+    pub validate_every: Option<u64>,
+    /// Redex priority classes and capacities of the nets it boots, for
+    /// every evaluator; the parallel one splits the high capacity between
+    /// its threads
+    pub bag: BagConfig,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Runtime: a checked book and the settings to run it with
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    book: Book,
+    config: Config,
+    safety: SafetyReport,
+    optimized: Option<OptReport>,
+    effects: Effects,
+}
+
+impl Runtime {
+    /// Optimizes and analyzes the book, refusing it if the config asks to
+    pub fn new(book: Book, config: Config) -> Result<Self> {
+        Self::with_effects(book, config, Effects::default())
+    }
+
+    /// `new`, handling calls to the effects' definitions in Rust. The
+    /// optimizer never inlines those.
+    pub fn with_effects(mut book: Book, config: Config, effects: Effects) -> Result<Self> {
+        let optimized = match &config.optimize {
+            Some(opt) => Some(book.optimize_keeping(opt, &effects.names())?),
+            None => None,
+        };
+        let safety = book.propagate_safety();
+        if config.refuse_unsafe && !safety.is_safe() {
+            return Err(CoreError::Unsafe(safety.to_string().trim_end().to_string()));
+        }
+        Ok(Self {
+            book,
+            config,
+            safety,
+            optimized,
+            effects,
+        })
+    }
+
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Safety analysis of the book
+    pub fn safety(&self) -> &SafetyReport {
+        &self.safety
+    }
+
+    /// What the optimizer did, if it ran
+    pub fn optimized(&self) -> Option<&OptReport> {
+        self.optimized.as_ref()
+    }
+
+    pub fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    /// Boots `@main` and reduces it to normal form, or as far as the root
+    /// needs in lazy mode
+    pub fn run(&self) -> Result<(GNet, Stats)> {
+        let mut net = GNet::new();
+        net.redexes.set_config(self.config.bag);
+        net.boot(&self.book)?;
+        let stats = self.reduce(&mut net)?;
+        Ok((net, stats))
+    }
+
+    /// Reduces a net's redexes with the configured evaluator. A net that
+    /// ran out of budget can be passed back in to carry on.
+    pub fn reduce(&self, net: &mut GNet) -> Result<Stats> {
+        let mut meter = self.config.budget.map(Meter::new);
+        let check = |net: &GNet, a: Port, b: Port| {
+            if self.config.refuse_unsafe && get_rule(a, b) == Rule::Call {
+                self.check_copy(a, b)?;
+            }
+            match &mut meter {
+                Some(meter) => meter.charge(net, &self.book, a, b),
+                None => Ok(()),
+            }
+        };
+        if self.config.mode == EvalMode::Lazy {
+            if !self.effects.is_empty() {
+                return Err(CoreError::Effect("effects need the strict evaluator".to_string()));
+            }
+            let stats = net.reduce_lazy_with(&self.book, check)?;
+            self.validate(net)?;
+            return Ok(stats);
+        }
+        if let (Some(par), None, true) = (&self.config.par, &self.config.budget, self.effects.is_empty()) {
+            let stats = net.reduce_par_with(&self.book, par, self.config.refuse_unsafe)?;
+            self.validate(net)?;
+            return Ok(stats);
+        }
+        let mut stats = Stats::new();
+        // Effect requests, set aside until the bag is empty
+        let mut pending = Vec::new();
+        let result = self.reduce_strict(net, check, &mut stats, &mut pending);
+        if result.is_err() {
+            // Requests go back with the rest, so the net can be reduced again
+            net.redexes.extend(pending);
+        }
+        result.map(|()| stats)
+    }
+
+    /// The sequential loop, holding effect requests in `pending`
+    fn reduce_strict(
+        &self,
+        net: &mut GNet,
+        mut check: impl FnMut(&GNet, Port, Port) -> Result<()>,
+        stats: &mut Stats,
+        pending: &mut Vec<Pair>,
+    ) -> Result<()> {
+        let mut calls = 0;
+        loop {
+            let Some(redex) = net.redexes.pop() else {
+                if pending.is_empty() {
+                    return Ok(());
+                }
+                self.effects.perform(net, &self.book, pending, &mut calls, &mut check)?;
+                continue;
+            };
+            let (a, b) = (redex.fst(), redex.snd());
+            // Requests are charged when they run
+            if self.effects.is_request(&self.book, a, b) {
+                pending.push(redex);
+                continue;
+            }
+            if let Err(err) = check(net, a, b) {
+                net.redexes.push(redex);
+                return Err(err);
+            }
+            let rule = net.interact_popped(&self.book, redex)?;
+            stats.record(rule);
+            if let Some(every) = self.config.validate_every {
+                if stats.interactions.is_multiple_of(every) && rule != Rule::Link {
+                    self.validate(net)?;
+                }
+            }
+        }
+    }
+
+    /// In debug builds with `validate_every` set, fails if `net` is broken
+    fn validate(&self, net: &GNet) -> Result<()> {
+        if cfg!(debug_assertions) && self.config.validate_every.is_some() {
+            let report = net.validate();
+            if !report.is_ok() {
+                return Err(CoreError::InvalidNet(report.to_string().trim_end().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if one side is an unsafe REF and the other a DUP
+    fn check_copy(&self, a: Port, b: Port) -> Result<()> {
+        let (r, d) = if a.tag() == Tag::Ref { (a, b) } else { (b, a) };
+        let safe = self.book.template(r.val()).is_none_or(|tmpl| tmpl.safe);
+        if d.tag() == Tag::Dup && !safe {
+            return Err(CoreError::UnsafeDup(r.val()));
+        }
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus;
+
+    /// Copies a lambda that holds a DUP, through a variable
+    const UNSAFE: &str = "@main = (a b) & @dup ~ (@g (a b))\n\
+                          @dup = ({x y} (x y))\n\
+                          @g = ({a b} (a b))";
+
+    fn par(threads: usize) -> Config {
+        Config {
+            par: Some(ParConfig {
+                node_len: 1 << 14,
+                vars_len: 1 << 14,
+                rbag_len: 1 << 12,
+                ..ParConfig::new(threads)
+            }),
+            ..Config::new()
+        }
+    }
+
+    fn lazy() -> Config {
+        Config {
+            mode: EvalMode::Lazy,
+            ..Config::new()
+        }
+    }
+
+    #[test]
+    fn test_runtime_matches_normalize() {
+        for (name, book, _) in corpus::all() {
+            let mut expected = GNet::new();
+            expected.normalize(&book).unwrap();
+            for config in [Config::new(), par(2), lazy()] {
+                let runtime = Runtime::new(book.clone(), config).unwrap();
+                let (net, _) = runtime.run().unwrap();
+                assert_eq!(net.to_string(), expected.to_string(), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_runtime_bag_bounds_every_evaluator() {
+        let bag = BagConfig {
+            low_len: Some(0),
+            ..BagConfig::new()
+        };
+        for config in [Config::new(), par(2), lazy()] {
+            let runtime = Runtime::new(corpus::sum(4), Config { bag, ..config }).unwrap();
+            assert_eq!(runtime.run().unwrap_err(), CoreError::BagOverflow(0), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn test_runtime_optimize_is_opt_in() {
+        let book = Book::parse("@main = r & @a ~ (1 r)\n@a = @inc\n@inc = (x y) & [+1] ~ $(x y)").unwrap();
+        let plain = Runtime::new(book.clone(), Config::new()).unwrap();
+        assert!(plain.optimized().is_none());
+        assert_eq!(plain.book(), &book);
+
+        let config = Config {
+            optimize: Some(OptConfig::new()),
+            ..Config::new()
+        };
+        let optimized = Runtime::new(book, config).unwrap();
+        let report = optimized.optimized().unwrap();
+        assert_eq!(report.aliases, vec![("a".to_string(), "inc".to_string())]);
+        assert_eq!(optimized.book().to_string(), "@main = 2\n@inc = ($([+1] a) a)\n");
+
+        let (net, stats) = optimized.run().unwrap();
+        let (expected, before) = plain.run().unwrap();
+        assert_eq!(net.to_string(), expected.to_string());
+        assert!(stats.interactions < before.interactions);
+    }
+
+    #[test]
+    fn test_runtime_budget() {
+        let book = corpus::sum(8);
+        let (expected, total) = Runtime::new(book.clone(), Config::new()).unwrap().run().unwrap();
+        for mode in [EvalMode::Strict, EvalMode::Lazy] {
+            let config = Config {
+                mode,
+                budget: Some(Budget {
+                    max_mana: Some(1),
+                    ..Budget::new()
+                }),
+                ..par(2)
+            };
+            let runtime = Runtime::new(book.clone(), config).unwrap();
+            let mut net = GNet::new();
+            net.boot(runtime.book()).unwrap();
+            // One interaction per call, and the links it leaves for free
+            let mut calls = 1;
+            while let Err(err) = runtime.reduce(&mut net) {
+                assert_eq!(err, CoreError::Exhausted(crate::Limit::Mana(1)));
+                calls += 1;
+            }
+            assert_eq!(net.root(), expected.root(), "{:?}", mode);
+            assert!(calls > 1, "{:?}", mode);
+            if mode == EvalMode::Strict {
+                assert_eq!(calls, total.interactions - total.count(Rule::Link));
+            }
+        }
+    }
+
+    #[test]
+    fn test_runtime_validates_in_debug_builds() {
+        let config = Config {
+            validate_every: Some(1),
+            ..Config::new()
+        };
+        // The lazy and parallel evaluators are checked when they stop
+        let stopping = [
+            Config {
+                mode: EvalMode::Lazy,
+                ..config
+            },
+            Config {
+                validate_every: Some(1),
+                ..par(2)
+            },
+        ];
+        for (name, book, _) in corpus::all() {
+            for config in [config].iter().chain(&stopping) {
+                let runtime = Runtime::new(book.clone(), *config).unwrap();
+                assert!(runtime.run().is_ok(), "{}", name);
+            }
+        }
+
+        // A variable nothing refers to. Nothing is left to drop, so lazy
+        // mode doesn't reclaim it.
+        for config in stopping {
+            let (_, book, _) = corpus::all().remove(0);
+            let runtime = Runtime::new(book, config).unwrap();
+            let mut net = GNet::new();
+            net.boot(runtime.book()).unwrap();
+            let var = net.vars_alloc().unwrap();
+            net.vars_create(var, Port::NONE);
+            let result = runtime.reduce(&mut net);
+            if cfg!(debug_assertions) {
+                assert!(matches!(result, Err(CoreError::InvalidNet(_))), "{:?}: {:?}", config.mode, result);
+            }
+        }
+
+        // A redex left over by a broken backend
+        let runtime = Runtime::new(corpus::sum(2), config).unwrap();
+        let mut net = GNet::new();
+        net.boot(runtime.book()).unwrap();
+        // Below the boot redex, so it is reached after some interactions
+        let boot: Vec<_> = net.redexes.drain().collect();
+        net.redexes.set_config(BagConfig {
+            high_len: Some(0),
+            ..BagConfig::new()
+        });
+        net.redexes.push(crate::Pair::new(Port::new(Tag::Var, 7), Port::new(Tag::Era, 0)));
+        net.redexes.extend(boot);
+        let result = runtime.reduce(&mut net);
+        if cfg!(debug_assertions) {
+            assert!(matches!(result, Err(CoreError::InvalidNet(msg)) if msg.contains("redex 0 is not an active pair")));
+        }
+    }
+
+    #[test]
+    fn test_runtime_refuses_dup_sites() {
+        let book = Book::parse("@main = (a b) & @g ~ {a b}\n@g = ({a b} (a b))").unwrap();
+        assert!(Runtime::new(book.clone(), Config::new()).is_ok());
+        let config = Config {
+            refuse_unsafe: true,
+            ..Config::new()
+        };
+        let err = Runtime::new(book, config).unwrap_err();
+        assert_eq!(
+            err,
+            CoreError::Unsafe("@main is unsafe: DUP nodes 1; refers to @g\n@g is unsafe: DUP nodes 1\n@main duplicates @g at node 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_runtime_refuses_unsafe_copy() {
+        let book = Book::parse(UNSAFE).unwrap();
+        let g = book.id("g").unwrap();
+        for config in [Config::new(), par(1), par(4), lazy()] {
+            let config = Config {
+                refuse_unsafe: true,
+                ..config
+            };
+            // Not visible statically, so only caught while running
+            let runtime = Runtime::new(book.clone(), config).unwrap();
+            assert_eq!(runtime.run().unwrap_err(), CoreError::UnsafeDup(g));
+        }
+        let runtime = Runtime::new(book, Config::new()).unwrap();
+        assert!(runtime.run().is_ok());
+    }
+}
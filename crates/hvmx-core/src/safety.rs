@@ -0,0 +1,276 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: safety.rs
+// Location: crates/hvmx-core/src/safety.rs
+// Purpose: Duplication-safety analysis for books
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Which definitions may be copied by a DUP.
+//!
+//! All DUP nodes share one label, so a DUP that copies a definition
+//! containing DUPs annihilates with them instead of commuting, and the
+//! result is wrong. A definition is safe when it has no DUP nodes and only
+//! refers to safe definitions; safe definitions are copied as references
+//! without being expanded, like `interact_call` in `docs/dor/hvm.c`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Book, Pair, Port, Tag, Val};
+
+/// Result of the analysis over a whole book
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SafetyReport {
+    /// Unsafe definitions, in fid order
+    pub defs: Vec<UnsafeDef>,
+    /// DUP nodes wired straight to an unsafe definition
+    pub sites: Vec<DupSite>,
+}
+
+/// A definition that must not be duplicated, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsafeDef {
+    pub fid: Val,
+    pub name: String,
+    /// Its own DUP nodes, as template node indices
+    pub dups: Vec<Val>,
+    /// Unsafe definitions it refers to
+    pub refs: Vec<String>,
+}
+
+/// A DUP in `def` that copies the unsafe definition `target`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DupSite {
+    pub def: String,
+    /// Template node index of the DUP
+    pub node: Val,
+    pub target: String,
+}
+
+impl SafetyReport {
+    /// Is no unsafe definition duplicated directly?
+    pub fn is_safe(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// Is the definition safe to copy?
+    pub fn is_safe_def(&self, fid: Val) -> bool {
+        !self.defs.iter().any(|def| def.fid == fid)
+    }
+}
+
+/// Every port of a template: root, redexes, then nodes
+fn ports(book: &Book, fid: Val) -> Vec<Port> {
+    let tmpl = book.template(fid).unwrap();
+    let pairs = tmpl.rbag.iter().chain(&tmpl.node);
+    let mut ports = vec![tmpl.root];
+    ports.extend(pairs.flat_map(|pair| [pair.fst(), pair.snd()]));
+    ports.retain(|&port| port != Port::NONE);
+    ports
+}
+
+impl Book {
+    /// Runs the analysis without touching the templates
+    pub fn safety(&self) -> SafetyReport {
+        let fids = 0..self.len() as Val;
+        let dups: Vec<Vec<Val>> = fids
+            .clone()
+            .map(|fid| {
+                let mut dups: Vec<Val> = ports(self, fid)
+                    .into_iter()
+                    .filter(|port| port.tag() == Tag::Dup)
+                    .map(|port| port.val())
+                    .collect();
+                dups.sort_unstable();
+                dups.dedup();
+                dups
+            })
+            .collect();
+        let refs: Vec<Vec<Val>> = fids
+            .clone()
+            .map(|fid| {
+                let mut refs: Vec<Val> = ports(self, fid)
+                    .into_iter()
+                    .filter(|port| port.tag() == Tag::Ref)
+                    .map(|port| port.val())
+                    .collect();
+                refs.sort_unstable();
+                refs.dedup();
+                refs
+            })
+            .collect();
+
+        // Greatest fixed point: recursion alone doesn't make a definition unsafe
+        let mut safe: Vec<bool> = dups.iter().map(Vec::is_empty).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for fid in fids.clone() {
+                let i = fid as usize;
+                if safe[i] && refs[i].iter().any(|&r| !safe.get(r as usize).copied().unwrap_or(false)) {
+                    safe[i] = false;
+                    changed = true;
+                }
+            }
+        }
+
+        let name = |fid: Val| match self.get_by_id(fid) {
+            Some(def) => def.name.clone(),
+            None => fid.to_string(),
+        };
+        let is_unsafe = |fid: Val| !safe.get(fid as usize).copied().unwrap_or(false);
+        let mut report = SafetyReport::default();
+        for fid in fids {
+            let i = fid as usize;
+            if !safe[i] {
+                report.defs.push(UnsafeDef {
+                    fid,
+                    name: name(fid),
+                    dups: dups[i].clone(),
+                    refs: refs[i].iter().filter(|&&r| is_unsafe(r)).map(|&r| name(r)).collect(),
+                });
+            }
+            let tmpl = self.template(fid).unwrap();
+            for (node, target) in dup_sites(&tmpl.rbag) {
+                if is_unsafe(target) {
+                    report.sites.push(DupSite {
+                        def: name(fid),
+                        node,
+                        target: name(target),
+                    });
+                }
+            }
+        }
+        report
+    }
+
+    /// Runs the analysis and stores each definition's flag in its template
+    pub fn propagate_safety(&mut self) -> SafetyReport {
+        let report = self.safety();
+        for fid in 0..self.len() as Val {
+            self.set_safe(fid, report.is_safe_def(fid));
+        }
+        report
+    }
+}
+
+/// `(dup node, ref fid)` for every DUP facing a REF in a redex, either
+/// directly or through one variable shared by two redexes
+fn dup_sites(rbag: &[Pair]) -> Vec<(Val, Val)> {
+    let mut sites = Vec::new();
+    let mut ends: HashMap<Val, Vec<Port>> = HashMap::new();
+    for redex in rbag {
+        let (a, b) = (redex.fst(), redex.snd());
+        for (x, y) in [(a, b), (b, a)] {
+            if x.tag() == Tag::Ref && y.tag() == Tag::Dup {
+                sites.push((y.val(), x.val()));
+            }
+            if x.is_var() && x != Port::NONE {
+                ends.entry(x.val()).or_default().push(y);
+            }
+        }
+    }
+    let mut vars: Vec<_> = ends.into_iter().collect();
+    vars.sort_unstable_by_key(|(var, _)| *var);
+    for (_, ends) in vars {
+        if let [x, y] = ends[..] {
+            for (x, y) in [(x, y), (y, x)] {
+                if x.tag() == Tag::Ref && y.tag() == Tag::Dup {
+                    sites.push((y.val(), x.val()));
+                }
+            }
+        }
+    }
+    sites
+}
+
+/// One line per unsafe definition and per duplicator site
+impl fmt::Display for SafetyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for def in &self.defs {
+            write!(f, "@{} is unsafe:", def.name)?;
+            if !def.dups.is_empty() {
+                let dups: Vec<String> = def.dups.iter().map(Val::to_string).collect();
+                write!(f, " DUP nodes {}", dups.join(", "))?;
+            }
+            if !def.refs.is_empty() {
+                let refs: Vec<String> = def.refs.iter().map(|nam| format!("@{}", nam)).collect();
+                write!(f, "{}refers to {}", if def.dups.is_empty() { " " } else { "; " }, refs.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        for site in &self.sites {
+            writeln!(f, "@{} duplicates @{} at node {}", site.def, site.target, site.node)?;
+        }
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_propagates_through_refs() {
+        let book = Book::parse(
+            "@main = r & @f ~ (0 r)\n@f = (x y) & @g ~ (x y)\n@g = ({a b} (a b))\n@id = (x x)",
+        )
+        .unwrap();
+        let report = book.safety();
+        let unsafe_defs: Vec<&str> = report.defs.iter().map(|def| def.name.as_str()).collect();
+        assert_eq!(unsafe_defs, vec!["main", "f", "g"]);
+        assert_eq!(report.defs[1].dups, Vec::<Val>::new());
+        assert_eq!(report.defs[1].refs, vec!["g".to_string()]);
+        assert_eq!(report.defs[2].dups, vec![1]);
+        assert!(report.is_safe_def(book.id("id").unwrap()));
+        assert!(report.is_safe());
+    }
+
+    #[test]
+    fn test_safety_recursion_is_safe() {
+        let book = Book::parse("@main = @loop\n@loop = (* @loop)").unwrap();
+        assert!(book.safety().defs.is_empty());
+        assert!(book.template(1).unwrap().safe);
+    }
+
+    #[test]
+    fn test_safety_dup_sites() {
+        let src = "@main = (a (b c)) & @g ~ {a b} & @id ~ {c d} & @g ~ e & {d *} ~ e\n\
+                   @g = ({a b} (a b))\n@id = (x x)";
+        let book = Book::parse(src).unwrap();
+        let report = book.safety();
+        assert!(!report.is_safe());
+        let sites: Vec<(&str, Val, &str)> =
+            report.sites.iter().map(|s| (s.def.as_str(), s.node, s.target.as_str())).collect();
+        assert_eq!(sites, vec![("main", 2, "g"), ("main", 4, "g")]);
+        assert_eq!(
+            report.to_string(),
+            "@main is unsafe: DUP nodes 2, 3, 4; refers to @g\n\
+             @g is unsafe: DUP nodes 1\n\
+             @main duplicates @g at node 2\n\
+             @main duplicates @g at node 4\n"
+        );
+    }
+
+    #[test]
+    fn test_propagate_safety_sets_flags() {
+        let mut book = Book::new();
+        let parsed = Book::parse("@main = @id\n@id = (x x)").unwrap();
+        for (_, def) in parsed.iter() {
+            book.insert(def.name.clone(), def.clone());
+        }
+        // Built by hand, references are assumed unsafe until analyzed
+        assert!(!book.template(0).unwrap().safe);
+        assert!(book.propagate_safety().defs.is_empty());
+        assert!(book.template(0).unwrap().safe);
+        assert_eq!(book, parsed);
+    }
+}
@@ -0,0 +1,243 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: show.rs
+// Location: crates/hvmx-core/src/show.rs
+// Purpose: Pretty-printer for Book, Def and GNet
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Canonical HVM2 text for books and nets.
+//!
+//! Nets are walked root first, then redex by redex, each tree in pre-order.
+//! Variables are named `a`, `b`, ..., `z`, `aa`, ... in order of first
+//! appearance, which is also the order the parser allocates them in, so
+//! printing then parsing a parsed book gives back the same book.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{Net, Tree};
+use crate::book::Def;
+use crate::{Book, GNet, Numb, Port, Tag};
+
+/// Variable name for the `idx`-th variable: a..z, aa..zz, aaa...
+fn var_name(mut idx: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (idx % 26) as u8);
+        if idx < 26 {
+            break;
+        }
+        idx = idx / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+/// Names variables and references while a net is read back
+struct Namer<'a> {
+    net: &'a GNet,
+    book: Option<&'a Book>,
+    vars: HashMap<Port, String>,
+}
+
+impl Namer<'_> {
+    fn var(&mut self, port: Port) -> String {
+        let next = self.vars.len();
+        self.vars.entry(port).or_insert_with(|| var_name(next)).clone()
+    }
+
+    fn reference(&self, port: Port) -> String {
+        match self.book.and_then(|book| book.get_by_id(port.val())) {
+            Some(def) => def.name.clone(),
+            None => port.val().to_string(),
+        }
+    }
+
+    /// Reads the tree hanging from a port, following variable chains
+    fn tree(&mut self, port: Port) -> Tree {
+        enum Item {
+            Port(Port),
+            Node(Tag),
+        }
+        let mut stack = vec![Item::Port(port)];
+        let mut done = Vec::new();
+        while let Some(item) = stack.pop() {
+            let port = match item {
+                Item::Node(tag) => {
+                    let snd = done.pop().unwrap();
+                    let fst = done.pop().unwrap();
+                    done.push(Tree::node(tag, fst, snd));
+                    continue;
+                }
+                Item::Port(port) => self.net.peek(port),
+            };
+            match port.tag() {
+                Tag::Var => done.push(Tree::Var { nam: self.var(port) }),
+                Tag::Ref => done.push(Tree::Ref { nam: self.reference(port) }),
+                Tag::Era => done.push(Tree::Era),
+                Tag::Num => done.push(Tree::Num { val: Numb::new(port.val()) }),
+                tag => {
+                    let node = self.net.node_load(port.val());
+                    stack.push(Item::Node(tag));
+                    stack.push(Item::Port(node.snd()));
+                    stack.push(Item::Port(node.fst()));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl GNet {
+    /// Reads the whole net back as a syntax tree. References are named after
+    /// their definitions in `book`, or by fid without one.
+    pub fn to_ast(&self, book: Option<&Book>) -> Net {
+        let mut namer = Namer {
+            net: self,
+            book,
+            vars: HashMap::new(),
+        };
+        let root = namer.tree(self.root());
+        let rbag = self
+            .redexes
+            .iter()
+            .map(|redex| (namer.tree(redex.fst()), namer.tree(redex.snd())))
+            .collect();
+        Net { root, rbag }
+    }
+
+    /// Reads the tree hanging from `port` back as a syntax tree
+    pub fn to_tree(&self, port: Port, book: Option<&Book>) -> Tree {
+        let mut namer = Namer {
+            net: self,
+            book,
+            vars: HashMap::new(),
+        };
+        namer.tree(port)
+    }
+
+    /// Displays the net with references named after `book`'s definitions
+    pub fn display<'a>(&'a self, book: &'a Book) -> Show<'a> {
+        Show { net: self, book }
+    }
+}
+
+/// A net printed along with the book its references point into
+pub struct Show<'a> {
+    net: &'a GNet,
+    book: &'a Book,
+}
+
+impl fmt::Display for Show<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.net.to_ast(Some(self.book)))
+    }
+}
+
+/// References print as `@fid`; use `GNet::display` to name them
+impl fmt::Display for GNet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_ast(None))
+    }
+}
+
+/// References print as `@fid`, as for `GNet`
+impl fmt::Display for Def {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{} = {}", self.name, self.net)
+    }
+}
+
+/// One definition per line, in fid order
+impl fmt::Display for Book {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (_, def) in self.iter() {
+            writeln!(f, "@{} = {}", def.name, def.net.display(self))?;
+        }
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus;
+
+    const SOURCES: [&str; 5] = [
+        "@main = 7",
+        "@main = r & @id ~ (+42 r)\n@id = (x x)",
+        "@main = r & @c2 ~ (@inc (0 r))\n@c2 = ({(x y) (y z)} (x z))\n@inc = (x y) & [+1] ~ $(x y)",
+        "@main = r & @sum ~ (100 r)\n@sum = (?((0 @sum.s) r) r)\n\
+         @sum.s = ({p0 p1} r) & @sum ~ (p0 $([+] $(q r))) & [+1] ~ $(p1 q)",
+        "@main = (a (b c)) & * ~ [:<<-3] & $(b [u24]) ~ {a c} & 1.5 ~ ?(-0.25 *)",
+    ];
+
+    #[test]
+    fn test_var_names() {
+        assert_eq!(var_name(0), "a");
+        assert_eq!(var_name(25), "z");
+        assert_eq!(var_name(26), "aa");
+        assert_eq!(var_name(27), "ab");
+        assert_eq!(var_name(26 + 26 * 26), "aaa");
+    }
+
+    #[test]
+    fn test_show_book() {
+        let book = Book::parse(SOURCES[2]).unwrap();
+        assert_eq!(
+            book.to_string(),
+            "@main = a\n  & @c2 ~ (@inc (0 a))\n\
+             @c2 = ({(a b) (b c)} (a c))\n\
+             @inc = (a b)\n  & [+1] ~ $(a b)\n"
+        );
+    }
+
+    #[test]
+    fn test_show_def_and_net() {
+        let book = Book::parse(SOURCES[1]).unwrap();
+        let main = book.get("main").unwrap();
+        assert_eq!(main.to_string(), "@main = a\n  & @1 ~ (+42 a)");
+        assert_eq!(main.net.display(&book).to_string(), "a\n  & @id ~ (+42 a)");
+    }
+
+    #[test]
+    fn test_show_roundtrip() {
+        for src in SOURCES {
+            let book = Book::parse(src).unwrap();
+            let text = book.to_string();
+            assert_eq!(Book::parse(&text).unwrap(), book, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_show_corpus_is_stable() {
+        for (name, book, _) in corpus::all() {
+            let text = book.to_string();
+            let again = Book::parse(&text).unwrap();
+            assert_eq!(again.to_string(), text, "{}", name);
+            assert_eq!(Book::parse(&again.to_string()).unwrap(), again, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_show_follows_substitutions() {
+        // Mid-reduction, variables point at what they were linked to
+        let book = Book::parse(SOURCES[1]).unwrap();
+        let mut net = GNet::new();
+        net.boot(&book).unwrap();
+        let redex = net.redexes.pop().unwrap();
+        crate::interact(&mut net, &book, redex.fst(), redex.snd()).unwrap();
+        assert_eq!(net.display(&book).to_string(), "a\n  & @id ~ (+42 a)");
+
+        net.reduce(&book).unwrap();
+        assert_eq!(net.to_string(), "+42");
+    }
+}
@@ -0,0 +1,290 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: snapshot.rs
+// Location: crates/hvmx-core/src/snapshot.rs
+// Purpose: Saving paused evaluations to disk and resuming them
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Paused evaluations, after `Heap::serialize` in `docs/dor/hvm.rs`.
+//!
+//! A snapshot is a net, its redex bag and the book it runs on, so it can
+//! be resumed by any evaluator. All words are little-endian.
+//!
+//! ```text
+//! snapshot ::= MAGIC VERSION payload_len checksum payload
+//! payload  ::= book_len book[book_len]
+//!              root node_len node_pair[node_len]
+//!              vars_len var[vars_len] rbag_len rbag_pair[rbag_len]
+//! ```
+//!
+//! `book` is a binary book (see `format`). The checksum is FNV-1a over the
+//! payload bytes. Slot 0 of both buffers is included; checkpoints are not.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::{Book, CoreError, GNet, Pair, Port, Result, Tag};
+
+/// "HVMS" in little-endian
+pub const MAGIC: u32 = u32::from_le_bytes(*b"HVMS");
+/// Format version
+pub const VERSION: u32 = 1;
+/// Header length in words
+pub const HEADER_LEN: usize = 4;
+
+fn invalid<T>(msg: impl Into<String>) -> Result<T> {
+    Err(CoreError::InvalidBuffer(msg.into()))
+}
+
+/// FNV-1a over the words' little-endian bytes
+fn checksum(words: &[u32]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in words.iter().flat_map(|w| w.to_le_bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// A paused evaluation: a net and the book it runs on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub book: Book,
+    pub net: GNet,
+}
+
+impl Snapshot {
+    pub fn new(book: Book, net: GNet) -> Self {
+        Self { book, net }
+    }
+
+    /// Serializes the snapshot
+    pub fn to_buffer(&self) -> Result<Vec<u32>> {
+        let book = self.book.to_buffer()?;
+        let (nodes, vars) = self.net.buffers();
+        let mut buf = vec![MAGIC, VERSION, 0, 0, book.len() as u32];
+        buf.extend(book);
+        buf.push(u32::from(self.net.vars_load(Port::ROOT.val())));
+        buf.push(nodes.len() as u32);
+        buf.extend(nodes.iter().flat_map(|pair| [u32::from(pair.fst()), u32::from(pair.snd())]));
+        buf.push(vars.len() as u32);
+        buf.extend(vars.iter().map(u32::from));
+        buf.push(self.net.redexes.len() as u32);
+        buf.extend(self.net.redexes.iter().flat_map(|pair| [u32::from(pair.fst()), u32::from(pair.snd())]));
+        buf[2] = (buf.len() - HEADER_LEN) as u32;
+        buf[3] = checksum(&buf[HEADER_LEN..]);
+        Ok(buf)
+    }
+
+    /// Serializes the snapshot as little-endian bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.to_buffer()?.iter().flat_map(|w| w.to_le_bytes()).collect())
+    }
+
+    /// Loads a snapshot, checking its header, checksum and ports
+    pub fn from_buffer(buf: &[u32]) -> Result<Snapshot> {
+        if buf.len() < HEADER_LEN {
+            return invalid("snapshot too short for its header");
+        }
+        if buf[0] != MAGIC {
+            return invalid(format!("bad snapshot magic {:#010x}", buf[0]));
+        }
+        if buf[1] != VERSION {
+            return invalid(format!("unsupported snapshot version {}", buf[1]));
+        }
+        let payload = &buf[HEADER_LEN..];
+        if payload.len() != buf[2] as usize {
+            return invalid(format!("payload is {} words, header says {}", payload.len(), buf[2]));
+        }
+        if checksum(payload) != buf[3] {
+            return invalid("snapshot checksum mismatch");
+        }
+
+        let mut words = payload.iter().copied();
+        let mut next = |what: &str| words.next().ok_or_else(|| CoreError::InvalidBuffer(format!("snapshot ends in {}", what)));
+        let book_len = next("book")? as usize;
+        let book: Vec<u32> = (0..book_len).map(|_| next("book")).collect::<Result<_>>()?;
+        let book = Book::from_buffer(&book)?;
+        let root = Port::from(next("root")?);
+        let node_len = next("nodes")? as usize;
+        let mut nodes = Vec::with_capacity(node_len.min(payload.len()));
+        for _ in 0..node_len {
+            nodes.push(Pair::new(Port::from(next("nodes")?), Port::from(next("nodes")?)));
+        }
+        let vars_len = next("vars")? as usize;
+        let vars: Vec<Port> = (0..vars_len).map(|_| next("vars").map(Port::from)).collect::<Result<_>>()?;
+        let rbag_len = next("redexes")? as usize;
+        let mut redexes = Vec::with_capacity(rbag_len.min(payload.len()));
+        for _ in 0..rbag_len {
+            redexes.push(Pair::new(Port::from(next("redexes")?), Port::from(next("redexes")?)));
+        }
+        if next("end").is_ok() {
+            return invalid("trailing words after snapshot");
+        }
+        if nodes.is_empty() || vars.is_empty() {
+            return invalid("snapshot buffers lack their reserved slot");
+        }
+
+        let check = |port: Port| -> Result<()> {
+            if port == Port::NONE || port == Port::FREE || port == Port::ROOT {
+                return Ok(());
+            }
+            let val = port.val() as usize;
+            let ok = match port.tag() {
+                Tag::Var => val < vars.len(),
+                Tag::Ref => val < book.len(),
+                Tag::Era | Tag::Num => true,
+                _ => val > 0 && val < nodes.len(),
+            };
+            if !ok {
+                return invalid(format!("port {:?} out of range", port));
+            }
+            Ok(())
+        };
+        check(root)?;
+        for pair in nodes.iter().chain(&redexes) {
+            check(pair.fst())?;
+            check(pair.snd())?;
+        }
+        for var in &vars {
+            check(*var)?;
+        }
+        let net = GNet::from_buffers(nodes, vars, root, redexes);
+        if let Some(violation) = net.validate().violations.first() {
+            return invalid(format!("snapshot net is malformed: {}", violation));
+        }
+        Ok(Snapshot { book, net })
+    }
+
+    /// Loads a snapshot from little-endian bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot> {
+        if !bytes.len().is_multiple_of(4) {
+            return invalid("snapshot length is not a whole number of words");
+        }
+        let words: Vec<u32> = bytes.chunks(4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]])).collect();
+        Snapshot::from_buffer(&words)
+    }
+
+    /// Writes the snapshot to `path`, through a temporary file so that a
+    /// crash never leaves half a snapshot behind
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let io = |err: std::io::Error| CoreError::Io(format!("{}: {}", path.display(), err));
+        let mut file = std::fs::File::create(&tmp).map_err(io)?;
+        file.write_all(&self.to_bytes()?).map_err(io)?;
+        file.sync_all().map_err(io)?;
+        drop(file);
+        std::fs::rename(&tmp, path).map_err(io)
+    }
+
+    /// Reads a snapshot from `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|err| CoreError::Io(format!("{}: {}", path.display(), err)))?;
+        Snapshot::from_bytes(&bytes)
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, Budget, Meter, ParConfig};
+
+    /// A net paused halfway through `book`
+    fn paused(book: &Book) -> GNet {
+        let mut net = GNet::new();
+        net.boot(book).unwrap();
+        let mut meter = Meter::new(Budget {
+            max_mana: Some(20),
+            ..Budget::new()
+        });
+        assert!(net.reduce_metered(book, &mut meter).is_err());
+        net
+    }
+
+    fn temp(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hvmx-{}-{}.snap", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let book = corpus::sum(8);
+        let net = paused(&book);
+        let snap = Snapshot::new(book.clone(), net.clone());
+        let back = Snapshot::from_bytes(&snap.to_bytes().unwrap()).unwrap();
+        assert_eq!(back.book.to_buffer(), book.to_buffer());
+        assert_eq!(back.net.to_string(), net.to_string());
+        assert_eq!(back.net.redexes, net.redexes);
+        assert_eq!(back.net.node_count(), net.node_count());
+        assert_eq!(back.net.vars_count(), net.vars_count());
+    }
+
+    #[test]
+    fn test_snapshot_resume_from_disk() {
+        let book = corpus::sum(8);
+        let mut expected = GNet::new();
+        expected.normalize(&book).unwrap();
+
+        let path = temp("resume");
+        Snapshot::new(book.clone(), paused(&book)).save(&path).unwrap();
+        let mut snap = Snapshot::load(&path).unwrap();
+        snap.net.reduce(&snap.book).unwrap();
+        assert_eq!(snap.net.to_string(), expected.to_string());
+
+        // On another backend
+        let mut snap = Snapshot::load(&path).unwrap();
+        snap.net.reduce_par(&snap.book, &ParConfig::new(2)).unwrap();
+        assert_eq!(snap.net.root(), expected.root());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let book = corpus::sum(4);
+        let buf = Snapshot::new(book.clone(), paused(&book)).to_buffer().unwrap();
+        let error = |buf: &[u32]| match Snapshot::from_buffer(buf) {
+            Err(CoreError::InvalidBuffer(msg)) => msg,
+            other => panic!("expected an invalid buffer, got {:?}", other),
+        };
+
+        assert!(error(&buf[..3]).contains("too short"));
+        let mut bad = buf.clone();
+        bad[0] = crate::format::MAGIC;
+        assert!(error(&bad).contains("magic"));
+        bad = buf.clone();
+        bad[1] = VERSION + 1;
+        assert!(error(&bad).contains("version"));
+        assert!(error(&buf[..buf.len() - 1]).contains("payload"));
+        bad = buf.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(error(&bad), "snapshot checksum mismatch");
+
+        // A well-formed buffer with a dangling port
+        bad = buf.clone();
+        let root = HEADER_LEN + 1 + bad[HEADER_LEN] as usize;
+        bad[root] = u32::from(Port::new(Tag::Con, 1 << 20));
+        bad[3] = checksum(&bad[HEADER_LEN..]);
+        assert!(error(&bad).contains("out of range"));
+
+        // In range, checksum intact, but a live port points at a freed node
+        bad = buf.clone();
+        let node = root + 4;
+        bad[node] = u32::from(Port::FREE);
+        bad[node + 1] = u32::from(Port::FREE);
+        bad[3] = checksum(&bad[HEADER_LEN..]);
+        assert!(error(&bad).contains("malformed"));
+
+        assert!(matches!(Snapshot::from_bytes(&[0; 7]), Err(CoreError::InvalidBuffer(_))));
+        assert!(matches!(Snapshot::load(temp("missing")), Err(CoreError::Io(_))));
+    }
+}
@@ -0,0 +1,1525 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: term.rs
+// Location: crates/hvmx-core/src/term.rs
+// Purpose: Lambda-calculus front-end: terms, their parser, compiler and readback
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Lambda terms, after `Term` in `docs/dor/hvm.rs`.
+//!
+//! ```text
+//! defs ::= (Name "=" term)*
+//! term ::= "@" name term                     lambda, also "λ"
+//!        | "(" term term+ ")"                application, also "(!f x)"
+//!        | "(" op term term ")"              + - * / % == != < > & | ^ << >>
+//!        | "(" "?" term term term ")"        switch: zero case, then successor
+//!        | "(" Name term* ")"                call of a definition
+//!        | "{" Name term* "}"                constructor
+//!        | "dup" name name "=" term ";" term
+//!        | "let" name "=" term ";" term
+//!        | "#"? numb | name | "*"
+//! ```
+//!
+//! A lambda is a CON node whose first port binds the variable. A variable
+//! used more than once gets a chain of DUP nodes, an unused one an ERA.
+//! Constructors are Scott-encoded over every constructor of the program,
+//! sorted by name: with `N` of them, `{C_i a b}` is `@c0 .. @cN-1 (c_i a b)`.
+//! `Main` compiles to `@main`, and closed lambdas that call definitions are
+//! moved into definitions of their own so recursion stays lazy. Switch
+//! cases get the variables they capture as extra arguments first, so that
+//! they are closed.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::{Net, Tree};
+use crate::book::Def;
+use crate::parse::Parser;
+use crate::{Book, CoreError, GNet, Numb, Result, Tag, Val};
+
+/// Term operators, longest first
+const OPERS: [(&str, u32); 14] = [
+    ("==", Numb::OP_EQ),
+    ("!=", Numb::OP_NEQ),
+    ("<<", Numb::OP_SHL),
+    (">>", Numb::OP_SHR),
+    ("+", Numb::OP_ADD),
+    ("-", Numb::OP_SUB),
+    ("*", Numb::OP_MUL),
+    ("/", Numb::OP_DIV),
+    ("%", Numb::OP_REM),
+    ("<", Numb::OP_LT),
+    (">", Numb::OP_GT),
+    ("&", Numb::OP_AND),
+    ("|", Numb::OP_OR),
+    ("^", Numb::OP_XOR),
+];
+
+/// Lambda term.
+///
+/// Parsing, compiling, reading back, cloning, comparing, printing and
+/// dropping keep their own stacks, as for `Tree`, so terms of any depth are
+/// safe to handle.
+#[derive(Debug)]
+pub enum Term {
+    /// `name`
+    Var { name: String },
+    /// `dup nam0 nam1 = expr; body`
+    Dup { nam0: String, nam1: String, expr: Box<Term>, body: Box<Term> },
+    /// `@name body`
+    Lam { name: String, body: Box<Term> },
+    /// `(func argm)`
+    App { func: Box<Term>, argm: Box<Term> },
+    /// `{Name args..}`
+    Ctr { name: String, args: Vec<Term> },
+    /// `(Name args..)`
+    Fun { name: String, args: Vec<Term> },
+    /// `123`
+    Num { numb: Numb },
+    /// `(+ val0 val1)`, with a `Numb::OP_*` operator
+    Op2 { oper: u32, val0: Box<Term>, val1: Box<Term> },
+    /// `*`, an erased value
+    Era,
+    /// `(? expr zero succ)`: `zero` if `expr` is 0, else `(succ expr-1)`
+    Swi { expr: Box<Term>, zero: Box<Term>, succ: Box<Term> },
+}
+
+impl Term {
+    pub fn var(name: &str) -> Term {
+        Term::Var { name: name.to_string() }
+    }
+
+    pub fn lam(name: &str, body: Term) -> Term {
+        Term::Lam {
+            name: name.to_string(),
+            body: Box::new(body),
+        }
+    }
+
+    pub fn app(func: Term, argm: Term) -> Term {
+        Term::App {
+            func: Box::new(func),
+            argm: Box::new(argm),
+        }
+    }
+
+    /// Parses a single term
+    pub fn parse(src: &str) -> Result<Term> {
+        let mut parser = Parser::new(src);
+        let term = parser.term()?;
+        parser.skip_trivia();
+        if parser.peek().is_some() {
+            return parser.error(format!("expected end of input, found {}", parser.found()));
+        }
+        Ok(term)
+    }
+
+    /// Compiles the term as `Main`
+    pub fn compile(&self) -> Result<Program> {
+        Program::compile(&[("Main".to_string(), self.clone())])
+    }
+
+    /// Subterms, in source order
+    fn children(&self) -> Vec<&Term> {
+        match self {
+            Term::Var { .. } | Term::Era | Term::Num { .. } => Vec::new(),
+            Term::Dup { expr, body, .. } => vec![expr, body],
+            Term::Lam { body, .. } => vec![body],
+            Term::App { func, argm } => vec![func, argm],
+            Term::Ctr { args, .. } | Term::Fun { args, .. } => args.iter().collect(),
+            Term::Op2 { val0, val1, .. } => vec![val0, val1],
+            Term::Swi { expr, zero, succ } => vec![expr, zero, succ],
+        }
+    }
+
+    fn children_mut(&mut self) -> Vec<&mut Term> {
+        match self {
+            Term::Var { .. } | Term::Era | Term::Num { .. } => Vec::new(),
+            Term::Dup { expr, body, .. } => vec![expr, body],
+            Term::Lam { body, .. } => vec![body],
+            Term::App { func, argm } => vec![func, argm],
+            Term::Ctr { args, .. } | Term::Fun { args, .. } => args.iter_mut().collect(),
+            Term::Op2 { val0, val1, .. } => vec![val0, val1],
+            Term::Swi { expr, zero, succ } => vec![expr, zero, succ],
+        }
+    }
+
+    /// Takes the term out, leaving `*` in its place
+    pub(crate) fn take(&mut self) -> Term {
+        std::mem::replace(self, Term::Era)
+    }
+
+    /// Takes the subterms out, in source order
+    fn detach(&mut self) -> Vec<Term> {
+        self.children_mut().into_iter().map(Term::take).collect()
+    }
+
+    /// Puts back subterms taken by `detach`
+    fn attach(&mut self, children: Vec<Term>) {
+        for (slot, child) in self.children_mut().into_iter().zip(children) {
+            *slot = child;
+        }
+    }
+
+    /// Copy of the term with `*` for each subterm
+    fn shallow(&self) -> Term {
+        let era = || Box::new(Term::Era);
+        match self {
+            Term::Var { name } => Term::var(name),
+            Term::Dup { nam0, nam1, .. } => Term::Dup {
+                nam0: nam0.clone(),
+                nam1: nam1.clone(),
+                expr: era(),
+                body: era(),
+            },
+            Term::Lam { name, .. } => Term::lam(name, Term::Era),
+            Term::App { .. } => Term::app(Term::Era, Term::Era),
+            Term::Ctr { name, args } => Term::Ctr {
+                name: name.clone(),
+                args: args.iter().map(|_| Term::Era).collect(),
+            },
+            Term::Fun { name, args } => Term::Fun {
+                name: name.clone(),
+                args: args.iter().map(|_| Term::Era).collect(),
+            },
+            Term::Num { numb } => Term::Num { numb: *numb },
+            Term::Op2 { oper, .. } => Term::Op2 {
+                oper: *oper,
+                val0: era(),
+                val1: era(),
+            },
+            Term::Era => Term::Era,
+            Term::Swi { .. } => Term::Swi {
+                expr: era(),
+                zero: era(),
+                succ: era(),
+            },
+        }
+    }
+
+    /// Whether two terms agree, subterms aside
+    fn same_node(&self, other: &Term) -> bool {
+        match (self, other) {
+            (Term::Var { name: a }, Term::Var { name: b }) => a == b,
+            (Term::Dup { nam0: a0, nam1: a1, .. }, Term::Dup { nam0: b0, nam1: b1, .. }) => a0 == b0 && a1 == b1,
+            (Term::Lam { name: a, .. }, Term::Lam { name: b, .. }) => a == b,
+            (Term::Ctr { name: a, args: x }, Term::Ctr { name: b, args: y })
+            | (Term::Fun { name: a, args: x }, Term::Fun { name: b, args: y }) => a == b && x.len() == y.len(),
+            (Term::Num { numb: a }, Term::Num { numb: b }) => a == b,
+            (Term::Op2 { oper: a, .. }, Term::Op2 { oper: b, .. }) => a == b,
+            (Term::App { .. }, Term::App { .. }) | (Term::Era, Term::Era) | (Term::Swi { .. }, Term::Swi { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Rebuilds the term with `f` applied to each subterm
+    pub(crate) fn map(mut self, mut f: impl FnMut(Term) -> Term) -> Term {
+        for child in self.children_mut() {
+            *child = f(child.take());
+        }
+        self
+    }
+
+    /// Rebuilds the term, keeping its own stack: `down` rewrites each
+    /// subterm before its own subterms are visited, and `up` once they are
+    /// rebuilt, given what `up` returned for each of them
+    pub(crate) fn rebuild<T>(
+        self,
+        mut down: impl FnMut(Term) -> Term,
+        mut up: impl FnMut(Term, Vec<T>) -> (Term, T),
+    ) -> (Term, T) {
+        // A term whose subterms are being rebuilt, with those left to do
+        // and those done
+        type Frame<T> = (Term, std::vec::IntoIter<Term>, Vec<(Term, T)>);
+        let mut stack: Vec<Frame<T>> = Vec::new();
+        let mut next = self;
+        loop {
+            let mut term = down(next);
+            let mut todo = term.detach().into_iter();
+            match todo.next() {
+                Some(child) => {
+                    stack.push((term, todo, Vec::new()));
+                    next = child;
+                    continue;
+                }
+                None => {
+                    let mut done = up(term, Vec::new());
+                    loop {
+                        let Some((_, todo, kids)) = stack.last_mut() else {
+                            return done;
+                        };
+                        kids.push(done);
+                        if let Some(child) = todo.next() {
+                            next = child;
+                            break;
+                        }
+                        let (mut term, _, kids) = stack.pop().unwrap();
+                        let (children, vals) = kids.into_iter().unzip();
+                        term.attach(children);
+                        done = up(term, vals);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Variables that occur free, in order of first occurrence
+    fn free(&self) -> Vec<&str> {
+        enum Item<'a> {
+            Term(&'a Term),
+            Bind(&'a str),
+            Unbind,
+        }
+        let mut bound: Vec<&str> = Vec::new();
+        let mut free: Vec<&str> = Vec::new();
+        let mut stack = vec![Item::Term(self)];
+        while let Some(item) = stack.pop() {
+            let term = match item {
+                Item::Term(term) => term,
+                Item::Bind(name) => {
+                    bound.push(name);
+                    continue;
+                }
+                Item::Unbind => {
+                    bound.pop();
+                    continue;
+                }
+            };
+            match term {
+                Term::Var { name } => {
+                    if !bound.contains(&name.as_str()) && !free.contains(&name.as_str()) {
+                        free.push(name);
+                    }
+                }
+                Term::Dup { nam0, nam1, expr, body } => {
+                    stack.extend([Item::Unbind, Item::Unbind, Item::Term(body)]);
+                    stack.extend([Item::Bind(nam1), Item::Bind(nam0), Item::Term(expr)]);
+                }
+                Term::Lam { name, body } => {
+                    stack.extend([Item::Unbind, Item::Term(body), Item::Bind(name)]);
+                }
+                term => stack.extend(term.children().into_iter().rev().map(Item::Term)),
+            }
+        }
+        free
+    }
+
+    /// Whether every variable is bound inside the term
+    fn is_closed(&self) -> bool {
+        self.free().is_empty()
+    }
+
+    /// Whether the term calls a definition
+    fn calls(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(term) = stack.pop() {
+            if matches!(term, Term::Fun { .. }) {
+                return true;
+            }
+            stack.extend(term.children());
+        }
+        false
+    }
+
+    /// Moves closed lambdas that call definitions out into definitions of
+    /// their own, named `def.0`, `def.1`, ... Nets reduce under lambdas, so
+    /// a recursive call left in place would unfold forever; as a REF it is
+    /// only expanded once the lambda is applied. The lambdas a definition
+    /// starts with stay where they are.
+    fn float(self, def: &str, lifted: &mut Vec<(String, Term)>, taken: &HashSet<String>) -> Term {
+        let mut names = Vec::new();
+        let mut body = self;
+        while let Term::Lam { name, body: next } = &mut body {
+            names.push(std::mem::take(name));
+            body = next.take();
+        }
+        let body = body.close_cases().map(|child| {
+            let lift = |term: Term, _| (Term::lift(term, def, lifted, taken), ());
+            child.rebuild(Term::close_cases, lift).0
+        });
+        names.iter().rev().fold(body, |body, name| Term::lam(name, body))
+    }
+
+    /// Moves the term to a new definition if `float` should
+    fn lift(self, def: &str, lifted: &mut Vec<(String, Term)>, taken: &HashSet<String>) -> Term {
+        if !matches!(self, Term::Lam { .. }) || !self.calls() || !self.is_closed() {
+            return self;
+        }
+        let name = (lifted.len()..)
+            .map(|i| format!("{}.{}", def, i))
+            .find(|name| !taken.contains(name))
+            .unwrap();
+        lifted.push((name.clone(), self));
+        Term::Fun { name, args: Vec::new() }
+    }
+
+    /// Passes the variables a switch's cases capture to them as extra
+    /// arguments, `((? n @m zero @p @m succ) m)`, so that cases that call
+    /// definitions are closed and can float.
+    fn close_cases(mut self) -> Term {
+        let Term::Swi { zero, succ, .. } = &self else {
+            return self;
+        };
+        let mut free: Vec<String> = zero.free().into_iter().map(String::from).collect();
+        for name in succ.free() {
+            if !free.iter().any(|nam| nam == name) {
+                free.push(name.to_string());
+            }
+        }
+        if free.is_empty() || !(zero.calls() || succ.calls()) {
+            return self;
+        }
+        let Term::Swi { expr, zero, succ } = &mut self else {
+            unreachable!();
+        };
+        let (expr, zero, mut succ) = (expr.take(), zero.take(), succ.take());
+        let close = |body: Term| free.iter().rev().fold(body, |body, name| Term::lam(name, body));
+        // Not a valid source name, so it can't capture anything
+        let (pred, body) = match &mut succ {
+            Term::Lam { name, body } => (std::mem::take(name), body.take()),
+            _ => ("%p".to_string(), Term::app(succ, Term::var("%p"))),
+        };
+        let swi = Term::Swi {
+            expr: Box::new(expr),
+            zero: Box::new(close(zero)),
+            succ: Box::new(Term::lam(&pred, close(body))),
+        };
+        free.iter().fold(swi, |func, name| Term::app(func, Term::var(name)))
+    }
+
+    /// Adds the constructors used, with their arity, to `ctrs`
+    pub(crate) fn constructors(&self, ctrs: &mut BTreeMap<String, usize>) -> Result<()> {
+        let mut stack = vec![self];
+        while let Some(term) = stack.pop() {
+            if let Term::Ctr { name, args } = term {
+                add_constructor(ctrs, name, args.len())?;
+            }
+            stack.extend(term.children().into_iter().rev());
+        }
+        Ok(())
+    }
+}
+
+impl Clone for Term {
+    fn clone(&self) -> Self {
+        enum Item<'a> {
+            Term(&'a Term),
+            Node(&'a Term),
+        }
+        let mut stack = vec![Item::Term(self)];
+        let mut done = Vec::new();
+        while let Some(item) = stack.pop() {
+            match item {
+                Item::Node(term) => {
+                    let children = done.split_off(done.len() - term.children().len());
+                    let mut copy = term.shallow();
+                    copy.attach(children);
+                    done.push(copy);
+                }
+                Item::Term(term) => {
+                    let children = term.children();
+                    if !children.is_empty() {
+                        stack.push(Item::Node(term));
+                        stack.extend(children.into_iter().rev().map(Item::Term));
+                    } else {
+                        done.push(term.shallow());
+                    }
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if !a.same_node(b) {
+                return false;
+            }
+            stack.extend(a.children().into_iter().zip(b.children()));
+        }
+        true
+    }
+}
+
+impl Drop for Term {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        let take = |term: &mut Term, stack: &mut Vec<Term>| {
+            for child in term.children_mut() {
+                if !matches!(child, Term::Var { .. } | Term::Era | Term::Num { .. }) {
+                    stack.push(child.take());
+                }
+            }
+        };
+        take(self, &mut stack);
+        while let Some(mut term) = stack.pop() {
+            take(&mut term, &mut stack);
+        }
+    }
+}
+
+/// Records a constructor's arity, failing if it was seen with another
+pub(crate) fn add_constructor(ctrs: &mut BTreeMap<String, usize>, name: &str, arity: usize) -> Result<()> {
+    let known = *ctrs.entry(name.to_string()).or_insert(arity);
+    if known != arity {
+        return Err(CoreError::Compile(format!(
+            "constructor {{{}}} is used with {} and {} fields",
+            name, known, arity
+        )));
+    }
+    Ok(())
+}
+
+impl FromStr for Term {
+    type Err = CoreError;
+
+    fn from_str(src: &str) -> Result<Term> {
+        Term::parse(src)
+    }
+}
+
+/// Definition name in the book
+fn book_name(name: &str) -> String {
+    if name == "Main" {
+        "main".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Definition name in terms
+fn term_name(name: &str) -> String {
+    if name == "main" {
+        "Main".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// ==============================================================================
+// PARSER
+// ==============================================================================
+
+/// A term being parsed, waiting on its subterms
+enum Open {
+    /// `@name` and its body
+    Lam(String),
+    /// `(op` and two operands
+    Op2(u32),
+    /// `(?` and three terms
+    Swi,
+    /// `(Name` and arguments up to `)`
+    Fun(String),
+    /// `(` and a function, then arguments up to `)`
+    App,
+    /// `{Name` and fields up to `}`
+    Ctr(String),
+    /// `dup nam0 nam1 =`, the expression, `;` and the body
+    Dup(String, String),
+    /// `let name =`, the expression, `;` and the body
+    Let(String),
+}
+
+impl<'a> Parser<'a> {
+    /// Parses a term, keeping its own stack so that nesting of any depth is
+    /// safe
+    pub fn term(&mut self) -> Result<Term> {
+        let mut stack: Vec<(Open, Vec<Term>)> = Vec::new();
+        loop {
+            let mut term = match self.term_head()? {
+                Ok(term) => term,
+                Err(open) => {
+                    stack.push((open, Vec::new()));
+                    match self.close(stack.last_mut().unwrap())? {
+                        Some(term) => {
+                            stack.pop();
+                            term
+                        }
+                        None => continue,
+                    }
+                }
+            };
+            // Hands the term to the ones waiting, closing those it completes
+            loop {
+                let Some(top) = stack.last_mut() else {
+                    return Ok(term);
+                };
+                top.1.push(term);
+                match self.close(top)? {
+                    Some(done) => {
+                        stack.pop();
+                        term = done;
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Parses a leaf term, or the start of one with subterms
+    fn term_head(&mut self) -> Result<std::result::Result<Term, Open>> {
+        self.skip_trivia();
+        match self.peek() {
+            Some('@' | 'λ') => {
+                self.advance();
+                Ok(Err(Open::Lam(self.name()?)))
+            }
+            Some('(') => {
+                self.advance();
+                self.skip_trivia();
+                let rest = self.rest();
+                let oper = OPERS.iter().find(|(sym, _)| {
+                    rest.starts_with(sym) && rest[sym.len()..].starts_with(|c: char| c.is_whitespace() || c == '(')
+                });
+                if let Some(&(sym, oper)) = oper {
+                    self.consume(sym)?;
+                    return Ok(Err(Open::Op2(oper)));
+                }
+                if self.try_consume("?") {
+                    return Ok(Err(Open::Swi));
+                }
+                self.try_consume("!");
+                if self.peek().is_some_and(|c| c.is_ascii_uppercase()) {
+                    return Ok(Err(Open::Fun(self.name()?)));
+                }
+                Ok(Err(Open::App))
+            }
+            Some('*') => {
+                self.advance();
+                Ok(Ok(Term::Era))
+            }
+            Some('{') => {
+                self.advance();
+                Ok(Err(Open::Ctr(self.name()?)))
+            }
+            Some('#') => {
+                self.advance();
+                Ok(Ok(Term::Num { numb: self.numb()? }))
+            }
+            Some(c) if c.is_ascii_digit() || (matches!(c, '+' | '-') && self.rest()[1..].starts_with(|c: char| c.is_ascii_digit())) => {
+                Ok(Ok(Term::Num { numb: self.numb()? }))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.name()?;
+                match name.as_str() {
+                    "dup" => {
+                        let nam0 = self.name()?;
+                        let nam1 = self.name()?;
+                        self.consume("=")?;
+                        Ok(Err(Open::Dup(nam0, nam1)))
+                    }
+                    "let" => {
+                        let name = self.name()?;
+                        self.consume("=")?;
+                        Ok(Err(Open::Let(name)))
+                    }
+                    _ => Ok(Ok(Term::Var { name })),
+                }
+            }
+            _ => self.error(format!("expected a term, found {}", self.found())),
+        }
+    }
+
+    /// Completes an open term if it has all its subterms, reading what
+    /// follows them
+    fn close(&mut self, (open, terms): &mut (Open, Vec<Term>)) -> Result<Option<Term>> {
+        let term = match open {
+            Open::Lam(name) if terms.len() == 1 => Term::lam(name, terms.pop().unwrap()),
+            Open::Op2(oper) if terms.len() == 2 => {
+                self.consume(")")?;
+                let val1 = Box::new(terms.pop().unwrap());
+                let val0 = Box::new(terms.pop().unwrap());
+                Term::Op2 { oper: *oper, val0, val1 }
+            }
+            Open::Swi if terms.len() == 3 => {
+                self.consume(")")?;
+                let succ = Box::new(terms.pop().unwrap());
+                let zero = Box::new(terms.pop().unwrap());
+                let expr = Box::new(terms.pop().unwrap());
+                Term::Swi { expr, zero, succ }
+            }
+            Open::Fun(name) if self.try_consume(")") => Term::Fun {
+                name: std::mem::take(name),
+                args: std::mem::take(terms),
+            },
+            Open::Ctr(name) if self.try_consume("}") => Term::Ctr {
+                name: std::mem::take(name),
+                args: std::mem::take(terms),
+            },
+            Open::App if !terms.is_empty() && self.try_consume(")") => {
+                let mut terms = std::mem::take(terms).into_iter();
+                let func = terms.next().unwrap();
+                terms.fold(func, Term::app)
+            }
+            Open::Dup(..) | Open::Let(_) if terms.len() == 1 => {
+                self.consume(";")?;
+                return Ok(None);
+            }
+            Open::Dup(nam0, nam1) if terms.len() == 2 => {
+                let body = Box::new(terms.pop().unwrap());
+                let expr = Box::new(terms.pop().unwrap());
+                Term::Dup {
+                    nam0: std::mem::take(nam0),
+                    nam1: std::mem::take(nam1),
+                    expr,
+                    body,
+                }
+            }
+            Open::Let(name) if terms.len() == 2 => {
+                let body = terms.pop().unwrap();
+                let expr = terms.pop().unwrap();
+                Term::app(Term::lam(name, body), expr)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(term))
+    }
+
+    /// Parses `Name = term` definitions
+    pub fn term_defs(&mut self) -> Result<Vec<(String, Term)>> {
+        let mut defs: Vec<(String, Term)> = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.peek().is_none() {
+                return Ok(defs);
+            }
+            let (line, col) = self.location();
+            let name = self.name()?;
+            if defs.iter().any(|(nam, _)| *nam == name) {
+                return Err(CoreError::Parse {
+                    line,
+                    col,
+                    msg: format!("duplicate definition '{}'", name),
+                });
+            }
+            self.consume("=")?;
+            defs.push((name, self.term()?));
+        }
+    }
+}
+
+// ==============================================================================
+// COMPILER
+// ==============================================================================
+
+/// A compiled term program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub book: Book,
+    /// Constructors and their arity, in encoding order
+    pub ctrs: Vec<(String, usize)>,
+}
+
+impl Program {
+    /// Parses and compiles `Name = term` definitions
+    pub fn parse(src: &str) -> Result<Program> {
+        Program::compile(&Parser::new(src).term_defs()?)
+    }
+
+    /// Compiles definitions into a book, `Main` first
+    pub fn compile(defs: &[(String, Term)]) -> Result<Program> {
+        Program::compile_with(defs, BTreeMap::new())
+    }
+
+    /// `compile`, also encoding constructors the terms may not use
+    pub(crate) fn compile_with(defs: &[(String, Term)], mut ctrs: BTreeMap<String, usize>) -> Result<Program> {
+        let taken: HashSet<String> = defs.iter().map(|(name, _)| name.clone()).collect();
+        let mut all = Vec::new();
+        for (name, term) in defs {
+            let mut lifted = Vec::new();
+            let term = term.clone().float(name, &mut lifted, &taken);
+            all.push((name.clone(), term));
+            all.extend(lifted);
+        }
+        let defs = &all;
+
+        for (_, term) in defs {
+            term.constructors(&mut ctrs)?;
+        }
+        let ctrs: Vec<(String, usize)> = ctrs.into_iter().collect();
+
+        let mut order: Vec<usize> = (0..defs.len()).collect();
+        if let Some(main) = defs.iter().position(|(name, _)| name == "Main") {
+            order.retain(|&i| i != main);
+            order.insert(0, main);
+        }
+        let ids: HashMap<String, Val> = order
+            .iter()
+            .enumerate()
+            .map(|(fid, &i)| (book_name(&defs[i].0), fid as Val))
+            .collect();
+
+        let mut book = Book::new();
+        for &i in &order {
+            let (name, term) = &defs[i];
+            let mut compiler = Compiler {
+                ids: &ids,
+                ctrs: &ctrs,
+                scope: Vec::new(),
+                rbag: Vec::new(),
+                subst: HashMap::new(),
+                next: 0,
+            };
+            let net = compiler.net(term)?;
+            let name = book_name(name);
+            let def = Def {
+                name: name.clone(),
+                arity: net.root.arity(),
+                net: net.build(|nam| ids.get(nam).copied())?,
+            };
+            book.insert(name, def);
+        }
+        book.propagate_safety();
+        Ok(Program { book, ctrs })
+    }
+}
+
+/// Compiler work, kept on its own stack. Trees go on a second one.
+enum Build<'t> {
+    /// Compiles the term, pushing its tree
+    Term(&'t Term),
+    /// Brings variables into scope
+    Scope(Vec<String>),
+    /// Pops a body and an expression, duplicating the expression into the
+    /// two innermost variables
+    Dup,
+    /// Pops a body, binding the given number of innermost variables around it
+    Lam(usize),
+    /// Pops an argument and a function, pushing the application
+    App,
+    /// Pops the cases and the number they switch on
+    Swi,
+    /// Pops two operands
+    Op2(u32),
+}
+
+/// Compiles one definition into a net
+struct Compiler<'a> {
+    ids: &'a HashMap<String, Val>,
+    ctrs: &'a [(String, usize)],
+    /// Variables in scope, innermost last, with the net variables of their uses
+    scope: Vec<(String, Vec<String>)>,
+    rbag: Vec<(Tree, Tree)>,
+    /// Trees that replace a net variable, for `connect`
+    subst: HashMap<String, Tree>,
+    next: usize,
+}
+
+impl Compiler<'_> {
+    fn net(&mut self, term: &Term) -> Result<Net> {
+        let mut root = self.term(term)?;
+        let mut rbag = std::mem::take(&mut self.rbag);
+        self.resolve(&mut root);
+        for (a, b) in &mut rbag {
+            self.resolve(a);
+            self.resolve(b);
+        }
+        Ok(Net { root, rbag })
+    }
+
+    fn fresh(&mut self) -> String {
+        self.next += 1;
+        format!("v{}", self.next - 1)
+    }
+
+    /// Compiles a term, returning the tree of its value. Keeps its own
+    /// stack, with the trees compiled so far on a second one.
+    fn term(&mut self, term: &Term) -> Result<Tree> {
+        let mut tasks = vec![Build::Term(term)];
+        let mut trees = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Build::Term(term) => self.start(term, &mut tasks, &mut trees)?,
+                Build::Scope(names) => {
+                    for name in names {
+                        self.scope.push((name.clone(), Vec::new()));
+                    }
+                }
+                Build::Dup => {
+                    let body = trees.pop().unwrap();
+                    let expr = trees.pop().unwrap();
+                    let snd = self.bind();
+                    let fst = self.bind();
+                    self.connect(expr, Tree::node(Tag::Dup, fst, snd));
+                    trees.push(body);
+                }
+                Build::Lam(count) => {
+                    let mut body = trees.pop().unwrap();
+                    for _ in 0..count {
+                        let var = self.bind();
+                        body = Tree::node(Tag::Con, var, body);
+                    }
+                    trees.push(body);
+                }
+                Build::App => {
+                    let argm = trees.pop().unwrap();
+                    let func = trees.pop().unwrap();
+                    let ret = self.apply(func, argm);
+                    trees.push(ret);
+                }
+                Build::Swi => {
+                    let succ = trees.pop().unwrap();
+                    let zero = trees.pop().unwrap();
+                    let expr = trees.pop().unwrap();
+                    let ret = self.fresh();
+                    let swi = Tree::node(Tag::Swi, Tree::node(Tag::Con, zero, succ), Tree::Var { nam: ret.clone() });
+                    self.connect(expr, swi);
+                    trees.push(Tree::Var { nam: ret });
+                }
+                Build::Op2(oper) => {
+                    let val1 = trees.pop().unwrap();
+                    let val0 = trees.pop().unwrap();
+                    let ret = self.fresh();
+                    let rest = Tree::node(Tag::Opr, val1, Tree::Var { nam: ret.clone() });
+                    let op = Tree::node(Tag::Opr, Tree::Num { val: Numb::new_sym(oper) }, rest);
+                    self.connect(val0, op);
+                    trees.push(Tree::Var { nam: ret });
+                }
+            }
+        }
+        Ok(trees.pop().unwrap())
+    }
+
+    /// Compiles a leaf, or queues the work for a term with subterms
+    fn start<'t>(&mut self, term: &'t Term, tasks: &mut Vec<Build<'t>>, trees: &mut Vec<Tree>) -> Result<()> {
+        match term {
+            Term::Var { name } => trees.push(self.var(name)?),
+            Term::Dup { nam0, nam1, expr, body } => {
+                let names = vec![nam0.clone(), nam1.clone()];
+                tasks.extend([Build::Dup, Build::Term(body), Build::Scope(names), Build::Term(expr)]);
+            }
+            Term::Lam { name, body } => {
+                self.scope.push((name.clone(), Vec::new()));
+                tasks.extend([Build::Lam(1), Build::Term(body)]);
+            }
+            Term::App { func, argm } => tasks.extend([Build::App, Build::Term(argm), Build::Term(func)]),
+            // `{C_i args..}` as `@c0 .. @cN-1 (c_i args..)`
+            Term::Ctr { name, args } => {
+                // Not a valid source name, so it can't capture anything
+                let case = |i: usize| format!("%c{}", i);
+                let idx = self.ctrs.iter().position(|(nam, _)| nam == name).unwrap();
+                for i in 0..self.ctrs.len() {
+                    self.scope.push((case(i), Vec::new()));
+                }
+                trees.push(self.var(&case(idx))?);
+                tasks.push(Build::Lam(self.ctrs.len()));
+                tasks.extend(args.iter().rev().flat_map(|arg| [Build::App, Build::Term(arg)]));
+            }
+            Term::Fun { name, args } => {
+                let nam = book_name(name);
+                if !self.ids.contains_key(&nam) {
+                    return Err(CoreError::Compile(format!("undefined function '{}'", name)));
+                }
+                trees.push(Tree::Ref { nam });
+                tasks.extend(args.iter().rev().flat_map(|arg| [Build::App, Build::Term(arg)]));
+            }
+            Term::Num { numb } => trees.push(Tree::Num { val: *numb }),
+            Term::Era => trees.push(Tree::Era),
+            Term::Swi { expr, zero, succ } => {
+                tasks.extend([Build::Swi, Build::Term(succ), Build::Term(zero), Build::Term(expr)]);
+            }
+            Term::Op2 { oper, val0, val1 } => {
+                tasks.extend([Build::Op2(*oper), Build::Term(val1), Build::Term(val0)]);
+            }
+        }
+        Ok(())
+    }
+
+    /// A use of a variable in scope
+    fn var(&mut self, name: &str) -> Result<Tree> {
+        let nam = self.fresh();
+        let Some((_, uses)) = self.scope.iter_mut().rev().find(|(var, _)| var == name) else {
+            return Err(CoreError::Compile(format!("unbound variable '{}'", name)));
+        };
+        uses.push(nam.clone());
+        Ok(Tree::Var { nam })
+    }
+
+    /// `func ~ (argm ret)`, returning `ret`
+    fn apply(&mut self, func: Tree, argm: Tree) -> Tree {
+        let ret = self.fresh();
+        self.connect(func, Tree::node(Tag::Con, argm, Tree::Var { nam: ret.clone() }));
+        Tree::Var { nam: ret }
+    }
+
+    /// Links two trees. A variable is replaced by the other tree where it
+    /// was bound, so no redex ever has a variable side.
+    fn connect(&mut self, a: Tree, b: Tree) {
+        match a {
+            Tree::Var { ref nam } => {
+                self.subst.insert(nam.clone(), b);
+            }
+            a => self.rbag.push((a, b)),
+        }
+    }
+
+    /// Pops the innermost variable, returning the tree that binds it: ERA
+    /// if unused, DUPs if used more than once
+    fn bind(&mut self) -> Tree {
+        let (_, mut uses) = self.scope.pop().unwrap();
+        let Some(last) = uses.pop() else {
+            return Tree::Era;
+        };
+        let mut tree = Tree::Var { nam: last };
+        while let Some(nam) = uses.pop() {
+            tree = Tree::node(Tag::Dup, Tree::Var { nam }, tree);
+        }
+        tree
+    }
+
+    /// Applies `connect`'s replacements inside a tree
+    fn resolve(&mut self, tree: &mut Tree) {
+        let mut stack = vec![tree];
+        while let Some(tree) = stack.pop() {
+            if let Tree::Var { nam } = &*tree {
+                if let Some(val) = self.subst.remove(nam) {
+                    *tree = val;
+                    stack.push(tree);
+                }
+                continue;
+            }
+            if let Some((fst, snd)) = tree.children_mut() {
+                stack.push(snd);
+                stack.push(fst);
+            }
+        }
+    }
+}
+
+// ==============================================================================
+// READBACK
+// ==============================================================================
+
+impl Program {
+    /// Reads a normalized net back as a term, expanding its REFs
+    pub fn readback(&self, net: &mut GNet) -> Result<Term> {
+        let tree = net.readback(&self.book)?;
+        self.read_tree(&tree)
+    }
+
+    /// Reads a tree as a term. Lambdas are named `x0`, `x1`, ... and
+    /// Scott-encoded values come back as constructors.
+    pub fn read_tree(&self, tree: &Tree) -> Result<Term> {
+        let mut reader = Reader {
+            subst: HashMap::new(),
+            next: 0,
+        };
+        let term = reader.read(tree)?;
+        let term = reader.substitute(term);
+        Ok(self.constructors(term))
+    }
+
+    /// Turns Scott-encoded values back into constructors, bottom-up
+    fn constructors(&self, term: Term) -> Term {
+        term.rebuild(|term| term, |term, kids| self.as_ctr(term, kids)).0
+    }
+
+    /// `{C_i args..}` if the term is `@c0 .. @cN-1 (c_i args..)` and no
+    /// argument uses a case, given what was found for its subterms
+    fn as_ctr(&self, term: Term, kids: Vec<Uses>) -> (Term, Uses) {
+        let uses = Uses::of(&term, kids, self.ctrs.len());
+        let len = self.ctrs.len();
+        if len == 0 || uses.binders.len() < len {
+            return (term, uses);
+        }
+        let mut cases = Vec::new();
+        let mut body = &term;
+        while cases.len() < len {
+            let Term::Lam { name, body: next } = body else {
+                return (term, uses);
+            };
+            cases.push(name.as_str());
+            body = next;
+        }
+        let mut arity = 0;
+        while let Term::App { func, .. } = body {
+            arity += 1;
+            body = func;
+        }
+        let Term::Var { name } = body else {
+            return (term, uses);
+        };
+        // The last binding of a name is the one in scope
+        let Some(idx) = cases.iter().rposition(|case| case == name) else {
+            return (term, uses);
+        };
+        let (ctr, ctr_arity) = &self.ctrs[idx];
+        // The head is the only use of a case
+        let free = uses.binders.iter().enumerate().all(|(i, &n)| n == usize::from(i == idx));
+        if *ctr_arity != arity || !free {
+            return (term, uses);
+        }
+        let mut term = term;
+        let mut body = &mut term;
+        for _ in 0..len {
+            let Term::Lam { body: next, .. } = body else {
+                unreachable!();
+            };
+            body = next;
+        }
+        let mut args = Vec::new();
+        while let Term::App { func, argm } = body {
+            args.push(argm.take());
+            body = func;
+        }
+        args.reverse();
+        let uses = Uses {
+            binders: Vec::new(),
+            ..uses
+        };
+        (Term::Ctr { name: ctr.clone(), args }, uses)
+    }
+}
+
+/// Free variable occurrences of a term, for `as_ctr`
+#[derive(Default)]
+struct Uses {
+    /// Occurrences of each free variable
+    free: HashMap<String, usize>,
+    /// Uses of the variables of the lambdas the term starts with, outermost
+    /// first, for the first `len` of them
+    binders: Vec<usize>,
+}
+
+impl Uses {
+    /// The uses of `term`, given those of its subterms
+    fn of(term: &Term, kids: Vec<Uses>, len: usize) -> Uses {
+        let mut kids = kids.into_iter();
+        match term {
+            Term::Var { name } => Uses {
+                free: HashMap::from([(name.clone(), 1)]),
+                binders: Vec::new(),
+            },
+            Term::Lam { name, .. } => {
+                let mut body = kids.next().unwrap();
+                let mut binders = vec![body.free.remove(name).unwrap_or(0)];
+                binders.extend(body.binders.into_iter().take(len.saturating_sub(1)));
+                Uses { free: body.free, binders }
+            }
+            Term::Dup { nam0, nam1, .. } => {
+                let expr = kids.next().unwrap();
+                let mut body = kids.next().unwrap();
+                body.free.remove(nam0);
+                body.free.remove(nam1);
+                Uses {
+                    free: Uses::merge(expr.free, body.free),
+                    binders: Vec::new(),
+                }
+            }
+            _ => Uses {
+                free: kids.map(|kid| kid.free).reduce(Uses::merge).unwrap_or_default(),
+                binders: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds the smaller count map into the larger
+    fn merge(a: HashMap<String, usize>, b: HashMap<String, usize>) -> HashMap<String, usize> {
+        let (mut big, small) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+        for (name, count) in small {
+            *big.entry(name).or_insert(0) += count;
+        }
+        big
+    }
+}
+
+/// Reads trees back as terms. A tree in a positive position is a value; one
+/// in a negative position says what is done with a value.
+struct Reader {
+    /// Terms that net variables stand for
+    subst: HashMap<String, Term>,
+    next: usize,
+}
+
+/// Reader work, kept on its own stack. Values go on a second stack.
+enum Task<'t> {
+    /// Reads the tree as a value, pushing it
+    Positive(&'t Tree),
+    /// Pops a value and reads what the tree does to it
+    Negative(&'t Tree),
+    /// Pops a body, pushing `@name body`
+    Lam(String),
+    /// Pops an argument and a function, pushing `(func argm)`
+    App,
+    /// Pops `b` and a value, pushing `val op b`
+    Op2(u32),
+    /// Pops the cases and a value, pushing the switch
+    Swi,
+}
+
+impl Reader {
+    fn error<T>(tree: &Tree) -> Result<T> {
+        Err(CoreError::Readback(format!("'{}' is not a term", tree)))
+    }
+
+    /// Reads a tree in a positive position
+    fn read(&mut self, tree: &Tree) -> Result<Term> {
+        let mut tasks = vec![Task::Positive(tree)];
+        let mut vals = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Positive(tree) => self.positive(tree, &mut tasks, &mut vals)?,
+                Task::Negative(tree) => {
+                    let val = vals.pop().unwrap();
+                    self.negative(tree, val, &mut tasks, &mut vals)?;
+                }
+                Task::Lam(name) => {
+                    let body = vals.pop().unwrap();
+                    vals.push(Term::lam(&name, body));
+                }
+                Task::App => {
+                    let argm = vals.pop().unwrap();
+                    let func = vals.pop().unwrap();
+                    vals.push(Term::app(func, argm));
+                }
+                Task::Op2(oper) => {
+                    let b = vals.pop().unwrap();
+                    let val = vals.pop().unwrap();
+                    vals.push(op2(oper, val, b));
+                }
+                Task::Swi => {
+                    let succ = Box::new(vals.pop().unwrap());
+                    let zero = Box::new(vals.pop().unwrap());
+                    let expr = Box::new(vals.pop().unwrap());
+                    vals.push(Term::Swi { expr, zero, succ });
+                }
+            }
+        }
+        Ok(vals.pop().unwrap())
+    }
+
+    fn positive<'t>(&mut self, tree: &'t Tree, tasks: &mut Vec<Task<'t>>, vals: &mut Vec<Term>) -> Result<()> {
+        let term = match tree {
+            Tree::Var { nam } => Term::var(nam),
+            Tree::Ref { nam } => Term::Fun {
+                name: term_name(nam),
+                args: Vec::new(),
+            },
+            Tree::Era => Term::Era,
+            Tree::Num { val } if val.is_num() => Term::Num { numb: *val },
+            Tree::Con { fst, snd } => {
+                let name = format!("x{}", self.next);
+                self.next += 1;
+                vals.push(Term::var(&name));
+                tasks.extend([Task::Lam(name), Task::Positive(snd), Task::Negative(fst)]);
+                return Ok(());
+            }
+            _ => return Self::error(tree),
+        };
+        vals.push(term);
+        Ok(())
+    }
+
+    /// Reads what a tree does to `val`
+    fn negative<'t>(&mut self, tree: &'t Tree, val: Term, tasks: &mut Vec<Task<'t>>, vals: &mut Vec<Term>) -> Result<()> {
+        match tree {
+            Tree::Var { nam } => {
+                self.subst.insert(nam.clone(), val);
+            }
+            Tree::Era => {}
+            Tree::Con { fst, snd } => {
+                vals.push(val);
+                tasks.extend([Task::Negative(snd), Task::App, Task::Positive(fst)]);
+            }
+            Tree::Dup { fst, snd } => {
+                vals.push(val.clone());
+                vals.push(val);
+                tasks.extend([Task::Negative(fst), Task::Negative(snd)]);
+            }
+            Tree::Opr { fst, snd } => match (&**fst, &**snd) {
+                // `$([op] $(b r))`: val op b
+                (Tree::Num { val: sym }, Tree::Opr { fst: b, snd: r }) if sym.typ() == Numb::TY_SYM => {
+                    vals.push(val);
+                    tasks.extend([Task::Negative(r), Task::Op2(sym.sym()), Task::Positive(b)]);
+                }
+                // `$([op n] r)`: n op val
+                (Tree::Num { val: part }, r) if part.typ() >= Numb::OP_ADD => {
+                    let n = Term::Num {
+                        numb: Numb::new_u24(part.u24()),
+                    };
+                    vals.push(op2(part.typ(), n, val));
+                    tasks.push(Task::Negative(r));
+                }
+                _ => return Self::error(tree),
+            },
+            // `?((zero succ) r)`
+            Tree::Swi { fst, snd } => {
+                let Tree::Con { fst: zero, snd: succ } = &**fst else {
+                    return Self::error(tree);
+                };
+                vals.push(val);
+                tasks.extend([Task::Negative(snd), Task::Swi, Task::Positive(succ), Task::Positive(zero)]);
+            }
+            _ => return Self::error(tree),
+        }
+        Ok(())
+    }
+
+    /// Replaces net variables with the terms they stand for
+    fn substitute(&self, term: Term) -> Term {
+        let down = |mut term: Term| {
+            while let Term::Var { name } = &term {
+                match self.subst.get(name) {
+                    Some(val) => term = val.clone(),
+                    None => break,
+                }
+            }
+            term
+        };
+        term.rebuild(down, |term, _| (term, ())).0
+    }
+}
+
+/// `Op2`, with flipped operators turned around
+fn op2(oper: u32, val0: Term, val1: Term) -> Term {
+    let (oper, val0, val1) = match oper {
+        Numb::FP_SUB => (Numb::OP_SUB, val1, val0),
+        Numb::FP_DIV => (Numb::OP_DIV, val1, val0),
+        Numb::FP_REM => (Numb::OP_REM, val1, val0),
+        Numb::FP_SHL => (Numb::OP_SHL, val1, val0),
+        Numb::FP_SHR => (Numb::OP_SHR, val1, val0),
+        _ => (oper, val0, val1),
+    };
+    Term::Op2 {
+        oper,
+        val0: Box::new(val0),
+        val1: Box::new(val1),
+    }
+}
+
+// ==============================================================================
+// DISPLAY
+// ==============================================================================
+
+/// Terms print in the syntax the parser reads. Printing keeps its own
+/// stack, so deep terms don't overflow the native one.
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        enum Item<'a> {
+            Term(&'a Term),
+            Text(&'static str),
+        }
+        let mut stack = vec![Item::Term(self)];
+        while let Some(item) = stack.pop() {
+            let term = match item {
+                Item::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Item::Term(term) => term,
+            };
+            // Subterms, each after a separator, then the closing text
+            let (parts, close): (Vec<(&'static str, &Term)>, &'static str) = match term {
+                Term::Var { name } => {
+                    f.write_str(name)?;
+                    continue;
+                }
+                Term::Dup { nam0, nam1, expr, body } => {
+                    write!(f, "dup {} {} = ", nam0, nam1)?;
+                    (vec![("", expr), ("; ", body)], "")
+                }
+                Term::Lam { name, body } => {
+                    write!(f, "@{} ", name)?;
+                    (vec![("", body)], "")
+                }
+                Term::App { .. } => {
+                    let mut args = Vec::new();
+                    let mut func = term;
+                    while let Term::App { func: next, argm } = func {
+                        args.push((" ", &**argm));
+                        func = next;
+                    }
+                    args.push(("(", func));
+                    args.reverse();
+                    (args, ")")
+                }
+                Term::Ctr { name, args } | Term::Fun { name, args } => {
+                    let (open, close) = if matches!(term, Term::Ctr { .. }) { ("{", "}") } else { ("(", ")") };
+                    write!(f, "{}{}", open, name)?;
+                    (args.iter().map(|arg| (" ", arg)).collect(), close)
+                }
+                Term::Num { numb } => {
+                    write!(f, "{}", numb)?;
+                    continue;
+                }
+                Term::Era => {
+                    f.write_str("*")?;
+                    continue;
+                }
+                Term::Swi { expr, zero, succ } => {
+                    f.write_str("(? ")?;
+                    (vec![("", expr), (" ", zero), (" ", succ)], ")")
+                }
+                Term::Op2 { oper, val0, val1 } => {
+                    match OPERS.iter().find(|(_, op)| op == oper) {
+                        Some((sym, _)) => write!(f, "({} ", sym)?,
+                        None => write!(f, "(?{} ", oper)?,
+                    }
+                    (vec![("", val0), (" ", val1)], ")")
+                }
+            };
+            stack.push(Item::Text(close));
+            for (sep, part) in parts.into_iter().rev() {
+                stack.push(Item::Term(part));
+                stack.push(Item::Text(sep));
+            }
+        }
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str) -> String {
+        let program = Program::parse(src).unwrap();
+        let mut net = GNet::new();
+        net.normalize(&program.book).unwrap();
+        program.readback(&mut net).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_term_parse_display() {
+        for src in [
+            "@f @x (f (f x))",
+            "dup a b = x; (a b)",
+            "{Cons 1 {Nil}}",
+            "(+ 1 (* x 2))",
+            "(Foo x {Bar})",
+            "(== +1 -2)",
+            "(? n * @p (f p))",
+        ] {
+            assert_eq!(Term::parse(src).unwrap().to_string(), src);
+        }
+        let term: Term = "let x = 1; λy (!y x) // comment".parse().unwrap();
+        assert_eq!(term.to_string(), "(@x @y (y x) 1)");
+        assert_eq!(Term::parse("#7").unwrap(), Term::Num { numb: Numb::new_u24(7) });
+    }
+
+    #[test]
+    fn test_term_parse_errors() {
+        let error = |src: &str| match Term::parse(src) {
+            Err(CoreError::Parse { line, col, msg }) => (line, col, msg),
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert_eq!(error("(f x"), (1, 5, "expected a term, found end of input".to_string()));
+        assert_eq!(error("@x x y"), (1, 6, "expected end of input, found 'y'".to_string()));
+        assert_eq!(error("dup a = x; a").2, "expected a name, found '='");
+        assert!(matches!(Program::parse("F = 1\nF = 2"), Err(CoreError::Parse { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_term_compile() {
+        let book = |src: &str| Program::parse(src).unwrap().book.to_string();
+        assert_eq!(book("Main = @x x"), "@main = (a a)\n");
+        // Unused variables are erased, repeated ones duplicated
+        assert_eq!(book("Main = @x @y x"), "@main = (a (* a))\n");
+        assert_eq!(book("Main = @f @x (f (f x))"), "@main = ({(a b) (c a)} (c b))\n");
+        assert_eq!(book("Main = (+ 2 3)"), "@main = a\n  & 2 ~ $([+] $(3 a))\n");
+        assert_eq!(
+            book("Id = @x x\nMain = (Id 1)"),
+            "@main = a\n  & @Id ~ (1 a)\n@Id = (a a)\n"
+        );
+        assert_eq!(book("Main = dup a b = 2; (+ a b)"), "@main = a\n  & 2 ~ {$([+] $(b a)) b}\n");
+    }
+
+    #[test]
+    fn test_term_compile_errors() {
+        let error = |src: &str| Program::parse(src).unwrap_err();
+        assert_eq!(error("Main = @x y"), CoreError::Compile("unbound variable 'y'".to_string()));
+        assert_eq!(error("Main = (F 1)"), CoreError::Compile("undefined function 'F'".to_string()));
+        assert_eq!(
+            error("Main = @x (x {Pair 1 2} {Pair 1})"),
+            CoreError::Compile("constructor {Pair} is used with 2 and 1 fields".to_string())
+        );
+    }
+
+    #[test]
+    fn test_term_numbers() {
+        assert_eq!(eval("Double = @x (* x 2)\nMain = (+ (Double 3) 1)"), "7");
+        assert_eq!(eval("Main = (- 10 3)"), "7");
+        assert_eq!(eval("Main = dup a b = 5; (< a (+ b 1))"), "1");
+        assert_eq!(eval("Main = (? 3 0 @p (+ p 10))"), "12");
+        assert_eq!(eval("Main = @x (? x 1 @p p)"), "@x0 (? x0 1 @x1 x1)");
+        // Stuck arithmetic reads back with its operands in order
+        assert_eq!(eval("Main = @x (- x 1)"), "@x0 (- x0 1)");
+        assert_eq!(eval("Main = @x (- 10 x)"), "@x0 (- 10 x0)");
+    }
+
+    #[test]
+    fn test_term_recursion_with_captures() {
+        // The successor case captures `m`, and still only unfolds when taken
+        let src = "F = @n @m (? n m @p (F p m))\nMain = (F 3 5)";
+        let book = Program::parse(src).unwrap().book.to_string();
+        assert!(book.contains("@F.0 = (a (b c))\n  & @F ~ (a (b c))"), "{}", book);
+        assert_eq!(eval(src), "5");
+        let sum = "Sum = @n @acc (? n acc @p (Sum p (+ acc n)))\nMain = (Sum 4 0)";
+        assert_eq!(eval(sum), "10");
+    }
+
+    #[test]
+    fn test_term_readback_lambdas() {
+        let church = "Two = @f @x (f (f x))\nAdd = @m @n @f @x (m f (n f x))\n";
+        assert_eq!(eval(&format!("{}Main = (Two @y y)", church)), "@x0 x0");
+        assert_eq!(
+            eval(&format!("{}Main = (Add (Two) (Two))", church)),
+            "@x0 @x1 (x0 (x0 (x0 (x0 x1))))"
+        );
+        assert_eq!(eval("Main = @x @y (y x x)"), "@x0 @x1 (x1 x0 x0)");
+        assert_eq!(eval("K = @a @b a\nMain = (K)"), "@x0 @x1 x0");
+    }
+
+    #[test]
+    fn test_term_constructors() {
+        let src = "Swap = @p (p @a @b {Pair b a})\nMain = (Swap {Pair 1 2})";
+        let program = Program::parse(src).unwrap();
+        assert_eq!(program.ctrs, vec![("Pair".to_string(), 2)]);
+        assert_eq!(eval(src), "{Pair 2 1}");
+
+        let src = "Len = @xs (xs @h @t (+ 1 (Len t)) 0)\n\
+                   Main = (Len {Cons 7 {Cons 8 {Cons 9 {Nil}}}})";
+        assert_eq!(eval(src), "3");
+        assert_eq!(eval("Main = {Cons @x x {Nil}}"), "{Cons @x1 x1 {Nil}}");
+    }
+
+    #[test]
+    fn test_readback_huge_output() {
+        // Each element is a constructor and a lambda per case deeper
+        let len = 100_000;
+        let src = format!("Ones = @n (? n {{Nil}} @p {{Cons 1 (Ones p)}})\nMain = (Ones {})", len);
+        let program = Program::parse(&src).unwrap();
+        let mut net = GNet::new();
+        net.normalize(&program.book).unwrap();
+        let term = program.readback(&mut net).unwrap();
+        let copy = term.clone();
+        assert_eq!(copy, term);
+        let text = term.to_string();
+        assert_eq!(text.len(), len * "{Cons 1 }".len() + "{Nil}".len());
+        assert!(text.starts_with("{Cons 1 {Cons 1 ") && text.contains("{Cons 1 {Nil}}}"));
+        let parsed = Term::parse(&text).unwrap();
+        assert_eq!(parsed, term);
+
+        // Compiling the value back gives a net that reads back as it
+        let program = parsed.compile().unwrap();
+        let mut net = GNet::new();
+        net.normalize(&program.book).unwrap();
+        assert_eq!(program.readback(&mut net).unwrap(), term);
+
+        let depth = 50_000;
+        let src = format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&format!("Main = {}", src)), (depth + 1).to_string());
+    }
+}
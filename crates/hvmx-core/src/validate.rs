@@ -0,0 +1,292 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: validate.rs
+// Location: crates/hvmx-core/src/validate.rs
+// Purpose: Structural checks over a net's heap, root and redex bag
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! Net validation.
+//!
+//! A variable is a wire with two endpoints. While it is unlinked both are
+//! ports stored somewhere in the net; once one end is linked, the variable
+//! holds what that end pointed to and a single port is left. Ports are
+//! stored in the root slot, in live nodes, in linked variables and in the
+//! redex bag, and `validate` counts every one of them:
+//!
+//! - each variable has two endpoints, counting its value when linked;
+//! - node ports point at live nodes, each node being pointed at once;
+//! - redexes are active pairs, or the boot redex `@main ~ ROOT`;
+//! - the root slot is in use and variable chains end.
+
+use std::fmt;
+
+use crate::{GNet, Pair, Port, Tag, Val};
+
+/// Where a port is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Site {
+    Root,
+    Node(Val),
+    Var(Val),
+    Redex(usize),
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Site::Root => write!(f, "the root"),
+            Site::Node(loc) => write!(f, "node {}", loc),
+            Site::Var(var) => write!(f, "var {}", var),
+            Site::Redex(i) => write!(f, "redex {}", i),
+        }
+    }
+}
+
+/// A broken invariant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A variable with other than two endpoints
+    Endpoints { var: Val, count: usize },
+    /// A port to a node slot that is free or out of the buffer
+    FreedNode { port: Port, site: Site },
+    /// A port to a variable slot out of the buffer
+    BadVar { port: Port, site: Site },
+    /// A node pointed at by more than one port
+    SharedNode { loc: Val, count: usize },
+    /// A redex that is not an active pair
+    Inactive { redex: Pair, index: usize },
+    /// The root slot is free
+    RootFree,
+    /// The root is unlinked and nothing points at it
+    RootUnreachable,
+    /// The root was pointed at by more than the root slot
+    RootEndpoints { count: usize },
+    /// A chain of linked variables that loops back on itself
+    Cycle { var: Val },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Endpoints { var, count } => write!(f, "var {} has {} endpoints", var, count),
+            Violation::FreedNode { port, site } => write!(f, "{} points at free node {:?}", site, port),
+            Violation::BadVar { port, site } => write!(f, "{} points at missing var {:?}", site, port),
+            Violation::SharedNode { loc, count } => write!(f, "node {} is pointed at {} times", loc, count),
+            Violation::Inactive { redex, index } => {
+                write!(f, "redex {} is not an active pair: {:?} ~ {:?}", index, redex.fst(), redex.snd())
+            }
+            Violation::RootFree => write!(f, "the root slot is free"),
+            Violation::RootUnreachable => write!(f, "the root is unreachable"),
+            Violation::RootEndpoints { count } => write!(f, "the root has {} endpoints", count),
+            Violation::Cycle { var } => write!(f, "var {} is linked in a cycle", var),
+        }
+    }
+}
+
+/// What `validate` found, in order of discovery
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for violation in &self.violations {
+            writeln!(f, "{}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl GNet {
+    /// Checks the net's structure, reporting every violation found
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let (nodes, vars) = self.buffers();
+        let mut node_refs = vec![0usize; nodes.len()];
+        let mut var_refs = vec![0usize; vars.len()];
+        let mut root_refs = 0;
+
+        let mut count = |port: Port, site: Site, report: &mut ValidationReport| {
+            if port == Port::NONE {
+                return;
+            }
+            if port == Port::ROOT {
+                root_refs += 1;
+            } else if port.tag() == Tag::Var {
+                match var_refs.get_mut(port.val() as usize) {
+                    Some(refs) if port.val() > 0 => *refs += 1,
+                    _ => report.violations.push(Violation::BadVar { port, site }),
+                }
+            } else if port.is_nod() {
+                let loc = port.val() as usize;
+                if loc == 0 || nodes.get(loc).is_none_or(|node| node == Pair::FREE) {
+                    report.violations.push(Violation::FreedNode { port, site });
+                } else {
+                    node_refs[loc] += 1;
+                }
+            }
+        };
+
+        let root = self.vars_load(Port::ROOT.val());
+        if root == Port::FREE {
+            report.violations.push(Violation::RootFree);
+        }
+        count(root, Site::Root, &mut report);
+        for (loc, node) in self.nodes() {
+            count(node.fst(), Site::Node(loc), &mut report);
+            count(node.snd(), Site::Node(loc), &mut report);
+        }
+        for (var, val) in self.vars() {
+            if val != Port::NONE {
+                count(val, Site::Var(var), &mut report);
+            }
+        }
+        for (index, redex) in self.redexes.iter().enumerate() {
+            count(redex.fst(), Site::Redex(index), &mut report);
+            count(redex.snd(), Site::Redex(index), &mut report);
+            let (a, b) = (redex.fst(), redex.snd());
+            let boot = a.tag() == Tag::Ref && b == Port::ROOT;
+            let principal = |port: Port| port.tag() != Tag::Var && port != Port::NONE;
+            let active = principal(a) && principal(b);
+            if !(boot || active) {
+                report.violations.push(Violation::Inactive { redex: *redex, index });
+            }
+        }
+
+        for (var, val) in self.vars() {
+            let linked = (val != Port::NONE) as usize;
+            let count = var_refs[var as usize] + linked;
+            if count != 2 {
+                report.violations.push(Violation::Endpoints { var, count });
+            }
+        }
+        for (var, refs) in var_refs.iter().enumerate() {
+            if *refs > 0 && vars.get(var).is_some_and(|val| val == Port::FREE) {
+                report.violations.push(Violation::Endpoints { var: var as Val, count: *refs });
+            }
+        }
+        for (loc, refs) in node_refs.iter().enumerate() {
+            if *refs > 1 {
+                report.violations.push(Violation::SharedNode { loc: loc as Val, count: *refs });
+            }
+        }
+        if root != Port::FREE {
+            let linked = (root != Port::NONE) as usize;
+            if root_refs + linked == 0 {
+                report.violations.push(Violation::RootUnreachable);
+            } else if root_refs + linked > 1 {
+                report.violations.push(Violation::RootEndpoints { count: root_refs + linked });
+            }
+        }
+        self.find_cycles(&mut report);
+        report
+    }
+
+    /// Reports a variable on each cycle of linked variables
+    fn find_cycles(&self, report: &mut ValidationReport) {
+        let (_, vars) = self.buffers();
+        // 0: not seen, 1: on the current chain, 2: done
+        let mut state = vec![0u8; vars.len()];
+        for start in 1..vars.len() {
+            let mut var = start;
+            let mut chain = Vec::new();
+            while state[var] != 2 {
+                if state[var] == 1 {
+                    report.violations.push(Violation::Cycle { var: var as Val });
+                    break;
+                }
+                state[var] = 1;
+                chain.push(var);
+                match vars.get(var) {
+                    Some(port) if port.tag() == Tag::Var && port != Port::FREE && port != Port::ROOT => {
+                        var = port.val() as usize;
+                        if var >= vars.len() {
+                            break;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+            for var in chain {
+                state[var] = 2;
+            }
+        }
+    }
+}
+
+// ==============================================================================
+// TESTS
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus, Book};
+
+    #[test]
+    fn test_validate_during_reduction() {
+        for (name, book, _) in corpus::all() {
+            let mut net = GNet::new();
+            net.boot(&book).unwrap();
+            assert!(net.validate().is_ok(), "{}: {}", name, net.validate());
+            while let Some(redex) = net.redexes.pop() {
+                crate::interact(&mut net, &book, redex.fst(), redex.snd()).unwrap();
+                let report = net.validate();
+                assert!(report.is_ok(), "{}: {}", name, report);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_violations() {
+        let book = Book::parse("@main = (a (b (a b)))").unwrap();
+        let mut net = GNet::new();
+        net.normalize(&book).unwrap();
+        assert!(net.validate().is_ok());
+
+        // A node dropped from under its port
+        let root = net.root();
+        let mut broken = net.clone();
+        broken.node_take(root.val());
+        let report = broken.validate();
+        assert!(report.violations.contains(&Violation::FreedNode { port: root, site: Site::Root }));
+        assert!(report.violations.iter().any(|v| matches!(v, Violation::Endpoints { count: 1, .. })));
+
+        // A port to the same node twice, and an inactive redex
+        let mut broken = net.clone();
+        broken.redexes.push(Pair::new(root, Port::new(Tag::Var, 1)));
+        let report = broken.validate();
+        assert_eq!(report.violations[0], Violation::Inactive { redex: broken.redexes[0], index: 0 });
+        assert!(report.violations.contains(&Violation::SharedNode { loc: root.val(), count: 2 }));
+        assert!(report.to_string().contains("node 1 is pointed at 2 times"));
+    }
+
+    #[test]
+    fn test_validate_root_and_cycles() {
+        let mut net = GNet::new();
+        assert_eq!(net.validate().violations, vec![Violation::RootUnreachable]);
+        net.set_root(Port::new(Tag::Era, 0));
+        assert!(net.validate().is_ok());
+
+        let x = net.vars_alloc().unwrap();
+        let y = net.vars_alloc().unwrap();
+        net.vars_create(x, Port::new(Tag::Var, y));
+        net.vars_create(y, Port::new(Tag::Var, x));
+        let report = net.validate();
+        assert!(report.violations.contains(&Violation::Cycle { var: x }));
+
+        net.vars_create(Port::ROOT.val(), Port::FREE);
+        assert!(net.validate().violations.contains(&Violation::RootFree));
+    }
+}
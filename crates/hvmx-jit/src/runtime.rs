@@ -107,10 +107,10 @@ impl HVMRuntime {
         let mut ir = HVMIR::new();
         
         // Convert each redex to IR instructions
-        for (a, b) in &net.redexes {
+        for redex in &net.redexes {
             ir.add_node(crate::ir::IRNode::Interact { 
-                a: a.val(), 
-                b: b.val() 
+                a: redex.fst().val(), 
+                b: redex.snd().val() 
             });
         }
 
@@ -0,0 +1,186 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: lib.rs
+// Location: crates/hvmx-macros/src/lib.rs
+// Purpose: book! and net! macros for inline HVM2 syntax
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+//! HVM2 syntax inline in Rust.
+//!
+//! `book!` and `net!` rebuild the source text from their tokens, spaced as
+//! they were in the file, and parse it with `hvmx-core` while expanding.
+//! Syntax errors, variables that do not occur exactly twice and undefined
+//! references are compile errors at the offending token. The expansion is
+//! the parsed value in binary form (see `format` and `snapshot`), loaded
+//! without parsing, so callers need `hvmx-core` as a dependency.
+//!
+//! ```
+//! use hvmx_core::GNet;
+//! use hvmx_macros::{book, net};
+//!
+//! let book = book! {
+//!     @main = r & @id ~ (5 r)
+//!     @id = (x x)
+//! };
+//! let mut net = GNet::new();
+//! net.normalize(&book).unwrap();
+//! assert_eq!(net.to_string(), "5");
+//!
+//! let net = net!((a b) & (b c) ~ {a c});
+//! assert_eq!(net.redexes.len(), 1);
+//! ```
+//!
+//! ```compile_fail
+//! // 'b' occurs only once
+//! let net = hvmx_macros::net!((a (a b)));
+//! ```
+//!
+//! ```compile_fail
+//! // undefined reference '@id'
+//! let book = hvmx_macros::book!(@main = r & @id ~ (5 r));
+//! ```
+
+use hvmx_core::{Book, CoreError, GNet, Result, Snapshot};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// A `Book` from inline HVM2 definitions
+#[proc_macro]
+pub fn book(input: TokenStream) -> TokenStream {
+    expand(input, |src| {
+        let buf = Book::parse(src)?.to_buffer()?;
+        Ok(format!("::hvmx_core::Book::from_buffer(&{})", words(&buf)))
+    })
+}
+
+/// A standalone `GNet` from an inline HVM2 net, which cannot refer to
+/// definitions
+#[proc_macro]
+pub fn net(input: TokenStream) -> TokenStream {
+    expand(input, |src| {
+        let buf = Snapshot::new(Book::new(), GNet::parse(src)?).to_buffer()?;
+        Ok(format!("::hvmx_core::Snapshot::from_buffer(&{}).map(|snap| snap.net)", words(&buf)))
+    })
+}
+
+/// Builds the input's text with `build`, expanding to the loader it
+/// returns; the buffer was made here, so loading it cannot fail
+fn expand(input: TokenStream, build: fn(&str) -> Result<String>) -> TokenStream {
+    let mut src = Source::new();
+    src.tokens(input);
+    match build(&src.text) {
+        Ok(load) => format!("{}.expect(\"built when the macro expanded\")", load)
+            .parse()
+            .unwrap(),
+        Err(CoreError::Parse { line, col, msg }) => compile_error(&msg, src.span_at(line, col)),
+        Err(err) => compile_error(&err.to_string(), Span::call_site()),
+    }
+}
+
+/// A `[u32; N]` literal
+fn words(buf: &[u32]) -> String {
+    let words: Vec<String> = buf.iter().map(|w| format!("{:#x}u32", w)).collect();
+    format!("[{}]", words.join(", "))
+}
+
+/// `compile_error!(msg)`, reported at `span`
+fn compile_error(msg: &str, span: Span) -> TokenStream {
+    let mut lit = Literal::string(msg);
+    lit.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenTree::Literal(lit).into());
+    args.set_span(span);
+    [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Source text rebuilt from tokens, and where each token starts in it
+struct Source {
+    text: String,
+    line: usize,
+    col: usize,
+    prev: Option<Span>,
+    starts: Vec<(usize, usize, Span)>,
+}
+
+impl Source {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            line: 1,
+            col: 1,
+            prev: None,
+            starts: Vec::new(),
+        }
+    }
+
+    fn tokens(&mut self, stream: TokenStream) {
+        for tree in stream {
+            match tree {
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => {
+                            self.tokens(group.stream());
+                            continue;
+                        }
+                    };
+                    self.push(open, group.span_open());
+                    self.tokens(group.stream());
+                    self.push(close, group.span_close());
+                }
+                TokenTree::Ident(ident) => self.push(&ident.to_string(), ident.span()),
+                TokenTree::Punct(punct) => self.push(&punct.as_char().to_string(), punct.span()),
+                TokenTree::Literal(lit) => self.push(&lit.to_string(), lit.span()),
+            }
+        }
+    }
+
+    /// Appends a token, separated from the last one as in the file: HVM2
+    /// reads `@sum.s` and `-1` as one token, but `@sum . s` and `- 1` not
+    fn push(&mut self, text: &str, span: Span) {
+        if let Some(prev) = self.prev {
+            let (end, start) = (prev.end(), span.start());
+            if start.line() > end.line() {
+                self.write("\n");
+            } else if (start.line(), start.column()) != (end.line(), end.column()) {
+                self.write(" ");
+            }
+        }
+        self.starts.push((self.line, self.col, span));
+        self.write(text);
+        self.prev = Some(span);
+    }
+
+    fn write(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.text.push_str(text);
+    }
+
+    /// The token at `line:col` of the text, or the last one before it
+    fn span_at(&self, line: usize, col: usize) -> Span {
+        self.starts
+            .iter()
+            .rev()
+            .find(|(l, c, _)| (*l, *c) <= (line, col))
+            .map_or_else(Span::call_site, |(_, _, span)| *span)
+    }
+}
@@ -0,0 +1,57 @@
+// ==============================================================================
+// HVMX - High-order Virtual Machine eXtreme
+// ==============================================================================
+// File: macros.rs
+// Location: crates/hvmx-macros/tests/macros.rs
+// Purpose: Expansion tests for book! and net!
+// Authors: scoobiii & GOS3 (Gang of Seven Senior Scrum LLM DevOps Team)
+// Date: 2024-12-28
+// License: MIT OR Apache-2.0
+// ==============================================================================
+
+use hvmx_core::{Book, GNet};
+use hvmx_macros::{book, net};
+
+#[test]
+fn test_book_matches_parse() {
+    let book = book! {
+        // Sums 0..100
+        @main = r & @sum ~ (100 r)
+        @sum = (?((0 @sum.s) r) r)
+        @sum.s = ({p0 p1} r)
+          & @sum ~ (p0 $([+] $(q r)))
+          & [+1] ~ $(p1 q)
+    };
+    let src = "
+        @main = r & @sum ~ (100 r)
+        @sum = (?((0 @sum.s) r) r)
+        @sum.s = ({p0 p1} r)
+          & @sum ~ (p0 $([+] $(q r)))
+          & [+1] ~ $(p1 q)
+    ";
+    assert_eq!(book.to_buffer(), Book::parse(src).unwrap().to_buffer());
+    assert_eq!(book.get("sum").unwrap().arity, 1);
+
+    let mut net = GNet::new();
+    net.normalize(&book).unwrap();
+    assert_eq!(net.to_string(), "5050");
+}
+
+#[test]
+fn test_net_numbers_and_names() {
+    let net = net!((+1 (-2 (1.5 (+inf ([*-2] (0x2A *)))))));
+    let src = "(+1 (-2 (1.5 (+inf ([*-2] (0x2A *))))))";
+    assert_eq!(net.to_string(), GNet::parse(src).unwrap().to_string());
+
+    let net = net!((a.b (x-1 (a.b x-1))));
+    assert_eq!(net.to_string(), "(a (b (a b)))");
+}
+
+#[test]
+fn test_net_reduces() {
+    let mut net = net! {
+        r & (x x) ~ (7 r)
+    };
+    net.reduce(&Book::new()).unwrap();
+    assert_eq!(net.to_string(), "7");
+}